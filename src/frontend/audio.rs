@@ -0,0 +1,69 @@
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+
+use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+use log::warn;
+use rustyboy::SAMPLE_RATE;
+
+// samples beyond 100 ms of queued audio are dropped to keep the latency down
+const MAX_QUEUED_SAMPLES: usize = SAMPLE_RATE as usize / 10 * 2;
+
+// stereo output on the default device, fed with the interleaved samples of the emulator
+pub struct Audio {
+    queue: Arc<Mutex<VecDeque<f32>>>,
+    // the stream stops playing when dropped
+    _stream: Option<cpal::Stream>,
+}
+
+impl Audio {
+    pub fn new() -> Audio {
+        let queue = Arc::new(Mutex::new(VecDeque::with_capacity(MAX_QUEUED_SAMPLES)));
+        let stream = match open_stream(queue.clone()) {
+            Ok(stream) => Some(stream),
+            Err(err) => {
+                warn!("Audio output unavailable: {}", err);
+                None
+            }
+        };
+        Audio {
+            queue,
+            _stream: stream,
+        }
+    }
+
+    pub fn push(&self, samples: &[f32]) {
+        let mut queue = self.queue.lock().unwrap();
+        queue.extend(samples);
+        if queue.len() > MAX_QUEUED_SAMPLES {
+            let excess = queue.len() - MAX_QUEUED_SAMPLES;
+            queue.drain(..excess);
+        }
+    }
+}
+
+fn open_stream(queue: Arc<Mutex<VecDeque<f32>>>) -> Result<cpal::Stream, String> {
+    let device = cpal::default_host()
+        .default_output_device()
+        .ok_or("no output device")?;
+    let config = cpal::StreamConfig {
+        channels: 2,
+        sample_rate: cpal::SampleRate(SAMPLE_RATE),
+        buffer_size: cpal::BufferSize::Default,
+    };
+    let stream = device
+        .build_output_stream(
+            &config,
+            move |data: &mut [f32], _| {
+                // play silence when the emulator falls behind
+                let mut queue = queue.lock().unwrap();
+                for sample in data.iter_mut() {
+                    *sample = queue.pop_front().unwrap_or(0.0);
+                }
+            },
+            |err| warn!("Audio stream error: {}", err),
+            None,
+        )
+        .map_err(|err| err.to_string())?;
+    stream.play().map_err(|err| err.to_string())?;
+    Ok(stream)
+}
@@ -0,0 +1,96 @@
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use log::error;
+use rustyboy::Emulator;
+
+// RAM changes are written to disk at most this often while playing
+const FLUSH_INTERVAL: Duration = Duration::from_secs(5);
+
+// keeps the .sav file next to the ROM in sync with the battery backed cartridge RAM
+pub struct BatterySave {
+    path: PathBuf,
+    pending: bool,
+    last_flush: Instant,
+}
+
+impl BatterySave {
+    pub fn new(rom_path: &Path) -> BatterySave {
+        BatterySave {
+            path: rom_path.with_extension("sav"),
+            pending: false,
+            last_flush: Instant::now(),
+        }
+    }
+
+    // load an existing save, a missing file just means the game was never saved
+    pub fn load(&self, emulator: &mut Emulator) -> Result<(), String> {
+        if emulator.save_data().is_none() {
+            return Ok(());
+        }
+        match std::fs::read(&self.path) {
+            Ok(data) => emulator
+                .load_save_data(&data)
+                .map_err(|err| err.to_string()),
+            Err(err) if err.kind() == ErrorKind::NotFound => Ok(()),
+            Err(err) => Err(err.to_string()),
+        }
+        .map_err(|err| format!("{}: {}", self.path.display(), err))
+    }
+
+    // called once per frame, writes the save some time after the game changed its RAM
+    pub fn update(&mut self, emulator: &mut Emulator) {
+        self.pending |= emulator.take_save_dirty();
+        if self.pending && self.last_flush.elapsed() >= FLUSH_INTERVAL {
+            self.flush(emulator);
+        }
+    }
+
+    pub fn flush(&mut self, emulator: &mut Emulator) {
+        let Some(data) = emulator.save_data() else {
+            return;
+        };
+        self.pending = false;
+        self.last_flush = Instant::now();
+
+        // write a temporary file first so a crash never leaves a truncated save behind
+        let temp_path = self.path.with_extension("sav.tmp");
+        if let Err(err) =
+            std::fs::write(&temp_path, data).and_then(|_| std::fs::rename(&temp_path, &self.path))
+        {
+            error!("Writing save {} failed: {}", self.path.display(), err);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // MBC1+RAM+BATTERY with 8 KiB RAM
+    fn battery_emulator() -> Emulator {
+        let mut rom = vec![0; 0x8000];
+        rom[0x0147] = 0x03;
+        rom[0x0149] = 0x02;
+        Emulator::from_rom_bytes(&rom).unwrap()
+    }
+
+    #[test]
+    fn test_save_round_trip() {
+        let dir = std::env::temp_dir().join(format!("rustyboy-battery-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let rom_path = dir.join("game.gb");
+
+        let mut emulator = battery_emulator();
+        let mut battery = BatterySave::new(&rom_path);
+        battery.load(&mut emulator).unwrap();
+        battery.flush(&mut emulator);
+        assert_eq!(std::fs::read(dir.join("game.sav")).unwrap().len(), 0x2000);
+        assert!(battery.load(&mut battery_emulator()).is_ok());
+
+        std::fs::write(dir.join("game.sav"), [0; 16]).unwrap();
+        assert!(battery.load(&mut battery_emulator()).is_err());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
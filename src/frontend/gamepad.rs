@@ -0,0 +1,69 @@
+use gilrs::{EventType, Gilrs};
+use log::warn;
+use rustyboy::Button;
+
+// first connected gamepad, mapped like the buttons of a Nintendo controller
+pub struct Gamepad {
+    gilrs: Option<Gilrs>,
+    held: Vec<Button>,
+}
+
+impl Gamepad {
+    pub fn new() -> Gamepad {
+        let gilrs = match Gilrs::new() {
+            Ok(gilrs) => Some(gilrs),
+            Err(err) => {
+                warn!("Gamepad support unavailable: {}", err);
+                None
+            }
+        };
+        Gamepad {
+            gilrs,
+            held: Vec::new(),
+        }
+    }
+
+    // process all pending gamepad events
+    pub fn poll(&mut self) {
+        let Some(gilrs) = self.gilrs.as_mut() else {
+            return;
+        };
+
+        while let Some(event) = gilrs.next_event() {
+            match event.event {
+                EventType::ButtonPressed(button, _) => {
+                    if let Some(button) = map_button(button) {
+                        if !self.held.contains(&button) {
+                            self.held.push(button);
+                        }
+                    }
+                }
+                EventType::ButtonReleased(button, _) => {
+                    if let Some(button) = map_button(button) {
+                        self.held.retain(|held| *held != button);
+                    }
+                }
+                EventType::Disconnected => self.held.clear(),
+                _ => {}
+            }
+        }
+    }
+
+    pub fn is_held(&self, button: Button) -> bool {
+        self.held.contains(&button)
+    }
+}
+
+fn map_button(button: gilrs::Button) -> Option<Button> {
+    match button {
+        gilrs::Button::DPadRight => Some(Button::Right),
+        gilrs::Button::DPadLeft => Some(Button::Left),
+        gilrs::Button::DPadUp => Some(Button::Up),
+        gilrs::Button::DPadDown => Some(Button::Down),
+        gilrs::Button::East => Some(Button::A),
+        gilrs::Button::South => Some(Button::B),
+        gilrs::Button::Select => Some(Button::Select),
+        gilrs::Button::Start => Some(Button::Start),
+        _ => None,
+    }
+}
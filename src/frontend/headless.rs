@@ -0,0 +1,53 @@
+use std::io::Write;
+
+use log::error;
+use rustyboy::{Emulator, SerialCapture};
+
+// Blargg's test ROMs end their report with a line starting with one of these
+const RESULTS: [&str; 2] = ["Passed", "Failed"];
+
+// run without a window, printing what the game sends over the serial port to stdout, until the
+// test ROM reported its result or the frames ran out, returns whether the tests passed
+pub fn run(emulator: &mut Emulator, frames: u32) -> bool {
+    let capture = SerialCapture::new();
+    emulator.set_link_cable(Box::new(capture.clone()));
+    let mut printed = 0;
+    for _ in 0..frames {
+        if let Err(err) = emulator.step_frame() {
+            error!("Emulation stopped: {}", err);
+            break;
+        }
+        let output = capture.bytes();
+        let mut stdout = std::io::stdout();
+        let _ = stdout.write_all(&output[printed..]).and(stdout.flush());
+        printed = output.len();
+        if finished(&capture.output()) {
+            break;
+        }
+    }
+    let output = capture.output();
+    output.contains("Passed") && !output.contains("Failed")
+}
+
+// the result line is complete
+fn finished(output: &str) -> bool {
+    output
+        .lines()
+        .zip(output.split_inclusive('\n'))
+        .any(|(line, full)| {
+            full.ends_with('\n') && RESULTS.iter().any(|result| line.starts_with(result))
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_finished() {
+        assert!(!finished("cpu_instrs\n\n01:ok  02:ok\n"));
+        assert!(!finished("cpu_instrs\n\nPassed all te"));
+        assert!(finished("cpu_instrs\n\nPassed all tests\n"));
+        assert!(finished("01-special\n\nFailed #6\n"));
+    }
+}
@@ -0,0 +1,152 @@
+use rustyboy::Button;
+use winit::keyboard::KeyCode;
+use winit_input_helper::WinitInputHelper;
+
+use super::BUTTONS;
+
+// keys that can be named in a key map file, using the winit key code names
+const KEYS: [(&str, KeyCode); 56] = [
+    ("KeyA", KeyCode::KeyA),
+    ("KeyB", KeyCode::KeyB),
+    ("KeyC", KeyCode::KeyC),
+    ("KeyD", KeyCode::KeyD),
+    ("KeyE", KeyCode::KeyE),
+    ("KeyF", KeyCode::KeyF),
+    ("KeyG", KeyCode::KeyG),
+    ("KeyH", KeyCode::KeyH),
+    ("KeyI", KeyCode::KeyI),
+    ("KeyJ", KeyCode::KeyJ),
+    ("KeyK", KeyCode::KeyK),
+    ("KeyL", KeyCode::KeyL),
+    ("KeyM", KeyCode::KeyM),
+    ("KeyN", KeyCode::KeyN),
+    ("KeyO", KeyCode::KeyO),
+    ("KeyP", KeyCode::KeyP),
+    ("KeyQ", KeyCode::KeyQ),
+    ("KeyR", KeyCode::KeyR),
+    ("KeyS", KeyCode::KeyS),
+    ("KeyT", KeyCode::KeyT),
+    ("KeyU", KeyCode::KeyU),
+    ("KeyV", KeyCode::KeyV),
+    ("KeyW", KeyCode::KeyW),
+    ("KeyX", KeyCode::KeyX),
+    ("KeyY", KeyCode::KeyY),
+    ("KeyZ", KeyCode::KeyZ),
+    ("Digit0", KeyCode::Digit0),
+    ("Digit1", KeyCode::Digit1),
+    ("Digit2", KeyCode::Digit2),
+    ("Digit3", KeyCode::Digit3),
+    ("Digit4", KeyCode::Digit4),
+    ("Digit5", KeyCode::Digit5),
+    ("Digit6", KeyCode::Digit6),
+    ("Digit7", KeyCode::Digit7),
+    ("Digit8", KeyCode::Digit8),
+    ("Digit9", KeyCode::Digit9),
+    ("ArrowUp", KeyCode::ArrowUp),
+    ("ArrowDown", KeyCode::ArrowDown),
+    ("ArrowLeft", KeyCode::ArrowLeft),
+    ("ArrowRight", KeyCode::ArrowRight),
+    ("Enter", KeyCode::Enter),
+    ("Space", KeyCode::Space),
+    ("Backspace", KeyCode::Backspace),
+    ("Tab", KeyCode::Tab),
+    ("ShiftLeft", KeyCode::ShiftLeft),
+    ("ShiftRight", KeyCode::ShiftRight),
+    ("ControlLeft", KeyCode::ControlLeft),
+    ("ControlRight", KeyCode::ControlRight),
+    ("AltLeft", KeyCode::AltLeft),
+    ("AltRight", KeyCode::AltRight),
+    ("Comma", KeyCode::Comma),
+    ("Period", KeyCode::Period),
+    ("Semicolon", KeyCode::Semicolon),
+    ("Quote", KeyCode::Quote),
+    ("Slash", KeyCode::Slash),
+    ("Backslash", KeyCode::Backslash),
+];
+
+// keyboard keys bound to each Game Boy button, several keys may share a button
+pub struct KeyMap {
+    bindings: Vec<(KeyCode, Button)>,
+}
+
+impl Default for KeyMap {
+    fn default() -> KeyMap {
+        KeyMap {
+            bindings: vec![
+                (KeyCode::ArrowRight, Button::Right),
+                (KeyCode::ArrowLeft, Button::Left),
+                (KeyCode::ArrowUp, Button::Up),
+                (KeyCode::ArrowDown, Button::Down),
+                (KeyCode::KeyX, Button::A),
+                (KeyCode::KeyZ, Button::B),
+                (KeyCode::Backspace, Button::Select),
+                (KeyCode::ShiftRight, Button::Select),
+                (KeyCode::Enter, Button::Start),
+            ],
+        }
+    }
+}
+
+impl KeyMap {
+    // parse lines of the form `A = KeyX`, empty lines and lines starting with # are skipped
+    pub fn parse(config: &str) -> Result<KeyMap, String> {
+        let mut bindings = Vec::new();
+        for (number, line) in config.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let (button_name, key_name) = line
+                .split_once('=')
+                .ok_or_else(|| format!("line {}: expected `Button = Key`", number + 1))?;
+            let button = lookup(&BUTTONS, button_name.trim())
+                .ok_or_else(|| format!("line {}: unknown button {}", number + 1, button_name))?;
+            let key = lookup(&KEYS, key_name.trim())
+                .ok_or_else(|| format!("line {}: unknown key {}", number + 1, key_name))?;
+            bindings.push((key, button));
+        }
+        Ok(KeyMap { bindings })
+    }
+
+    // a button is held while any of its keys is
+    pub fn is_held(&self, input: &WinitInputHelper, button: Button) -> bool {
+        self.bindings
+            .iter()
+            .any(|(key, bound)| *bound == button && input.key_held(*key))
+    }
+}
+
+fn lookup<T: Copy>(table: &[(&str, T)], name: &str) -> Option<T> {
+    table
+        .iter()
+        .find(|(entry, _)| entry.eq_ignore_ascii_case(name))
+        .map(|(_, value)| *value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let keymap =
+            KeyMap::parse("# arrows\nUp = KeyW\n\nstart = enter\nA = KeyJ\nA = Space").unwrap();
+        assert_eq!(
+            keymap.bindings,
+            vec![
+                (KeyCode::KeyW, Button::Up),
+                (KeyCode::Enter, Button::Start),
+                (KeyCode::KeyJ, Button::A),
+                (KeyCode::Space, Button::A),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert!(KeyMap::parse("Up KeyW").is_err());
+        assert!(KeyMap::parse("Turbo = KeyW").is_err());
+        assert!(KeyMap::parse("Up = F13").is_err());
+    }
+}
@@ -0,0 +1,21 @@
+#[cfg(feature = "audio")]
+pub mod audio;
+pub mod battery;
+#[cfg(feature = "gamepad")]
+pub mod gamepad;
+pub mod headless;
+pub mod keymap;
+
+use rustyboy::Button;
+
+// Game Boy buttons with the names used in configuration files
+pub const BUTTONS: [(&str, Button); 8] = [
+    ("Right", Button::Right),
+    ("Left", Button::Left),
+    ("Up", Button::Up),
+    ("Down", Button::Down),
+    ("A", Button::A),
+    ("B", Button::B),
+    ("Select", Button::Select),
+    ("Start", Button::Start),
+];
@@ -0,0 +1,506 @@
+mod noise;
+mod square;
+mod wave;
+
+use std::collections::VecDeque;
+
+use noise::Noise;
+use square::Square;
+use wave::Wave;
+
+// rate of the resampled output, in stereo frames per second
+pub const SAMPLE_RATE: u32 = 48000;
+
+const CPU_CLOCK: u32 = 4_194_304;
+
+// at most a quarter second of interleaved stereo samples is kept, older ones are dropped
+const BUFFER_CAPACITY: usize = SAMPLE_RATE as usize / 4 * 2;
+
+// counts down while a channel plays and turns it off when it expires
+pub struct LengthCounter {
+    pub enabled: bool,
+    pub counter: u16,
+    max: u16,
+}
+
+impl LengthCounter {
+    fn new(max: u16) -> LengthCounter {
+        LengthCounter {
+            enabled: false,
+            counter: 0,
+            max,
+        }
+    }
+
+    fn load(&mut self, value: u16) {
+        self.counter = self.max - value;
+    }
+
+    // returns true if the counter just expired
+    fn clock(&mut self) -> bool {
+        if self.enabled && self.counter > 0 {
+            self.counter -= 1;
+            return self.counter == 0;
+        }
+        false
+    }
+
+    fn trigger(&mut self, extra_clock: bool) {
+        if self.counter == 0 {
+            self.counter = self.max;
+            if self.enabled && extra_clock {
+                self.counter -= 1;
+            }
+        }
+    }
+
+    // enabling the counter while the next frame sequencer step does not clock it
+    // clocks it once immediately, returns true if that expired the counter
+    fn write_enable(&mut self, enable: bool, extra_clock: bool) -> bool {
+        let was_enabled = self.enabled;
+        self.enabled = enable;
+        if !was_enabled && enable && extra_clock && self.counter > 0 {
+            self.counter -= 1;
+            return self.counter == 0;
+        }
+        false
+    }
+}
+
+// volume envelope of the square and noise channels
+pub struct Envelope {
+    initial_volume: u8,
+    increase: bool,
+    period: u8,
+    timer: u8,
+    volume: u8,
+}
+
+impl Envelope {
+    fn new() -> Envelope {
+        Envelope {
+            initial_volume: 0,
+            increase: false,
+            period: 0,
+            timer: 0,
+            volume: 0,
+        }
+    }
+
+    fn read_byte(&self) -> u8 {
+        self.initial_volume << 4 | (self.increase as u8) << 3 | self.period
+    }
+
+    fn write_byte(&mut self, val: u8) {
+        self.initial_volume = val >> 4;
+        self.increase = val & 0x08 != 0;
+        self.period = val & 0x07;
+    }
+
+    // the DAC is powered as long as the upper five bits of NRx2 are not all zero
+    fn dac_enabled(&self) -> bool {
+        self.initial_volume != 0 || self.increase
+    }
+
+    fn trigger(&mut self) {
+        self.volume = self.initial_volume;
+        self.timer = self.period;
+    }
+
+    fn clock(&mut self) {
+        if self.period == 0 {
+            return;
+        }
+        if self.timer > 0 {
+            self.timer -= 1;
+        }
+        if self.timer == 0 {
+            self.timer = self.period;
+            if self.increase && self.volume < 15 {
+                self.volume += 1;
+            } else if !self.increase && self.volume > 0 {
+                self.volume -= 1;
+            }
+        }
+    }
+}
+
+pub struct Apu {
+    powered: bool,
+    square1: Square,
+    square2: Square,
+    wave: Wave,
+    noise: Noise,
+    // NR50, master volume per side
+    master_volume: u8,
+    // NR51, which channels are sent to which side
+    panning: u8,
+    // next step of the 512 Hz frame sequencer
+    frame_step: u8,
+    // clock cycles short of a whole machine cycle, carried over to the next tick since double
+    // speed mode hands out half machine cycles
+    pending_cycles: u32,
+    // advances by SAMPLE_RATE per clock cycle, a sample is due every CPU_CLOCK
+    sample_clock: u32,
+    // output summed up since the last sample, averaged when resampling
+    left_sum: f32,
+    right_sum: f32,
+    sum_count: u32,
+    // high pass filter removing the DC offset like the capacitors of the real hardware
+    capacitors: [f32; 2],
+    charge_factor: f32,
+    samples: VecDeque<f32>,
+}
+
+impl Apu {
+    pub fn new() -> Apu {
+        Apu {
+            powered: false,
+            square1: Square::new(true),
+            square2: Square::new(false),
+            wave: Wave::new(),
+            noise: Noise::new(),
+            master_volume: 0,
+            panning: 0,
+            frame_step: 0,
+            pending_cycles: 0,
+            sample_clock: 0,
+            left_sum: 0.0,
+            right_sum: 0.0,
+            sum_count: 0,
+            capacitors: [0.0; 2],
+            charge_factor: 0.999958f32.powf(CPU_CLOCK as f32 / SAMPLE_RATE as f32),
+            samples: VecDeque::with_capacity(BUFFER_CAPACITY),
+        }
+    }
+
+    // advance by the given amount of clock cycles, frame_clocks is the number of
+    // falling edges of DIV bit 4 that happened in the meantime
+    pub fn tick(&mut self, t_cycles: u32, frame_clocks: u32) {
+        if self.powered {
+            for _ in 0..frame_clocks {
+                self.clock_frame_sequencer();
+            }
+        }
+
+        // the channels are stepped and mixed once per machine cycle
+        let t_cycles = self.pending_cycles + t_cycles;
+        self.pending_cycles = t_cycles % 4;
+        for _ in 0..t_cycles / 4 {
+            if self.powered {
+                self.square1.step(4);
+                self.square2.step(4);
+                self.wave.step(4);
+                self.noise.step(4);
+            }
+
+            let (left, right) = self.mix();
+            self.left_sum += left;
+            self.right_sum += right;
+            self.sum_count += 1;
+
+            self.sample_clock += 4 * SAMPLE_RATE;
+            if self.sample_clock >= CPU_CLOCK {
+                self.sample_clock -= CPU_CLOCK;
+                self.push_sample();
+            }
+        }
+    }
+
+    fn clock_frame_sequencer(&mut self) {
+        match self.frame_step {
+            0 | 4 => self.clock_length(),
+            2 | 6 => {
+                self.clock_length();
+                self.square1.clock_sweep();
+            }
+            7 => {
+                self.square1.clock_envelope();
+                self.square2.clock_envelope();
+                self.noise.clock_envelope();
+            }
+            _ => {}
+        }
+        self.frame_step = (self.frame_step + 1) & 7;
+    }
+
+    fn clock_length(&mut self) {
+        self.square1.clock_length();
+        self.square2.clock_length();
+        self.wave.clock_length();
+        self.noise.clock_length();
+    }
+
+    fn mix(&self) -> (f32, f32) {
+        if !self.powered {
+            return (0.0, 0.0);
+        }
+
+        let channels = [
+            dac_output(self.square1.output(), self.square1.dac_enabled()),
+            dac_output(self.square2.output(), self.square2.dac_enabled()),
+            dac_output(self.wave.output(), self.wave.dac_enabled()),
+            dac_output(self.noise.output(), self.noise.dac_enabled()),
+        ];
+        let mut left = 0.0;
+        let mut right = 0.0;
+        for (i, output) in channels.iter().enumerate() {
+            if self.panning & (0x10 << i) != 0 {
+                left += output;
+            }
+            if self.panning & (0x01 << i) != 0 {
+                right += output;
+            }
+        }
+
+        let left_volume = ((self.master_volume >> 4) & 0x07) as f32 + 1.0;
+        let right_volume = (self.master_volume & 0x07) as f32 + 1.0;
+        (
+            left / 4.0 * left_volume / 8.0,
+            right / 4.0 * right_volume / 8.0,
+        )
+    }
+
+    fn push_sample(&mut self) {
+        let count = self.sum_count.max(1) as f32;
+        let left = self.high_pass(0, self.left_sum / count);
+        let right = self.high_pass(1, self.right_sum / count);
+        self.left_sum = 0.0;
+        self.right_sum = 0.0;
+        self.sum_count = 0;
+
+        if self.samples.len() >= BUFFER_CAPACITY {
+            self.samples.drain(..2);
+        }
+        self.samples.push_back(left);
+        self.samples.push_back(right);
+    }
+
+    fn high_pass(&mut self, side: usize, input: f32) -> f32 {
+        let output = input - self.capacitors[side];
+        self.capacitors[side] = input - output * self.charge_factor;
+        output
+    }
+
+    // interleaved stereo samples at SAMPLE_RATE produced since the last call
+    pub fn take_samples(&mut self) -> Vec<f32> {
+        self.samples.drain(..).collect()
+    }
+
+    pub fn read_byte(&self, addr: u16) -> u8 {
+        match addr {
+            0xFF10..=0xFF14 => self.square1.read_byte(addr - 0xFF10),
+            0xFF15..=0xFF19 => self.square2.read_byte(addr - 0xFF15),
+            0xFF1A..=0xFF1E => self.wave.read_byte(addr - 0xFF1A),
+            0xFF1F..=0xFF23 => self.noise.read_byte(addr - 0xFF1F),
+            0xFF24 => self.master_volume,
+            0xFF25 => self.panning,
+            0xFF26 => {
+                0x70 | (self.powered as u8) << 7
+                    | (self.noise.enabled as u8) << 3
+                    | (self.wave.enabled as u8) << 2
+                    | (self.square2.enabled as u8) << 1
+                    | self.square1.enabled as u8
+            }
+            0xFF27..=0xFF2F => 0xFF,
+            0xFF30..=0xFF3F => self.wave.read_ram(addr),
+            _ => panic!("access to apu in non mapped memory space: {:X}", addr),
+        }
+    }
+
+    pub fn write_byte(&mut self, addr: u16, val: u8) {
+        match addr {
+            0xFF26 => self.write_power(val & 0x80 != 0),
+            0xFF30..=0xFF3F => self.wave.write_ram(addr, val),
+            // while powered off only the length counters can be written
+            _ if !self.powered => match addr {
+                0xFF11 => self.square1.write_length(val),
+                0xFF16 => self.square2.write_length(val),
+                0xFF1B => self.wave.write_length(val),
+                0xFF20 => self.noise.write_length(val),
+                _ => {}
+            },
+            0xFF10..=0xFF14 => {
+                self.square1
+                    .write_byte(addr - 0xFF10, val, self.extra_length_clock())
+            }
+            0xFF15..=0xFF19 => {
+                self.square2
+                    .write_byte(addr - 0xFF15, val, self.extra_length_clock())
+            }
+            0xFF1A..=0xFF1E => self
+                .wave
+                .write_byte(addr - 0xFF1A, val, self.extra_length_clock()),
+            0xFF1F..=0xFF23 => self
+                .noise
+                .write_byte(addr - 0xFF1F, val, self.extra_length_clock()),
+            0xFF24 => self.master_volume = val,
+            0xFF25 => self.panning = val,
+            0xFF27..=0xFF2F => {}
+            _ => panic!("access to apu in non mapped memory space: {:X}", addr),
+        }
+    }
+
+    // true if the next frame sequencer step does not clock the length counters
+    fn extra_length_clock(&self) -> bool {
+        self.frame_step & 1 == 1
+    }
+
+    fn write_power(&mut self, on: bool) {
+        if self.powered && !on {
+            // powering off clears every register except wave RAM
+            self.square1.power_off();
+            self.square2.power_off();
+            self.wave.power_off();
+            self.noise.power_off();
+            self.master_volume = 0;
+            self.panning = 0;
+        } else if !self.powered && on {
+            self.frame_step = 0;
+        }
+        self.powered = on;
+    }
+}
+
+// the DACs map the digital 0-15 output linearly to -1.0..1.0, a disabled DAC outputs 0
+fn dac_output(digital: u8, enabled: bool) -> f32 {
+    if enabled {
+        digital as f32 / 7.5 - 1.0
+    } else {
+        0.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn powered_apu() -> Apu {
+        let mut apu = Apu::new();
+        apu.write_byte(0xFF26, 0x80);
+        apu
+    }
+
+    #[test]
+    fn test_read_masks() {
+        let apu = powered_apu();
+        assert_eq!(apu.read_byte(0xFF10), 0x80);
+        assert_eq!(apu.read_byte(0xFF11), 0x3F);
+        assert_eq!(apu.read_byte(0xFF13), 0xFF);
+        assert_eq!(apu.read_byte(0xFF15), 0xFF);
+        assert_eq!(apu.read_byte(0xFF1A), 0x7F);
+        assert_eq!(apu.read_byte(0xFF1C), 0x9F);
+        assert_eq!(apu.read_byte(0xFF23), 0xBF);
+        assert_eq!(apu.read_byte(0xFF26), 0xF0);
+        assert_eq!(apu.read_byte(0xFF27), 0xFF);
+    }
+
+    #[test]
+    fn test_power_off() {
+        let mut apu = powered_apu();
+        apu.write_byte(0xFF24, 0x77);
+        apu.write_byte(0xFF12, 0xF0);
+        apu.write_byte(0xFF30, 0x12);
+        apu.write_byte(0xFF26, 0x00);
+        assert_eq!(apu.read_byte(0xFF24), 0x00);
+        assert_eq!(apu.read_byte(0xFF12), 0x00);
+        assert_eq!(apu.read_byte(0xFF26), 0x70);
+        assert_eq!(apu.read_byte(0xFF30), 0x12);
+
+        // registers are read only while powered off
+        apu.write_byte(0xFF25, 0xFF);
+        assert_eq!(apu.read_byte(0xFF25), 0x00);
+    }
+
+    #[test]
+    fn test_trigger_and_length() {
+        let mut apu = powered_apu();
+        apu.write_byte(0xFF21, 0xF0);
+        // length of 2
+        apu.write_byte(0xFF20, 62);
+        apu.write_byte(0xFF23, 0xC0);
+        assert_eq!(apu.read_byte(0xFF26) & 0x08, 0x08);
+
+        apu.tick(0, 1);
+        assert_eq!(apu.read_byte(0xFF26) & 0x08, 0x08);
+        // step 1 does not clock the length counter
+        apu.tick(0, 1);
+        assert_eq!(apu.read_byte(0xFF26) & 0x08, 0x08);
+        apu.tick(0, 1);
+        assert_eq!(apu.read_byte(0xFF26) & 0x08, 0x00);
+    }
+
+    #[test]
+    fn test_dac_off() {
+        let mut apu = powered_apu();
+        apu.write_byte(0xFF12, 0xF0);
+        apu.write_byte(0xFF14, 0x80);
+        assert_eq!(apu.read_byte(0xFF26) & 0x01, 0x01);
+        apu.write_byte(0xFF12, 0x00);
+        assert_eq!(apu.read_byte(0xFF26) & 0x01, 0x00);
+
+        // triggering with the DAC off does not enable the channel
+        apu.write_byte(0xFF14, 0x80);
+        assert_eq!(apu.read_byte(0xFF26) & 0x01, 0x00);
+    }
+
+    #[test]
+    fn test_sweep_overflow() {
+        let mut apu = powered_apu();
+        apu.write_byte(0xFF12, 0xF0);
+        // period 1, shift 1
+        apu.write_byte(0xFF10, 0x11);
+        apu.write_byte(0xFF13, 0x00);
+        apu.write_byte(0xFF14, 0x85);
+        assert_eq!(apu.read_byte(0xFF26) & 0x01, 0x01);
+
+        // the sweep at step 2 writes back 0x780, its overflow check then disables the channel
+        apu.tick(0, 3);
+        assert_eq!(apu.read_byte(0xFF26) & 0x01, 0x00);
+    }
+
+    #[test]
+    fn test_wave_output() {
+        let mut apu = powered_apu();
+        apu.write_byte(0xFF30, 0xF0);
+        apu.write_byte(0xFF1A, 0x80);
+        apu.write_byte(0xFF1C, 0x20);
+        apu.write_byte(0xFF1D, 0xFF);
+        apu.write_byte(0xFF1E, 0x87);
+        // a period of 2 clock cycles per sample, playback starts at the second sample
+        apu.wave.step(2);
+        assert_eq!(apu.wave.output(), 0x00);
+        apu.wave.step(62);
+        assert_eq!(apu.wave.output(), 0x0F);
+    }
+
+    #[test]
+    fn test_sample_rate() {
+        let mut apu = powered_apu();
+        apu.write_byte(0xFF24, 0x77);
+        apu.write_byte(0xFF25, 0xFF);
+        apu.write_byte(0xFF12, 0xF0);
+        apu.write_byte(0xFF14, 0x87);
+        apu.tick(CPU_CLOCK / 64, 0);
+        let samples = apu.take_samples();
+        assert_eq!(samples.len(), SAMPLE_RATE as usize / 64 * 2);
+        assert!(samples.iter().any(|sample| *sample != 0.0));
+        assert!(apu.take_samples().is_empty());
+    }
+
+    #[test]
+    fn test_half_machine_cycles() {
+        // double speed mode ticks the APU with 2 clock cycles per machine cycle
+        let mut half = powered_apu();
+        let mut whole = powered_apu();
+        for _ in 0..CPU_CLOCK / 64 {
+            half.tick(2, 0);
+        }
+        for _ in 0..CPU_CLOCK / 128 {
+            whole.tick(4, 0);
+        }
+        let samples = half.take_samples().len();
+        assert_eq!(samples, SAMPLE_RATE as usize / 32 * 2);
+        assert_eq!(samples, whole.take_samples().len());
+    }
+}
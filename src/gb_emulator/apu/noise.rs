@@ -0,0 +1,130 @@
+use super::{Envelope, LengthCounter};
+
+const DIVISORS: [u32; 8] = [8, 16, 32, 48, 64, 80, 96, 112];
+
+// channel 4, pseudo random noise from a linear feedback shift register
+pub struct Noise {
+    pub enabled: bool,
+    clock_shift: u8,
+    narrow: bool,
+    divisor_code: u8,
+    timer: u32,
+    lfsr: u16,
+    pub length: LengthCounter,
+    envelope: Envelope,
+}
+
+impl Noise {
+    pub fn new() -> Noise {
+        Noise {
+            enabled: false,
+            clock_shift: 0,
+            narrow: false,
+            divisor_code: 0,
+            timer: 8,
+            lfsr: 0x7FFF,
+            length: LengthCounter::new(64),
+            envelope: Envelope::new(),
+        }
+    }
+
+    fn period(&self) -> u32 {
+        DIVISORS[self.divisor_code as usize] << self.clock_shift
+    }
+
+    pub fn dac_enabled(&self) -> bool {
+        self.envelope.dac_enabled()
+    }
+
+    pub fn output(&self) -> u8 {
+        if !self.enabled {
+            return 0;
+        }
+        // the inverted low bit of the shift register is played
+        (!self.lfsr & 1) as u8 * self.envelope.volume
+    }
+
+    pub fn step(&mut self, mut cycles: u32) {
+        while cycles >= self.timer {
+            cycles -= self.timer;
+            self.timer = self.period();
+            // shifts of 14 and 15 stop the clock of the shift register
+            if self.clock_shift < 14 {
+                self.shift_lfsr();
+            }
+        }
+        self.timer -= cycles;
+    }
+
+    fn shift_lfsr(&mut self) {
+        let feedback = (self.lfsr ^ (self.lfsr >> 1)) & 1;
+        self.lfsr = (self.lfsr >> 1) | feedback << 14;
+        if self.narrow {
+            self.lfsr = (self.lfsr & !0x40) | feedback << 6;
+        }
+    }
+
+    pub fn clock_length(&mut self) {
+        if self.length.clock() {
+            self.enabled = false;
+        }
+    }
+
+    pub fn clock_envelope(&mut self) {
+        self.envelope.clock();
+    }
+
+    fn trigger(&mut self, extra_length_clock: bool) {
+        self.enabled = self.dac_enabled();
+        self.timer = self.period();
+        self.lfsr = 0x7FFF;
+        self.length.trigger(extra_length_clock);
+        self.envelope.trigger();
+    }
+
+    pub fn read_byte(&self, register: u16) -> u8 {
+        match register {
+            2 => self.envelope.read_byte(),
+            3 => self.clock_shift << 4 | (self.narrow as u8) << 3 | self.divisor_code,
+            4 => 0xBF | (self.length.enabled as u8) << 6,
+            _ => 0xFF,
+        }
+    }
+
+    pub fn write_byte(&mut self, register: u16, val: u8, extra_length_clock: bool) {
+        match register {
+            1 => self.length.load((val & 0x3F) as u16),
+            2 => {
+                self.envelope.write_byte(val);
+                if !self.dac_enabled() {
+                    self.enabled = false;
+                }
+            }
+            3 => {
+                self.clock_shift = val >> 4;
+                self.narrow = val & 0x08 != 0;
+                self.divisor_code = val & 0x07;
+            }
+            4 => {
+                let length_enable = val & 0x40 != 0;
+                if self.length.write_enable(length_enable, extra_length_clock) {
+                    self.enabled = false;
+                }
+                if val & 0x80 != 0 {
+                    self.trigger(extra_length_clock);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    pub fn write_length(&mut self, val: u8) {
+        self.length.load((val & 0x3F) as u16);
+    }
+
+    pub fn power_off(&mut self) {
+        let length = self.length.counter;
+        *self = Noise::new();
+        self.length.counter = length;
+    }
+}
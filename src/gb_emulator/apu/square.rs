@@ -0,0 +1,202 @@
+use super::{Envelope, LengthCounter};
+
+// waveforms for the four duty settings, played from the most significant bit
+const DUTY_PATTERNS: [u8; 4] = [0b0000_0001, 0b1000_0001, 0b1000_0111, 0b0111_1110];
+
+// square channels 1 (with frequency sweep) and 2
+pub struct Square {
+    has_sweep: bool,
+    pub enabled: bool,
+    duty: u8,
+    duty_step: u8,
+    frequency: u16,
+    timer: u32,
+    pub length: LengthCounter,
+    envelope: Envelope,
+    sweep_period: u8,
+    sweep_negate: bool,
+    sweep_shift: u8,
+    sweep_timer: u8,
+    sweep_enabled: bool,
+    shadow_frequency: u16,
+    // leaving negate mode after a negated calculation disables the channel
+    sweep_negate_used: bool,
+}
+
+impl Square {
+    pub fn new(has_sweep: bool) -> Square {
+        Square {
+            has_sweep,
+            enabled: false,
+            duty: 0,
+            duty_step: 0,
+            frequency: 0,
+            timer: 8192,
+            length: LengthCounter::new(64),
+            envelope: Envelope::new(),
+            sweep_period: 0,
+            sweep_negate: false,
+            sweep_shift: 0,
+            sweep_timer: 0,
+            sweep_enabled: false,
+            shadow_frequency: 0,
+            sweep_negate_used: false,
+        }
+    }
+
+    fn period(&self) -> u32 {
+        (2048 - self.frequency as u32) * 4
+    }
+
+    pub fn dac_enabled(&self) -> bool {
+        self.envelope.dac_enabled()
+    }
+
+    pub fn output(&self) -> u8 {
+        if !self.enabled {
+            return 0;
+        }
+        let high = DUTY_PATTERNS[self.duty as usize] >> (7 - self.duty_step) & 1;
+        high * self.envelope.volume
+    }
+
+    pub fn step(&mut self, mut cycles: u32) {
+        while cycles >= self.timer {
+            cycles -= self.timer;
+            self.timer = self.period();
+            self.duty_step = (self.duty_step + 1) & 7;
+        }
+        self.timer -= cycles;
+    }
+
+    pub fn clock_length(&mut self) {
+        if self.length.clock() {
+            self.enabled = false;
+        }
+    }
+
+    pub fn clock_envelope(&mut self) {
+        self.envelope.clock();
+    }
+
+    pub fn clock_sweep(&mut self) {
+        if self.sweep_timer > 0 {
+            self.sweep_timer -= 1;
+        }
+        if self.sweep_timer != 0 {
+            return;
+        }
+
+        self.reload_sweep_timer();
+        if self.sweep_enabled && self.sweep_period != 0 {
+            let frequency = self.sweep_frequency();
+            if frequency <= 2047 && self.sweep_shift != 0 {
+                self.shadow_frequency = frequency;
+                self.frequency = frequency;
+                // the new frequency is checked for overflow again but not written back
+                self.sweep_frequency();
+            }
+        }
+    }
+
+    fn reload_sweep_timer(&mut self) {
+        // a period of 0 is treated as 8 by the sweep timer
+        self.sweep_timer = if self.sweep_period == 0 {
+            8
+        } else {
+            self.sweep_period
+        };
+    }
+
+    // next sweep frequency, disables the channel if it overflows
+    fn sweep_frequency(&mut self) -> u16 {
+        let delta = self.shadow_frequency >> self.sweep_shift;
+        let frequency = if self.sweep_negate {
+            self.sweep_negate_used = true;
+            self.shadow_frequency - delta
+        } else {
+            self.shadow_frequency + delta
+        };
+        if frequency > 2047 {
+            self.enabled = false;
+        }
+        frequency
+    }
+
+    fn trigger(&mut self, extra_length_clock: bool) {
+        self.enabled = self.dac_enabled();
+        self.timer = self.period();
+        self.length.trigger(extra_length_clock);
+        self.envelope.trigger();
+
+        if self.has_sweep {
+            self.shadow_frequency = self.frequency;
+            self.sweep_negate_used = false;
+            self.reload_sweep_timer();
+            self.sweep_enabled = self.sweep_period != 0 || self.sweep_shift != 0;
+            if self.sweep_shift != 0 {
+                self.sweep_frequency();
+            }
+        }
+    }
+
+    // register 0 is NR10 (sweep), only present on channel 1
+    pub fn read_byte(&self, register: u16) -> u8 {
+        match register {
+            0 if self.has_sweep => {
+                0x80 | self.sweep_period << 4 | (self.sweep_negate as u8) << 3 | self.sweep_shift
+            }
+            1 => self.duty << 6 | 0x3F,
+            2 => self.envelope.read_byte(),
+            4 => 0xBF | (self.length.enabled as u8) << 6,
+            _ => 0xFF,
+        }
+    }
+
+    pub fn write_byte(&mut self, register: u16, val: u8, extra_length_clock: bool) {
+        match register {
+            0 if self.has_sweep => {
+                self.sweep_period = (val >> 4) & 0x07;
+                self.sweep_negate = val & 0x08 != 0;
+                self.sweep_shift = val & 0x07;
+                if !self.sweep_negate && self.sweep_negate_used {
+                    self.enabled = false;
+                }
+            }
+            1 => {
+                self.duty = val >> 6;
+                self.length.load((val & 0x3F) as u16);
+            }
+            2 => {
+                self.envelope.write_byte(val);
+                if !self.dac_enabled() {
+                    self.enabled = false;
+                }
+            }
+            3 => self.frequency = (self.frequency & 0x700) | val as u16,
+            4 => {
+                self.frequency = (self.frequency & 0xFF) | ((val & 0x07) as u16) << 8;
+                let length_enable = val & 0x40 != 0;
+                if self.length.write_enable(length_enable, extra_length_clock) {
+                    self.enabled = false;
+                }
+                if val & 0x80 != 0 {
+                    self.trigger(extra_length_clock);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    // writes to the length register still work while the APU is powered off
+    pub fn write_length(&mut self, val: u8) {
+        self.length.load((val & 0x3F) as u16);
+    }
+
+    pub fn power_off(&mut self) {
+        let length = self.length.counter;
+        *self = Square::new(self.has_sweep);
+        // the DMG keeps its length counters while powered off
+        self.length.counter = length;
+    }
+}
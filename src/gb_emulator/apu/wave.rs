@@ -0,0 +1,132 @@
+use super::LengthCounter;
+
+// channel 3, plays back the 32 4-bit samples stored in wave RAM
+pub struct Wave {
+    pub enabled: bool,
+    dac_enabled: bool,
+    volume_code: u8,
+    frequency: u16,
+    timer: u32,
+    position: u8,
+    sample: u8,
+    pub length: LengthCounter,
+    ram: [u8; 16],
+}
+
+impl Wave {
+    pub fn new() -> Wave {
+        Wave {
+            enabled: false,
+            dac_enabled: false,
+            volume_code: 0,
+            frequency: 0,
+            timer: 4096,
+            position: 0,
+            sample: 0,
+            length: LengthCounter::new(256),
+            ram: [0; 16],
+        }
+    }
+
+    fn period(&self) -> u32 {
+        (2048 - self.frequency as u32) * 2
+    }
+
+    pub fn dac_enabled(&self) -> bool {
+        self.dac_enabled
+    }
+
+    pub fn output(&self) -> u8 {
+        if !self.enabled {
+            return 0;
+        }
+        match self.volume_code {
+            0 => 0,
+            code => self.sample >> (code - 1),
+        }
+    }
+
+    pub fn step(&mut self, mut cycles: u32) {
+        while cycles >= self.timer {
+            cycles -= self.timer;
+            self.timer = self.period();
+            self.position = (self.position + 1) & 31;
+            let byte = self.ram[(self.position / 2) as usize];
+            // the upper nibble is played first
+            self.sample = if self.position & 1 == 0 {
+                byte >> 4
+            } else {
+                byte & 0x0F
+            };
+        }
+        self.timer -= cycles;
+    }
+
+    pub fn clock_length(&mut self) {
+        if self.length.clock() {
+            self.enabled = false;
+        }
+    }
+
+    fn trigger(&mut self, extra_length_clock: bool) {
+        self.enabled = self.dac_enabled;
+        self.timer = self.period();
+        self.position = 0;
+        self.length.trigger(extra_length_clock);
+    }
+
+    pub fn read_byte(&self, register: u16) -> u8 {
+        match register {
+            0 => 0x7F | (self.dac_enabled as u8) << 7,
+            2 => 0x9F | self.volume_code << 5,
+            4 => 0xBF | (self.length.enabled as u8) << 6,
+            _ => 0xFF,
+        }
+    }
+
+    pub fn write_byte(&mut self, register: u16, val: u8, extra_length_clock: bool) {
+        match register {
+            0 => {
+                self.dac_enabled = val & 0x80 != 0;
+                if !self.dac_enabled {
+                    self.enabled = false;
+                }
+            }
+            1 => self.length.load(val as u16),
+            2 => self.volume_code = (val >> 5) & 0x03,
+            3 => self.frequency = (self.frequency & 0x700) | val as u16,
+            4 => {
+                self.frequency = (self.frequency & 0xFF) | ((val & 0x07) as u16) << 8;
+                let length_enable = val & 0x40 != 0;
+                if self.length.write_enable(length_enable, extra_length_clock) {
+                    self.enabled = false;
+                }
+                if val & 0x80 != 0 {
+                    self.trigger(extra_length_clock);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    pub fn write_length(&mut self, val: u8) {
+        self.length.load(val as u16);
+    }
+
+    pub fn read_ram(&self, addr: u16) -> u8 {
+        self.ram[(addr - 0xFF30) as usize]
+    }
+
+    pub fn write_ram(&mut self, addr: u16, val: u8) {
+        self.ram[(addr - 0xFF30) as usize] = val;
+    }
+
+    pub fn power_off(&mut self) {
+        let length = self.length.counter;
+        let ram = self.ram;
+        *self = Wave::new();
+        // wave RAM and, on the DMG, the length counter survive a power cycle
+        self.length.counter = length;
+        self.ram = ram;
+    }
+}
@@ -1,63 +1,215 @@
 mod opcodes;
 
+use std::error::Error;
+use std::fmt;
+
 use opcodes::Opcodes;
 
-use super::memory::MemoryBus;
+use super::memory::{Button, Cartridge, CgbSupport, LinkCable, MemoryBus};
+use super::model::Model;
 use super::registers::Flag;
 use super::registers::Registers;
+use super::utils::U16Ext;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CpuError {
+    // one of the unused opcodes was executed, which locks up the real hardware
+    IllegalOpcode { pc: u16, opcode: u8 },
+}
+
+impl fmt::Display for CpuError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CpuError::IllegalOpcode { pc, opcode } => {
+                write!(f, "illegal opcode {:02X} at {:04X}", opcode, pc)
+            }
+        }
+    }
+}
+
+impl Error for CpuError {}
 
 pub struct Z80CPU {
     r: Registers,
     m: MemoryBus,
     halted: bool,
     ime: bool,
+    // EI only takes effect after the instruction following it
+    ime_pending: bool,
+    // HALT with IME=0 and a pending interrupt fails to increment PC after it
+    halt_bug: bool,
+    locked: bool,
 }
 
 impl Z80CPU {
-    pub fn new() -> Z80CPU {
+    pub fn new(m: MemoryBus) -> Z80CPU {
         let r = Registers::new();
-        let m = MemoryBus::new();
         Z80CPU {
             r,
             m,
             halted: false,
             ime: false,
+            ime_pending: false,
+            halt_bug: false,
+            locked: false,
         }
     }
 
-    pub fn run(&mut self) {
+    pub fn framebuffer(&self) -> &[u8] {
+        self.m.framebuffer()
+    }
+
+    pub fn take_frame_complete(&mut self) -> bool {
+        self.m.take_frame_complete()
+    }
+
+    pub fn screen_size(&self) -> (usize, usize) {
+        self.m.screen_size()
+    }
+
+    pub fn double_speed(&self) -> bool {
+        self.m.double_speed()
+    }
+
+    pub fn set_button(&mut self, button: Button, pressed: bool) {
+        self.m.set_button(button, pressed);
+    }
+
+    pub fn set_link_cable(&mut self, cable: Box<dyn LinkCable>) {
+        self.m.set_link_cable(cable);
+    }
+
+    pub fn cartridge(&mut self) -> &mut Cartridge {
+        self.m.cartridge()
+    }
+
+    pub fn take_audio_samples(&mut self) -> Vec<f32> {
+        self.m.take_audio_samples()
+    }
+
+    pub fn load_boot_rom(&mut self, boot_rom: Vec<u8>) {
+        self.m.load_boot_rom(boot_rom);
+    }
+
+    // start at the cartridge entry point with the registers the boot ROM of the model leaves
+    pub fn skip_boot(&mut self, model: Model) {
+        let header = self.m.cartridge().header();
+        // the DMG boot ROMs leave H and C set unless the header checksum is zero
+        let checksum_flags = if header.header_checksum == 0 {
+            0x80
+        } else {
+            0xB0
+        };
+        let cgb_game = header.cgb_support != CgbSupport::None;
+
+        let [a, f, b, c, d, e, h, l] = match model {
+            Model::Dmg => [0x01, checksum_flags, 0x00, 0x13, 0x00, 0xD8, 0x01, 0x4D],
+            Model::Mgb => [0xFF, checksum_flags, 0x00, 0x13, 0x00, 0xD8, 0x01, 0x4D],
+            Model::Sgb => [0x01, 0x00, 0x00, 0x14, 0x00, 0x00, 0xC0, 0x60],
+            Model::Sgb2 => [0xFF, 0x00, 0x00, 0x14, 0x00, 0x00, 0xC0, 0x60],
+            Model::Cgb if cgb_game => [0x11, 0x80, 0x00, 0x00, 0xFF, 0x56, 0x00, 0x0D],
+            // B depends on the title for some licensees, it is zero for everything else
+            Model::Cgb => [0x11, 0x80, 0x00, 0x00, 0x00, 0x08, 0x00, 0x7C],
+        };
+        self.r.set_af(u16::from_be_bytes([a, f]));
+        self.r.set_bc(u16::from_be_bytes([b, c]));
+        self.r.set_de(u16::from_be_bytes([d, e]));
+        self.r.set_hl(u16::from_be_bytes([h, l]));
+        self.r.sp = 0xFFFE;
+        self.r.pc = 0x0100;
+        self.m.skip_boot(model);
+    }
+
+    // run one instruction or interrupt dispatch and advance the rest of the system by its length
+    pub fn cycle(&mut self) -> Result<u32, CpuError> {
+        let mut ticks = self.step()?;
+        self.m.tick(ticks);
+        // the CPU waits while VRAM DMA copies, the system keeps running meanwhile
         loop {
-            let ticks = self.cycle();
-            dbg!(ticks);
+            let stall = self.m.take_dma_stall();
+            if stall == 0 {
+                break;
+            }
+            self.m.tick(stall);
+            ticks += stall;
         }
+        Ok(ticks)
     }
 
-    fn cycle(&mut self) -> u32 {
+    fn step(&mut self) -> Result<u32, CpuError> {
+        if self.locked {
+            return Ok(1);
+        }
+
+        let mut wake_ticks = 0;
         if self.halted {
-            1
-        } else {
-            self.interpret()
+            // a pending interrupt ends HALT even if IME is not set
+            if self.m.pending_interrupt().is_none() {
+                return Ok(1);
+            }
+            self.halted = false;
+            wake_ticks = 1;
         }
+
+        if self.ime && self.m.pending_interrupt().is_some() {
+            return Ok(wake_ticks + self.service_interrupt());
+        }
+
+        let enable_ime = self.ime_pending;
+        let ticks = self.interpret()?;
+        if enable_ime && self.ime_pending {
+            self.ime = true;
+            self.ime_pending = false;
+        }
+        Ok(wake_ticks + ticks)
+    }
+
+    // push PC and jump to the interrupt vector, takes five machine cycles
+    fn service_interrupt(&mut self) -> u32 {
+        self.ime = false;
+        let pc = self.r.pc;
+
+        self.r.sp = self.r.sp.wrapping_sub(1);
+        self.m.write_byte(self.r.sp, pc.hi());
+        // pushing the high byte onto IE can cancel the dispatch, which then jumps to 0x0000
+        let interrupt = self.m.pending_interrupt();
+        self.r.sp = self.r.sp.wrapping_sub(1);
+        self.m.write_byte(self.r.sp, pc.lo());
+
+        match interrupt {
+            Some(interrupt) => {
+                self.m.acknowledge_interrupt(interrupt);
+                self.r.pc = interrupt.vector();
+            }
+            None => self.r.pc = 0x0000,
+        }
+        5
     }
 
-    fn reset(&mut self) {
+    pub fn reset(&mut self) {
+        self.m.reset();
         self.r = Registers::new();
         self.halted = false;
         self.ime = false;
+        self.ime_pending = false;
+        self.halt_bug = false;
+        self.locked = false;
     }
 
     fn fetch_byte(&mut self) -> u8 {
         let b = self.m.read_byte(self.r.pc);
-        self.r.pc += 1;
+        if self.halt_bug {
+            self.halt_bug = false;
+        } else {
+            self.r.pc = self.r.pc.wrapping_add(1);
+        }
         b
     }
 
     fn fetch_word(&mut self) -> u16 {
-        let b = self.m.read_byte(self.r.pc);
-        self.r.pc += 1;
-        let w = (b as u16) << 8 + self.m.read_byte(self.r.pc);
-        self.r.pc += 1;
-        w
+        let lo = self.fetch_byte();
+        let hi = self.fetch_byte();
+        (hi as u16) << 8 | lo as u16
     }
 
     fn push_stack(&mut self, val: u16) {
@@ -71,9 +223,18 @@ impl Z80CPU {
         val
     }
 
+    // the CPU stops fetching instructions for good, only a reset recovers from this
+    fn lock_up(&mut self, opcode: u8) -> CpuError {
+        self.locked = true;
+        CpuError::IllegalOpcode {
+            pc: self.r.pc.wrapping_sub(1),
+            opcode,
+        }
+    }
+
     // fetch and run the next instruction, returns the length of the ran instruction
-    fn interpret(&mut self) -> u32 {
-        match self.fetch_byte() {
+    fn interpret(&mut self) -> Result<u32, CpuError> {
+        Ok(match self.fetch_byte() {
             0x00 => 1,
             0x01 => {
                 let d = self.fetch_word();
@@ -102,6 +263,7 @@ impl Z80CPU {
             }
             0x07 => {
                 self.r.a = self.rlc(self.r.a);
+                self.r.set_flag(Flag::Z, false);
                 1
             }
             0x08 => {
@@ -120,7 +282,7 @@ impl Z80CPU {
                 2
             }
             0x0B => {
-                self.r.set_bc(self.r.get_bc().wrapping_rem(1));
+                self.r.set_bc(self.r.get_bc().wrapping_sub(1));
                 2
             }
             0x0C => {
@@ -137,9 +299,17 @@ impl Z80CPU {
             }
             0x0F => {
                 self.r.a = self.rrc(self.r.a);
+                self.r.set_flag(Flag::Z, false);
+                1
+            }
+            0x10 => {
+                // STOP is followed by a padding byte
+                self.fetch_byte();
+                // on the CGB it switches the speed when armed through KEY1, the low power
+                // mode itself is not emulated
+                self.m.switch_speed();
                 1
             }
-            0x10 => 1, //STOP
             0x11 => {
                 let v = self.fetch_word();
                 self.r.set_de(v);
@@ -167,6 +337,7 @@ impl Z80CPU {
             }
             0x17 => {
                 self.r.a = self.rl(self.r.a);
+                self.r.set_flag(Flag::Z, false);
                 1
             }
             0x18 => {
@@ -183,7 +354,7 @@ impl Z80CPU {
                 2
             }
             0x1B => {
-                self.r.set_de(self.r.get_de().wrapping_rem(1));
+                self.r.set_de(self.r.get_de().wrapping_sub(1));
                 2
             }
             0x1C => {
@@ -200,6 +371,7 @@ impl Z80CPU {
             }
             0x1F => {
                 self.r.a = self.rr(self.r.a);
+                self.r.set_flag(Flag::Z, false);
                 1
             }
             0x20 => {
@@ -207,7 +379,7 @@ impl Z80CPU {
                     self.jr();
                     3
                 } else {
-                    self.r.pc += 1;
+                    self.r.pc = self.r.pc.wrapping_add(1);
                     2
                 }
             }
@@ -217,7 +389,9 @@ impl Z80CPU {
                 3
             }
             0x22 => {
-                self.m.write_byte(self.r.get_hl(), self.r.a); // TODO: correct?
+                let addr = self.r.get_hl();
+                self.m.write_byte(addr, self.r.a);
+                self.r.set_hl(addr.wrapping_add(1));
                 2
             }
             0x23 => {
@@ -245,7 +419,7 @@ impl Z80CPU {
                     self.jr();
                     3
                 } else {
-                    self.r.pc += 1;
+                    self.r.pc = self.r.pc.wrapping_add(1);
                     2
                 }
             }
@@ -255,11 +429,13 @@ impl Z80CPU {
                 2
             }
             0x2A => {
-                self.r.a = self.m.read_byte(self.r.get_hl()); // TODO: correct?
+                let addr = self.r.get_hl();
+                self.r.a = self.m.read_byte(addr);
+                self.r.set_hl(addr.wrapping_add(1));
                 2
             }
             0x2B => {
-                self.r.set_hl(self.r.get_hl().wrapping_rem(1));
+                self.r.set_hl(self.r.get_hl().wrapping_sub(1));
                 2
             }
             0x2C => {
@@ -285,7 +461,7 @@ impl Z80CPU {
                     self.jr();
                     3
                 } else {
-                    self.r.pc += 1;
+                    self.r.pc = self.r.pc.wrapping_add(1);
                     2
                 }
             }
@@ -294,7 +470,9 @@ impl Z80CPU {
                 3
             }
             0x32 => {
-                self.m.write_byte(self.r.get_hl(), self.r.a); // TODO: correct?
+                let addr = self.r.get_hl();
+                self.m.write_byte(addr, self.r.a);
+                self.r.set_hl(addr.wrapping_sub(1));
                 2
             }
             0x33 => {
@@ -329,7 +507,7 @@ impl Z80CPU {
                     self.jr();
                     3
                 } else {
-                    self.r.pc += 1;
+                    self.r.pc = self.r.pc.wrapping_add(1);
                     2
                 }
             }
@@ -339,11 +517,13 @@ impl Z80CPU {
                 2
             }
             0x3A => {
-                self.r.a = self.m.read_byte(self.r.get_hl()); // TODO: correct?
+                let addr = self.r.get_hl();
+                self.r.a = self.m.read_byte(addr);
+                self.r.set_hl(addr.wrapping_sub(1));
                 2
             }
             0x3B => {
-                self.r.sp = self.r.sp.wrapping_rem(1);
+                self.r.sp = self.r.sp.wrapping_sub(1);
                 2
             }
             0x3C => {
@@ -563,7 +743,11 @@ impl Z80CPU {
                 2
             }
             0x76 => {
-                self.halted = true;
+                if !self.ime && self.m.pending_interrupt().is_some() {
+                    self.halt_bug = true;
+                } else {
+                    self.halted = true;
+                }
                 1
             }
             0x77 => {
@@ -881,7 +1065,7 @@ impl Z80CPU {
                     self.r.pc = self.fetch_word();
                     4
                 } else {
-                    self.r.pc += 2;
+                    self.r.pc = self.r.pc.wrapping_add(2);
                     3
                 }
             }
@@ -891,10 +1075,11 @@ impl Z80CPU {
             }
             0xC4 => {
                 if !self.r.get_flag(Flag::Z) {
-                    self.push_stack(self.r.pc + 2);
+                    self.push_stack(self.r.pc.wrapping_add(2));
+                    self.r.pc = self.fetch_word();
                     6
                 } else {
-                    self.r.pc = self.fetch_word();
+                    self.r.pc = self.r.pc.wrapping_add(2);
                     3
                 }
             }
@@ -929,23 +1114,23 @@ impl Z80CPU {
                     self.r.pc = self.fetch_word();
                     4
                 } else {
-                    self.r.pc += 2;
+                    self.r.pc = self.r.pc.wrapping_add(2);
                     3
                 }
             }
             0xCB => self.interpret_cb(),
             0xCC => {
                 if self.r.get_flag(Flag::Z) {
-                    self.push_stack(self.r.pc + 2);
+                    self.push_stack(self.r.pc.wrapping_add(2));
                     self.r.pc = self.fetch_word();
                     6
                 } else {
-                    self.r.pc += 2;
+                    self.r.pc = self.r.pc.wrapping_add(2);
                     3
                 }
             }
             0xCD => {
-                self.push_stack(self.r.pc + 2);
+                self.push_stack(self.r.pc.wrapping_add(2));
                 self.r.pc = self.fetch_word();
                 6
             }
@@ -977,17 +1162,17 @@ impl Z80CPU {
                     self.r.pc = self.fetch_word();
                     4
                 } else {
-                    self.r.pc += 2;
+                    self.r.pc = self.r.pc.wrapping_add(2);
                     3
                 }
             }
             0xD4 => {
                 if !self.r.get_flag(Flag::C) {
-                    self.push_stack(self.r.pc + 2);
+                    self.push_stack(self.r.pc.wrapping_add(2));
                     self.r.pc = self.fetch_word();
                     6
                 } else {
-                    self.r.pc += 2;
+                    self.r.pc = self.r.pc.wrapping_add(2);
                     3
                 }
             }
@@ -1015,7 +1200,7 @@ impl Z80CPU {
             }
             0xD9 => {
                 self.r.pc = self.pop_stack();
-                // TODO: Enable Interrupts
+                self.ime = true;
                 4
             }
             0xDA => {
@@ -1023,17 +1208,17 @@ impl Z80CPU {
                     self.r.pc = self.fetch_word();
                     4
                 } else {
-                    self.r.pc += 2;
+                    self.r.pc = self.r.pc.wrapping_add(2);
                     3
                 }
             }
             0xDC => {
                 if self.r.get_flag(Flag::C) {
-                    self.push_stack(self.r.pc + 2);
+                    self.push_stack(self.r.pc.wrapping_add(2));
                     self.r.pc = self.fetch_word();
                     6
                 } else {
-                    self.r.pc += 2;
+                    self.r.pc = self.r.pc.wrapping_add(2);
                     3
                 }
             }
@@ -1048,7 +1233,7 @@ impl Z80CPU {
                 4
             }
             0xE0 => {
-                let addr = 0xFF00 + self.fetch_byte() as u16;
+                let addr = 0xFF00 | self.fetch_byte() as u16;
                 self.m.write_byte(addr, self.r.a);
                 3
             }
@@ -1058,7 +1243,7 @@ impl Z80CPU {
                 3
             }
             0xE2 => {
-                let addr = 0xFF00 + self.r.c as u16;
+                let addr = 0xFF00 | self.r.c as u16;
                 self.m.write_byte(addr, self.r.a);
                 2
             }
@@ -1115,7 +1300,8 @@ impl Z80CPU {
                 2
             }
             0xF3 => {
-                // TODO: Disable Interrupts
+                self.ime = false;
+                self.ime_pending = false;
                 1
             }
             0xF5 => {
@@ -1147,7 +1333,7 @@ impl Z80CPU {
                 4
             }
             0xFB => {
-                //TODO: Enable interrupts
+                self.ime_pending = true;
                 1
             }
             0xFE => {
@@ -1160,36 +1346,142 @@ impl Z80CPU {
                 self.r.pc = 0x38;
                 4
             }
-            notimpl => unimplemented!("Instruction {:2X} is not implemented", notimpl),
+            illegal => return Err(self.lock_up(illegal)),
+        })
+    }
+
+    // the low three bits of a CB opcode select the operand: B, C, D, E, H, L, (HL), A
+    fn read_operand(&self, idx: u8) -> u8 {
+        match idx {
+            0 => self.r.b,
+            1 => self.r.c,
+            2 => self.r.d,
+            3 => self.r.e,
+            4 => self.r.h,
+            5 => self.r.l,
+            6 => self.m.read_byte(self.r.get_hl()),
+            _ => self.r.a,
+        }
+    }
+
+    fn write_operand(&mut self, idx: u8, val: u8) {
+        match idx {
+            0 => self.r.b = val,
+            1 => self.r.c = val,
+            2 => self.r.d = val,
+            3 => self.r.e = val,
+            4 => self.r.h = val,
+            5 => self.r.l = val,
+            6 => self.m.write_byte(self.r.get_hl(), val),
+            _ => self.r.a = val,
         }
     }
 
     fn interpret_cb(&mut self) -> u32 {
-        match self.fetch_byte() {
-            0x00 => {
-                self.r.b = self.rlc(self.r.b);
-                2
-            }
-            // TODO: Other instructions
-            notimpl => unimplemented!("Instruction {:2X} is not implemented", notimpl),
+        let opcode = self.fetch_byte();
+        let idx = opcode & 0x07;
+        let bit = (opcode >> 3) & 0x07;
+        let val = self.read_operand(idx);
+
+        match opcode {
+            0x00..=0x3F => {
+                let res = match bit {
+                    0 => self.rlc(val),
+                    1 => self.rrc(val),
+                    2 => self.rl(val),
+                    3 => self.rr(val),
+                    4 => self.sla(val),
+                    5 => self.sra(val),
+                    6 => self.swap(val),
+                    _ => self.srl(val),
+                };
+                self.write_operand(idx, res);
+            }
+            0x40..=0x7F => {
+                self.bit(bit, val);
+                // BIT only reads (HL), so it skips the write back cycle
+                return if idx == 6 { 3 } else { 2 };
+            }
+            0x80..=0xBF => self.write_operand(idx, val & !(1 << bit)),
+            0xC0..=0xFF => self.write_operand(idx, val | (1 << bit)),
+        }
+
+        if idx == 6 {
+            4
+        } else {
+            2
         }
     }
 }
 
 #[cfg(test)]
+#[allow(clippy::bool_assert_comparison)]
 mod tests {
+    use super::super::memory::{Cartridge, Interrupt};
     use super::*;
 
+    fn test_cpu() -> Z80CPU {
+        let cartridge = Cartridge::new(vec![0; 0x8000]).unwrap();
+        Z80CPU::new(MemoryBus::new(cartridge))
+    }
+
     #[test]
     fn test_new() {
-        let cpu = Z80CPU::new();
+        let cpu = test_cpu();
         assert_eq!(cpu.halted, false);
         assert_eq!(cpu.ime, false);
     }
 
+    #[test]
+    fn test_skip_boot() {
+        let mut cpu = test_cpu();
+        cpu.skip_boot(Model::Dmg);
+        assert_eq!(cpu.r.get_af(), 0x0180);
+        assert_eq!(cpu.r.get_bc(), 0x0013);
+        assert_eq!(cpu.r.get_de(), 0x00D8);
+        assert_eq!(cpu.r.get_hl(), 0x014D);
+        assert_eq!(cpu.r.sp, 0xFFFE);
+        assert_eq!(cpu.r.pc, 0x0100);
+
+        cpu.skip_boot(Model::Cgb);
+        assert_eq!(cpu.r.get_af(), 0x1180);
+        assert_eq!(cpu.r.get_hl(), 0x007C);
+    }
+
+    #[test]
+    fn test_stop_switches_speed() {
+        let mut rom = vec![0; 0x8000];
+        rom[0x0143] = 0xC0;
+        let mut cpu = Z80CPU::new(MemoryBus::new(Cartridge::new(rom).unwrap()));
+        cpu.skip_boot(Model::Cgb);
+        cpu.m.write_byte(0xC000, 0x10);
+        cpu.m.write_byte(0xC002, 0x10);
+        cpu.r.pc = 0xC000;
+        cpu.cycle().unwrap();
+        assert_eq!(cpu.r.pc, 0xC002);
+        assert!(!cpu.double_speed());
+
+        cpu.m.write_byte(0xFF4D, 0x01);
+        cpu.cycle().unwrap();
+        assert!(cpu.double_speed());
+    }
+
+    #[test]
+    fn test_vram_dma_stalls_cpu() {
+        let mut rom = vec![0; 0x8000];
+        rom[0x0143] = 0x80;
+        let mut cpu = Z80CPU::new(MemoryBus::new(Cartridge::new(rom).unwrap()));
+        cpu.skip_boot(Model::Cgb);
+        cpu.m.write_byte(0xFF40, 0x00);
+        // a NOP after a general purpose transfer of two blocks
+        cpu.m.write_byte(0xFF55, 0x01);
+        assert_eq!(cpu.cycle(), Ok(17));
+        assert_eq!(cpu.cycle(), Ok(1));
+    }
+
     #[test]
     fn test_reset() {
-        let mut cpu = Z80CPU::new();
+        let mut cpu = test_cpu();
         cpu.halted = true;
         cpu.ime = true;
         cpu.reset();
@@ -1199,7 +1491,7 @@ mod tests {
 
     #[test]
     fn test_add() {
-        let mut cpu = Z80CPU::new();
+        let mut cpu = test_cpu();
         cpu.r.a = 0x01;
         cpu.add(0x01);
         assert_eq!(cpu.r.a, 0x02);
@@ -1235,7 +1527,7 @@ mod tests {
 
     #[test]
     fn test_adc() {
-        let mut cpu = Z80CPU::new();
+        let mut cpu = test_cpu();
         cpu.r.a = 0x01;
         cpu.r.set_flag(Flag::C, false);
         cpu.adc(0x01);
@@ -1275,7 +1567,7 @@ mod tests {
 
     #[test]
     fn test_sub() {
-        let mut cpu = Z80CPU::new();
+        let mut cpu = test_cpu();
         cpu.r.a = 0x01;
         cpu.sub(0x01);
         assert_eq!(cpu.r.a, 0x00);
@@ -1311,7 +1603,7 @@ mod tests {
 
     #[test]
     fn test_sbc() {
-        let mut cpu = Z80CPU::new();
+        let mut cpu = test_cpu();
         cpu.r.a = 0x01;
         cpu.r.set_flag(Flag::C, false);
         cpu.sbc(0x01);
@@ -1351,7 +1643,7 @@ mod tests {
 
     #[test]
     fn test_and() {
-        let mut cpu = Z80CPU::new();
+        let mut cpu = test_cpu();
         cpu.r.a = 0x01;
         cpu.and(0x01);
         assert_eq!(cpu.r.a, 0x01);
@@ -1387,7 +1679,7 @@ mod tests {
 
     #[test]
     fn test_xor() {
-        let mut cpu = Z80CPU::new();
+        let mut cpu = test_cpu();
         cpu.r.a = 0x01;
         cpu.xor(0x01);
         assert_eq!(cpu.r.a, 0x00);
@@ -1423,7 +1715,7 @@ mod tests {
 
     #[test]
     fn test_or() {
-        let mut cpu = Z80CPU::new();
+        let mut cpu = test_cpu();
         cpu.r.a = 0x01;
         cpu.or(0x01);
         assert_eq!(cpu.r.a, 0x01);
@@ -1459,7 +1751,7 @@ mod tests {
 
     #[test]
     fn test_cp() {
-        let mut cpu = Z80CPU::new();
+        let mut cpu = test_cpu();
         cpu.r.a = 0x01;
         cpu.cp(0x01);
         assert_eq!(cpu.r.get_flag(Flag::Z), true);
@@ -1491,7 +1783,7 @@ mod tests {
 
     #[test]
     fn test_rlc() {
-        let mut cpu = Z80CPU::new();
+        let mut cpu = test_cpu();
         let value = 0x01;
         let result = cpu.rlc(value);
         assert_eq!(result, 0x02);
@@ -1511,7 +1803,7 @@ mod tests {
 
     #[test]
     fn test_rl() {
-        let mut cpu = Z80CPU::new();
+        let mut cpu = test_cpu();
         let value = 0x01;
         cpu.r.set_flag(Flag::C, false);
         let result = cpu.rl(value);
@@ -1533,7 +1825,7 @@ mod tests {
 
     #[test]
     fn test_rrc() {
-        let mut cpu = Z80CPU::new();
+        let mut cpu = test_cpu();
         let value = 0x01;
         let result = cpu.rrc(value);
         assert_eq!(result, 0x80);
@@ -1545,7 +1837,7 @@ mod tests {
 
     #[test]
     fn test_rr() {
-        let mut cpu = Z80CPU::new();
+        let mut cpu = test_cpu();
         let value = 0x01;
         cpu.r.set_flag(Flag::C, false);
         let result = cpu.rr(value);
@@ -1567,7 +1859,7 @@ mod tests {
 
     #[test]
     fn test_inc() {
-        let mut cpu = Z80CPU::new();
+        let mut cpu = test_cpu();
         let value = 0x01;
         let result = cpu.inc(value);
         assert_eq!(result, 0x02);
@@ -1582,4 +1874,376 @@ mod tests {
         assert_eq!(cpu.r.get_flag(Flag::N), false);
         assert_eq!(cpu.r.get_flag(Flag::H), true);
     }
+
+    const HL_ADDR: u16 = 0xC100;
+
+    fn load_program(cpu: &mut Z80CPU, program: &[u8]) {
+        cpu.r.pc = 0xC000;
+        for (i, byte) in program.iter().enumerate() {
+            cpu.m.write_byte(0xC000 + i as u16, *byte);
+        }
+    }
+
+    fn run_cb(cpu: &mut Z80CPU, opcode: u8) -> u32 {
+        load_program(cpu, &[0xCB, opcode]);
+        let ticks = cpu.interpret().unwrap();
+        assert_eq!(cpu.r.pc, 0xC002);
+        ticks
+    }
+
+    fn set_operand(cpu: &mut Z80CPU, idx: u8, val: u8) {
+        cpu.r.set_hl(HL_ADDR);
+        cpu.write_operand(idx, val);
+    }
+
+    fn get_operand(cpu: &Z80CPU, idx: u8) -> u8 {
+        if idx == 6 {
+            cpu.m.read_byte(HL_ADDR)
+        } else {
+            cpu.read_operand(idx)
+        }
+    }
+
+    #[test]
+    fn test_cb_rotate_shift() {
+        // (base opcode, input, carry in, result, Z, C)
+        let table: [(u8, u8, bool, u8, bool, bool); 20] = [
+            (0x00, 0x85, false, 0x0B, false, true),  // RLC
+            (0x00, 0x00, true, 0x00, true, false),   // RLC
+            (0x08, 0x01, false, 0x80, false, true),  // RRC
+            (0x08, 0x00, true, 0x00, true, false),   // RRC
+            (0x10, 0x80, false, 0x00, true, true),   // RL
+            (0x10, 0x11, true, 0x23, false, false),  // RL
+            (0x18, 0x01, false, 0x00, true, true),   // RR
+            (0x18, 0x8A, true, 0xC5, false, false),  // RR
+            (0x20, 0x80, false, 0x00, true, true),   // SLA
+            (0x20, 0x41, true, 0x82, false, false),  // SLA
+            (0x28, 0x8A, false, 0xC5, false, false), // SRA
+            (0x28, 0x01, true, 0x00, true, true),    // SRA
+            (0x28, 0x81, false, 0xC0, false, true),  // SRA
+            (0x30, 0xF1, true, 0x1F, false, false),  // SWAP
+            (0x30, 0x00, true, 0x00, true, false),   // SWAP
+            (0x30, 0x0F, false, 0xF0, false, false), // SWAP
+            (0x38, 0x01, false, 0x00, true, true),   // SRL
+            (0x38, 0xFF, true, 0x7F, false, true),   // SRL
+            (0x38, 0x80, true, 0x40, false, false),  // SRL
+            (0x00, 0x80, false, 0x01, false, true),  // RLC
+        ];
+
+        for (base, input, carry, result, z, c) in table {
+            for idx in 0..8 {
+                let opcode = base | idx;
+                let mut cpu = test_cpu();
+                set_operand(&mut cpu, idx, input);
+                cpu.r.set_flag(Flag::C, carry);
+                cpu.r.set_flag(Flag::H, true);
+                cpu.r.set_flag(Flag::N, true);
+
+                let ticks = run_cb(&mut cpu, opcode);
+
+                assert_eq!(get_operand(&cpu, idx), result, "opcode CB {:02X}", opcode);
+                assert_eq!(cpu.r.get_flag(Flag::Z), z, "Z of CB {:02X}", opcode);
+                assert_eq!(cpu.r.get_flag(Flag::C), c, "C of CB {:02X}", opcode);
+                assert_eq!(cpu.r.get_flag(Flag::H), false, "H of CB {:02X}", opcode);
+                assert_eq!(cpu.r.get_flag(Flag::N), false, "N of CB {:02X}", opcode);
+                assert_eq!(ticks, if idx == 6 { 4 } else { 2 });
+            }
+        }
+    }
+
+    #[test]
+    fn test_cb_bit() {
+        for opcode in 0x40..=0x7Fu8 {
+            let idx = opcode & 0x07;
+            let bit = (opcode >> 3) & 0x07;
+            for (input, z) in [(1 << bit, false), (!(1 << bit), true)] {
+                let mut cpu = test_cpu();
+                set_operand(&mut cpu, idx, input);
+                cpu.r.set_flag(Flag::C, true);
+                cpu.r.set_flag(Flag::N, true);
+
+                let ticks = run_cb(&mut cpu, opcode);
+
+                assert_eq!(get_operand(&cpu, idx), input, "opcode CB {:02X}", opcode);
+                assert_eq!(cpu.r.get_flag(Flag::Z), z, "Z of CB {:02X}", opcode);
+                assert_eq!(cpu.r.get_flag(Flag::H), true);
+                assert_eq!(cpu.r.get_flag(Flag::N), false);
+                assert_eq!(cpu.r.get_flag(Flag::C), true);
+                assert_eq!(ticks, if idx == 6 { 3 } else { 2 });
+            }
+        }
+    }
+
+    #[test]
+    fn test_cb_res_set() {
+        for opcode in 0x80..=0xFFu8 {
+            let idx = opcode & 0x07;
+            let bit = (opcode >> 3) & 0x07;
+            let (input, result) = if opcode < 0xC0 {
+                (0xFF, !(1 << bit))
+            } else {
+                (0x00, 1 << bit)
+            };
+            let mut cpu = test_cpu();
+            set_operand(&mut cpu, idx, input);
+            let flags = cpu.r.f;
+
+            let ticks = run_cb(&mut cpu, opcode);
+
+            assert_eq!(get_operand(&cpu, idx), result, "opcode CB {:02X}", opcode);
+            assert_eq!(cpu.r.f, flags, "flags of CB {:02X}", opcode);
+            assert_eq!(ticks, if idx == 6 { 4 } else { 2 });
+        }
+    }
+
+    #[test]
+    fn test_illegal_opcodes() {
+        for opcode in [
+            0xD3, 0xDB, 0xDD, 0xE3, 0xE4, 0xEB, 0xEC, 0xED, 0xF4, 0xFC, 0xFD,
+        ] {
+            let mut cpu = test_cpu();
+            load_program(&mut cpu, &[0x00, opcode, 0x00]);
+            assert_eq!(cpu.cycle(), Ok(1));
+            assert_eq!(
+                cpu.cycle(),
+                Err(CpuError::IllegalOpcode { pc: 0xC001, opcode })
+            );
+            assert_eq!(cpu.locked, true);
+
+            // the CPU stays locked up without fetching anything else
+            assert_eq!(cpu.cycle(), Ok(1));
+            assert_eq!(cpu.r.pc, 0xC002);
+
+            cpu.reset();
+            assert_eq!(cpu.locked, false);
+        }
+    }
+
+    #[test]
+    fn test_ei_delay() {
+        let mut cpu = test_cpu();
+        // EI, NOP, DI, EI, DI
+        load_program(&mut cpu, &[0xFB, 0x00, 0xF3, 0xFB, 0xF3]);
+        cpu.cycle().unwrap();
+        assert_eq!(cpu.ime, false);
+        cpu.cycle().unwrap();
+        assert_eq!(cpu.ime, true);
+        cpu.cycle().unwrap();
+        assert_eq!(cpu.ime, false);
+
+        // DI directly after EI cancels the pending enable
+        cpu.cycle().unwrap();
+        cpu.cycle().unwrap();
+        assert_eq!(cpu.ime, false);
+        assert_eq!(cpu.ime_pending, false);
+    }
+
+    #[test]
+    fn test_reti_enables_interrupts() {
+        let mut cpu = test_cpu();
+        cpu.r.sp = 0xD000;
+        cpu.m.write_word(0xD000, 0x1234);
+        load_program(&mut cpu, &[0xD9]);
+        assert_eq!(cpu.cycle(), Ok(4));
+        assert_eq!(cpu.r.pc, 0x1234);
+        assert_eq!(cpu.r.sp, 0xD002);
+        assert_eq!(cpu.ime, true);
+    }
+
+    #[test]
+    fn test_ld_hl_increment_decrement() {
+        let mut cpu = test_cpu();
+        cpu.r.set_hl(HL_ADDR);
+        cpu.r.a = 0x42;
+        // LD (HL+),A; LD (HL-),A; LD A,(HL-); LD A,(HL+)
+        load_program(&mut cpu, &[0x22, 0x32, 0x3A, 0x2A]);
+        cpu.cycle().unwrap();
+        assert_eq!(cpu.m.read_byte(HL_ADDR), 0x42);
+        assert_eq!(cpu.r.get_hl(), HL_ADDR + 1);
+        cpu.cycle().unwrap();
+        assert_eq!(cpu.m.read_byte(HL_ADDR + 1), 0x42);
+        assert_eq!(cpu.r.get_hl(), HL_ADDR);
+
+        cpu.r.a = 0;
+        cpu.cycle().unwrap();
+        assert_eq!(cpu.r.a, 0x42);
+        assert_eq!(cpu.r.get_hl(), HL_ADDR - 1);
+        cpu.cycle().unwrap();
+        assert_eq!(cpu.r.get_hl(), HL_ADDR);
+    }
+
+    #[test]
+    fn test_call_and_ret() {
+        let mut cpu = test_cpu();
+        cpu.r.sp = 0xD000;
+        cpu.r.set_flag(Flag::Z, true);
+        // CALL NZ,C010 (not taken); CALL Z,C010
+        load_program(&mut cpu, &[0xC4, 0x10, 0xC0, 0xCC, 0x10, 0xC0]);
+        cpu.m.write_byte(0xC010, 0xC9);
+        assert_eq!(cpu.cycle(), Ok(3));
+        assert_eq!(cpu.r.pc, 0xC003);
+        assert_eq!(cpu.cycle(), Ok(6));
+        assert_eq!(cpu.r.pc, 0xC010);
+        assert_eq!(cpu.m.read_word(cpu.r.sp), 0xC006);
+        assert_eq!(cpu.cycle(), Ok(4));
+        assert_eq!(cpu.r.pc, 0xC006);
+        assert_eq!(cpu.r.sp, 0xD000);
+    }
+
+    #[test]
+    fn test_dec() {
+        let mut cpu = test_cpu();
+        let result = cpu.dec(0x01);
+        assert_eq!(result, 0x00);
+        assert_eq!(cpu.r.get_flag(Flag::Z), true);
+        assert_eq!(cpu.r.get_flag(Flag::N), true);
+        assert_eq!(cpu.r.get_flag(Flag::H), false);
+
+        let result = cpu.dec(0x10);
+        assert_eq!(result, 0x0F);
+        assert_eq!(cpu.r.get_flag(Flag::Z), false);
+        assert_eq!(cpu.r.get_flag(Flag::N), true);
+        assert_eq!(cpu.r.get_flag(Flag::H), true);
+    }
+
+    #[test]
+    fn test_interrupt_dispatch() {
+        let mut cpu = test_cpu();
+        cpu.r.sp = 0xD000;
+        cpu.ime = true;
+        load_program(&mut cpu, &[0x00]);
+        cpu.m.write_byte(0xFFFF, 0x1F);
+        cpu.m.request_interrupt(Interrupt::Joypad);
+        cpu.m.request_interrupt(Interrupt::Timer);
+
+        assert_eq!(cpu.cycle(), Ok(5));
+        assert_eq!(cpu.r.pc, 0x50);
+        assert_eq!(cpu.r.sp, 0xCFFE);
+        assert_eq!(cpu.m.read_word(0xCFFE), 0xC000);
+        assert_eq!(cpu.ime, false);
+        assert_eq!(cpu.m.read_byte(0xFF0F), 0xE0 | Interrupt::Joypad as u8);
+    }
+
+    #[test]
+    fn test_interrupt_priority() {
+        let vectors = [
+            (Interrupt::VBlank, 0x40),
+            (Interrupt::LcdStat, 0x48),
+            (Interrupt::Timer, 0x50),
+            (Interrupt::Serial, 0x58),
+            (Interrupt::Joypad, 0x60),
+        ];
+        let mut cpu = test_cpu();
+        cpu.r.sp = 0xD000;
+        cpu.m.write_byte(0xFFFF, 0x1F);
+        cpu.m.write_byte(0xFF0F, 0x1F);
+
+        for (interrupt, vector) in vectors {
+            cpu.ime = true;
+            cpu.cycle().unwrap();
+            assert_eq!(cpu.r.pc, vector, "{:?}", interrupt);
+        }
+        assert_eq!(cpu.m.pending_interrupt(), None);
+    }
+
+    #[test]
+    fn test_interrupt_cancelled_by_ie_push() {
+        let mut cpu = test_cpu();
+        // the high byte of PC lands on IE and clears the VBlank enable
+        cpu.r.pc = 0x0200;
+        cpu.r.sp = 0x0000;
+        cpu.ime = true;
+        cpu.m.write_byte(0xFFFF, Interrupt::VBlank as u8);
+        cpu.m.request_interrupt(Interrupt::VBlank);
+        assert_eq!(cpu.cycle(), Ok(5));
+        assert_eq!(cpu.r.pc, 0x0000);
+        assert_eq!(cpu.m.read_byte(0xFFFF), 0x02);
+        assert_eq!(cpu.r.sp, 0xFFFE);
+    }
+
+    #[test]
+    fn test_interrupt_not_enabled() {
+        let mut cpu = test_cpu();
+        cpu.ime = true;
+        load_program(&mut cpu, &[0x00]);
+        cpu.m.write_byte(0xFFFF, Interrupt::VBlank as u8);
+        cpu.m.request_interrupt(Interrupt::Timer);
+        assert_eq!(cpu.cycle(), Ok(1));
+        assert_eq!(cpu.r.pc, 0xC001);
+    }
+
+    #[test]
+    fn test_halt_wakes_without_ime() {
+        let mut cpu = test_cpu();
+        // HALT; INC A
+        load_program(&mut cpu, &[0x76, 0x3C]);
+        cpu.m.write_byte(0xFFFF, Interrupt::Timer as u8);
+        cpu.cycle().unwrap();
+        assert_eq!(cpu.halted, true);
+        cpu.cycle().unwrap();
+        assert_eq!(cpu.halted, true);
+
+        cpu.m.request_interrupt(Interrupt::Timer);
+        cpu.cycle().unwrap();
+        assert_eq!(cpu.halted, false);
+        assert_eq!(cpu.r.a, 0x01);
+        assert_eq!(cpu.r.pc, 0xC002);
+        // without IME the interrupt stays requested
+        assert_eq!(cpu.m.pending_interrupt(), Some(Interrupt::Timer));
+    }
+
+    #[test]
+    fn test_halt_wakes_with_ime() {
+        let mut cpu = test_cpu();
+        cpu.r.sp = 0xD000;
+        cpu.ime = true;
+        load_program(&mut cpu, &[0x76, 0x3C]);
+        cpu.m.write_byte(0xFFFF, Interrupt::Serial as u8);
+        cpu.cycle().unwrap();
+        assert_eq!(cpu.halted, true);
+
+        cpu.m.request_interrupt(Interrupt::Serial);
+        assert_eq!(cpu.cycle(), Ok(6));
+        assert_eq!(cpu.r.pc, 0x58);
+        assert_eq!(cpu.m.read_word(cpu.r.sp), 0xC001);
+    }
+
+    #[test]
+    fn test_halt_bug() {
+        let mut cpu = test_cpu();
+        // HALT; INC A; INC B
+        load_program(&mut cpu, &[0x76, 0x3C, 0x04]);
+        cpu.m.write_byte(0xFFFF, Interrupt::VBlank as u8);
+        cpu.m.request_interrupt(Interrupt::VBlank);
+        cpu.cycle().unwrap();
+        assert_eq!(cpu.halted, false);
+
+        // the byte after HALT is executed twice
+        cpu.cycle().unwrap();
+        cpu.cycle().unwrap();
+        cpu.cycle().unwrap();
+        assert_eq!(cpu.r.a, 0x02);
+        assert_eq!(cpu.r.b, 0x01);
+        assert_eq!(cpu.r.pc, 0xC003);
+    }
+
+    #[test]
+    fn test_timer_interrupt() {
+        let mut cpu = test_cpu();
+        cpu.r.sp = 0xD000;
+        cpu.ime = true;
+        // HALT
+        load_program(&mut cpu, &[0x76]);
+        cpu.m.write_byte(0xFFFF, Interrupt::Timer as u8);
+        cpu.m.write_byte(0xFF05, 0xFF);
+        cpu.m.write_byte(0xFF07, 0x05);
+
+        for _ in 0..5 {
+            cpu.cycle().unwrap();
+            assert_eq!(cpu.halted, true);
+        }
+        cpu.cycle().unwrap();
+        assert_eq!(cpu.r.pc, 0x50);
+        assert_eq!(cpu.halted, false);
+    }
 }
@@ -25,6 +25,12 @@ pub trait Opcodes {
     fn rr(&mut self, val: u8) -> u8;
     fn rrc(&mut self, val: u8) -> u8;
 
+    fn sla(&mut self, val: u8) -> u8;
+    fn sra(&mut self, val: u8) -> u8;
+    fn srl(&mut self, val: u8) -> u8;
+    fn swap(&mut self, val: u8) -> u8;
+    fn bit(&mut self, bit: u8, val: u8);
+
     fn daa(&mut self);
     fn jr(&mut self);
 }
@@ -43,15 +49,14 @@ impl Opcodes for Z80CPU {
     }
 
     fn adc(&mut self, val: u8) {
-        let mut res = (self.r.a as u16) + (val as u16);
-
-        if self.r.get_flag(Flag::C) {
-            res += 1;
-        }
+        let carry = self.r.get_flag(Flag::C) as u8;
+        let res = (self.r.a as u16) + (val as u16) + (carry as u16);
 
         self.r.set_flag(Flag::Z, res.lo() == 0);
-        self.r
-            .set_flag(Flag::H, ((self.r.a & 0x0F) + (val & 0x0F)) & 0x10 == 0x10);
+        self.r.set_flag(
+            Flag::H,
+            ((self.r.a & 0x0F) + (val & 0x0F) + carry) & 0x10 == 0x10,
+        );
         self.r.set_flag(Flag::C, res > 0xFF);
         self.r.set_flag(Flag::N, false);
 
@@ -136,10 +141,10 @@ impl Opcodes for Z80CPU {
     }
 
     fn dec(&mut self, val: u8) -> u8 {
-        let res = val.wrapping_rem(1);
+        let res = val.wrapping_sub(1);
 
         self.r.set_flag(Flag::Z, res == 0);
-        self.r.set_flag(Flag::H, (res & 0xF) == 0xF);
+        self.r.set_flag(Flag::H, (val & 0xF) == 0);
         self.r.set_flag(Flag::N, true);
 
         res
@@ -187,6 +192,48 @@ impl Opcodes for Z80CPU {
         result
     }
 
+    fn sla(&mut self, val: u8) -> u8 {
+        let res = val << 1;
+        self.r.set_flag(Flag::C, val & 0x80 == 0x80);
+        self.r.set_flag(Flag::H, false);
+        self.r.set_flag(Flag::N, false);
+        self.r.set_flag(Flag::Z, res == 0);
+        res
+    }
+
+    fn sra(&mut self, val: u8) -> u8 {
+        let res = (val >> 1) | (val & 0x80);
+        self.r.set_flag(Flag::C, val & 0x01 == 0x01);
+        self.r.set_flag(Flag::H, false);
+        self.r.set_flag(Flag::N, false);
+        self.r.set_flag(Flag::Z, res == 0);
+        res
+    }
+
+    fn srl(&mut self, val: u8) -> u8 {
+        let res = val >> 1;
+        self.r.set_flag(Flag::C, val & 0x01 == 0x01);
+        self.r.set_flag(Flag::H, false);
+        self.r.set_flag(Flag::N, false);
+        self.r.set_flag(Flag::Z, res == 0);
+        res
+    }
+
+    fn swap(&mut self, val: u8) -> u8 {
+        let res = val.rotate_left(4);
+        self.r.set_flag(Flag::C, false);
+        self.r.set_flag(Flag::H, false);
+        self.r.set_flag(Flag::N, false);
+        self.r.set_flag(Flag::Z, res == 0);
+        res
+    }
+
+    fn bit(&mut self, bit: u8, val: u8) {
+        self.r.set_flag(Flag::Z, val & (1 << bit) == 0);
+        self.r.set_flag(Flag::H, true);
+        self.r.set_flag(Flag::N, false);
+    }
+
     fn add_16(&mut self, lhs: u16, rhs: u16) -> u16 {
         let (result, carry) = lhs.overflowing_add(rhs);
         self.r.set_flag(Flag::C, carry);
@@ -204,7 +251,7 @@ impl Opcodes for Z80CPU {
             .set_flag(Flag::H, (lhs & 0x000F) + (rhs & 0x000F) > 0x000F);
         self.r
             .set_flag(Flag::C, (lhs & 0x00FF) + (rhs & 0x00FF) > 0x00FF);
-        return lhs.wrapping_add(rhs);
+        lhs.wrapping_add(rhs)
     }
 
     fn daa(&mut self) {
@@ -0,0 +1,150 @@
+use super::mapper::{Banks, Mapper};
+
+pub const CAMERA_WIDTH: usize = 128;
+pub const CAMERA_HEIGHT: usize = 112;
+// the picture is stored as 2bpp tiles in the first RAM bank after the first 256 bytes
+const IMAGE_OFFSET: usize = 0x100;
+// 4x4 matrix of three thresholds each, at 0xA006-0xA035
+const MATRIX_START: usize = 0x06;
+const REGISTER_COUNT: usize = 0x36;
+
+// Game Boy Camera, RAM bank 0x10 maps the registers of the sensor, a capture turns the host
+// picture into tiles right away using the dither matrix set by the game, exposure, edge
+// enhancement and the other sensor settings are not emulated
+pub struct PocketCamera {
+    banks: Banks,
+    registers_mapped: bool,
+    registers: [u8; REGISTER_COUNT],
+    image: Vec<u8>,
+}
+
+impl PocketCamera {
+    pub fn new(banks: Banks) -> PocketCamera {
+        PocketCamera {
+            banks,
+            registers_mapped: false,
+            registers: [0; REGISTER_COUNT],
+            image: vec![0x80; CAMERA_WIDTH * CAMERA_HEIGHT],
+        }
+    }
+
+    fn capture(&mut self) {
+        for y in 0..CAMERA_HEIGHT {
+            for x in 0..CAMERA_WIDTH {
+                let matrix = MATRIX_START + ((y % 4) * 4 + x % 4) * 3;
+                let thresholds = &self.registers[matrix..matrix + 3];
+                let pixel = self.image[y * CAMERA_WIDTH + x];
+                // brighter pixels get lighter shades
+                let shade = 3 - thresholds.iter().filter(|t| pixel >= **t).count() as u8;
+
+                let tile = (y / 8) * (CAMERA_WIDTH / 8) + x / 8;
+                let row = IMAGE_OFFSET + tile * 16 + (y % 8) * 2;
+                let bit = 0x80 >> (x % 8);
+                let ram = self.banks.ram_mut();
+                for (plane, mask) in [(0, 0x01), (1, 0x02)] {
+                    if shade & mask != 0 {
+                        ram[row + plane] |= bit;
+                    } else {
+                        ram[row + plane] &= !bit;
+                    }
+                }
+            }
+        }
+        self.banks.mark_dirty();
+    }
+}
+
+impl Mapper for PocketCamera {
+    fn banks(&self) -> &Banks {
+        &self.banks
+    }
+
+    fn banks_mut(&mut self) -> &mut Banks {
+        &mut self.banks
+    }
+
+    fn write_register(&mut self, addr: u16, val: u8) {
+        match addr {
+            0x0000..=0x1FFF => self.banks.ram_enabled = (val & 0xF) == 0xA,
+            0x2000..=0x3FFF => self.banks.select_rom_bank((val & 0x3F) as usize),
+            0x4000..=0x5FFF => {
+                self.registers_mapped = val & 0x10 != 0;
+                self.banks.select_ram_bank((val & 0x0F) as usize);
+            }
+            _ => {}
+        }
+    }
+
+    fn reset(&mut self) {
+        self.banks.reset();
+        self.registers_mapped = false;
+        self.registers = [0; REGISTER_COUNT];
+    }
+
+    // the registers are write only except for the capture flag, which clears once done
+    fn read_ram(&self, addr: u16) -> u8 {
+        if !self.registers_mapped {
+            return self.banks.read_ram(addr);
+        }
+        match addr & 0x7F {
+            0x00 => self.registers[0],
+            _ => 0x00,
+        }
+    }
+
+    fn write_ram(&mut self, addr: u16, val: u8) {
+        if !self.registers_mapped {
+            self.banks.write_ram(addr, val);
+            return;
+        }
+        let register = (addr & 0x7F) as usize;
+        if register >= REGISTER_COUNT {
+            return;
+        }
+        self.registers[register] = val;
+        if register == 0 && val & 0x01 != 0 {
+            self.capture();
+            self.registers[0] &= !0x01;
+        }
+    }
+
+    fn set_camera_image(&mut self, pixels: &[u8]) {
+        let len = pixels.len().min(self.image.len());
+        self.image[..len].copy_from_slice(&pixels[..len]);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::tests::rom_with_ram;
+    use super::super::Cartridge;
+    use super::*;
+
+    #[test]
+    fn test_capture() {
+        let mut cartridge = Cartridge::new(rom_with_ram(0xFC, 64, 0x04)).unwrap();
+        cartridge.write_byte(0x0000, 0x0A);
+        // left half dark, right half bright
+        let pixels: Vec<u8> = (0..CAMERA_WIDTH * CAMERA_HEIGHT)
+            .map(|i| if i % CAMERA_WIDTH < 64 { 0x10 } else { 0xF0 })
+            .collect();
+        cartridge.set_camera_image(&pixels);
+
+        cartridge.write_byte(0x4000, 0x10);
+        for register in 0..16 {
+            let matrix = 0xA000 + (MATRIX_START + register * 3) as u16;
+            cartridge.write_byte(matrix, 0x40);
+            cartridge.write_byte(matrix + 1, 0x80);
+            cartridge.write_byte(matrix + 2, 0xC0);
+        }
+        cartridge.write_byte(0xA000, 0x01);
+        assert_eq!(cartridge.read_byte(0xA000) & 0x01, 0);
+
+        cartridge.write_byte(0x4000, 0x00);
+        // first tile is black, tile 8 of the first row white
+        assert_eq!(cartridge.read_byte(0xA100), 0xFF);
+        assert_eq!(cartridge.read_byte(0xA101), 0xFF);
+        assert_eq!(cartridge.read_byte(0xA100 + 8 * 16), 0x00);
+        assert_eq!(cartridge.read_byte(0xA101 + 8 * 16), 0x00);
+    }
+}
@@ -0,0 +1,311 @@
+use super::CartridgeError;
+
+// the cartridge header ends at 0x014F
+const HEADER_END: usize = 0x0150;
+
+// bitmap shown by the boot ROM, every licensed cartridge carries it at 0x0104
+pub const NINTENDO_LOGO: [u8; 48] = [
+    0xCE, 0xED, 0x66, 0x66, 0xCC, 0x0D, 0x00, 0x0B, 0x03, 0x73, 0x00, 0x83, 0x00, 0x0C, 0x00, 0x0D,
+    0x00, 0x08, 0x11, 0x1F, 0x88, 0x89, 0x00, 0x0E, 0xDC, 0xCC, 0x6E, 0xE6, 0xDD, 0xDD, 0xD9, 0x99,
+    0xBB, 0xBB, 0x67, 0x63, 0x6E, 0x0E, 0xEC, 0xCC, 0xDD, 0xDC, 0x99, 0x9F, 0xBB, 0xB9, 0x33, 0x3E,
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CgbSupport {
+    // a DMG game
+    None,
+    // runs on both, with colors on the CGB (0x80)
+    Enhanced,
+    // refuses to run on a DMG (0xC0)
+    Required,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Licensee {
+    // the one byte code at 0x014B
+    Old(u8),
+    // two ASCII characters at 0x0144, used when the old code is 0x33
+    New(String),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Destination {
+    Japan,
+    Overseas,
+}
+
+// the fields of the cartridge header at 0x0100-0x014F
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CartridgeHeader {
+    pub title: String,
+    // four letter code of newer games, part of the title on older ones
+    pub manufacturer_code: Option<String>,
+    pub cgb_support: CgbSupport,
+    pub sgb_support: bool,
+    // byte 0x0147, selects the MBC and extra hardware
+    pub cartridge_type: u8,
+    pub licensee: Licensee,
+    // sizes in bytes
+    pub rom_size: usize,
+    pub ram_size: usize,
+    pub destination: Destination,
+    pub version: u8,
+    pub header_checksum: u8,
+    pub global_checksum: u16,
+}
+
+impl CartridgeHeader {
+    pub fn parse(rom: &[u8]) -> Result<CartridgeHeader, CartridgeError> {
+        if rom.len() < HEADER_END {
+            return Err(CartridgeError::Truncated { size: rom.len() });
+        }
+
+        let rom_size = match rom[0x0148] {
+            code @ 0x00..=0x08 => 0x8000 << code,
+            code => return Err(CartridgeError::UnknownRomSize { code }),
+        };
+        if rom.len() < rom_size {
+            return Err(CartridgeError::RomSizeMismatch {
+                declared: rom_size,
+                actual: rom.len(),
+            });
+        }
+        let ram_size = match rom[0x0149] {
+            0x00 => 0,
+            0x01 => 0x800,
+            0x02 => 0x2000,
+            0x03 => 0x8000,
+            0x04 => 0x20000,
+            0x05 => 0x10000,
+            code => return Err(CartridgeError::UnknownRamSize { code }),
+        };
+
+        let cgb_support = match rom[0x0143] {
+            0xC0 => CgbSupport::Required,
+            flag if flag & 0x80 != 0 => CgbSupport::Enhanced,
+            _ => CgbSupport::None,
+        };
+        // CGB games use the last title byte as flag, newer ones also the four before it
+        let manufacturer = &rom[0x013F..0x0143];
+        let (title, manufacturer_code) = if cgb_support == CgbSupport::None {
+            (&rom[0x0134..0x0144], None)
+        } else if manufacturer
+            .iter()
+            .all(|c| c.is_ascii_uppercase() || c.is_ascii_digit())
+        {
+            (&rom[0x0134..0x013F], Some(ascii_string(manufacturer)))
+        } else {
+            (&rom[0x0134..0x0143], None)
+        };
+
+        let licensee = match rom[0x014B] {
+            0x33 => Licensee::New(ascii_string(&rom[0x0144..0x0146])),
+            code => Licensee::Old(code),
+        };
+
+        Ok(CartridgeHeader {
+            title: ascii_string(title),
+            manufacturer_code,
+            cgb_support,
+            sgb_support: rom[0x0146] == 0x03,
+            cartridge_type: rom[0x0147],
+            licensee,
+            rom_size,
+            ram_size,
+            destination: if rom[0x014A] == 0x00 {
+                Destination::Japan
+            } else {
+                Destination::Overseas
+            },
+            version: rom[0x014C],
+            header_checksum: rom[0x014D],
+            global_checksum: u16::from_be_bytes([rom[0x014E], rom[0x014F]]),
+        })
+    }
+
+    // check the logo and both checksums, the boot ROM refuses to start games with a bad
+    // logo or header checksum while the global checksum is never verified by the hardware
+    pub fn verify(&self, rom: &[u8]) -> Result<(), CartridgeError> {
+        if rom.len() < HEADER_END {
+            return Err(CartridgeError::Truncated { size: rom.len() });
+        }
+        if rom[0x0104..0x0134] != NINTENDO_LOGO {
+            return Err(CartridgeError::InvalidLogo);
+        }
+        let header_checksum = header_checksum(rom);
+        if header_checksum != self.header_checksum {
+            return Err(CartridgeError::HeaderChecksum {
+                expected: self.header_checksum,
+                actual: header_checksum,
+            });
+        }
+        let global_checksum = global_checksum(rom);
+        if global_checksum != self.global_checksum {
+            return Err(CartridgeError::GlobalChecksum {
+                expected: self.global_checksum,
+                actual: global_checksum,
+            });
+        }
+        Ok(())
+    }
+
+    // RAM and clock of these cartridge types survive power off, the MBC6 flash and the MBC7
+    // and TAMA5 EEPROMs keep their contents without battery but are saved the same way
+    pub fn has_battery(&self) -> bool {
+        matches!(
+            self.cartridge_type,
+            0x03 | 0x06 | 0x09 | 0x0D | 0x0F | 0x10 | 0x13 | 0x1B | 0x1E
+        ) || matches!(self.cartridge_type, 0x20 | 0x22 | 0xFC..=0xFF)
+    }
+}
+
+fn header_checksum(rom: &[u8]) -> u8 {
+    rom[0x0134..0x014D]
+        .iter()
+        .fold(0u8, |sum, byte| sum.wrapping_sub(*byte).wrapping_sub(1))
+}
+
+// sum of all bytes of the ROM except the checksum itself
+fn global_checksum(rom: &[u8]) -> u16 {
+    rom.iter()
+        .enumerate()
+        .filter(|(addr, _)| *addr != 0x014E && *addr != 0x014F)
+        .fold(0u16, |sum, (_, byte)| sum.wrapping_add(*byte as u16))
+}
+
+// header strings are padded with zeros, anything that is not printable is dropped
+fn ascii_string(bytes: &[u8]) -> String {
+    bytes
+        .iter()
+        .take_while(|c| **c != 0)
+        .filter(|c| c.is_ascii_graphic() || **c == b' ')
+        .map(|c| *c as char)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fix_checksums(rom: &mut [u8]) {
+        rom[0x014D] = header_checksum(rom);
+        let [hi, lo] = global_checksum(rom).to_be_bytes();
+        rom[0x014E] = hi;
+        rom[0x014F] = lo;
+    }
+
+    fn valid_rom() -> Vec<u8> {
+        let mut rom = vec![0; 0x10000];
+        rom[0x0104..0x0134].copy_from_slice(&NINTENDO_LOGO);
+        rom[0x0134..0x013A].copy_from_slice(b"TETRIS");
+        rom[0x0147] = 0x03;
+        rom[0x0148] = 0x01;
+        rom[0x0149] = 0x03;
+        rom[0x014A] = 0x01;
+        rom[0x014B] = 0x01;
+        rom[0x014C] = 0x02;
+        fix_checksums(&mut rom);
+        rom
+    }
+
+    #[test]
+    fn test_parse() {
+        let rom = valid_rom();
+        let header = CartridgeHeader::parse(&rom).unwrap();
+        assert_eq!(header.title, "TETRIS");
+        assert_eq!(header.manufacturer_code, None);
+        assert_eq!(header.cgb_support, CgbSupport::None);
+        assert!(!header.sgb_support);
+        assert_eq!(header.licensee, Licensee::Old(0x01));
+        assert_eq!(header.rom_size, 0x10000);
+        assert_eq!(header.ram_size, 0x8000);
+        assert_eq!(header.destination, Destination::Overseas);
+        assert_eq!(header.version, 2);
+        assert!(header.has_battery());
+        assert_eq!(header.verify(&rom), Ok(()));
+    }
+
+    #[test]
+    fn test_cgb_title() {
+        let mut rom = valid_rom();
+        rom[0x0134..0x0143].copy_from_slice(b"POKEMON_GLDAAUE");
+        rom[0x0143] = 0x80;
+        rom[0x0144..0x0146].copy_from_slice(b"01");
+        rom[0x0146] = 0x03;
+        rom[0x014B] = 0x33;
+        let header = CartridgeHeader::parse(&rom).unwrap();
+        assert_eq!(header.title, "POKEMON_GLD");
+        assert_eq!(header.manufacturer_code.as_deref(), Some("AAUE"));
+        assert_eq!(header.cgb_support, CgbSupport::Enhanced);
+        assert!(header.sgb_support);
+        assert_eq!(header.licensee, Licensee::New("01".to_string()));
+
+        // older CGB games use 15 title characters
+        rom[0x0134..0x0143].copy_from_slice(b"ZELDA DX\0\0\0\0\0\0\0");
+        rom[0x0143] = 0xC0;
+        let header = CartridgeHeader::parse(&rom).unwrap();
+        assert_eq!(header.title, "ZELDA DX");
+        assert_eq!(header.manufacturer_code, None);
+        assert_eq!(header.cgb_support, CgbSupport::Required);
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert_eq!(
+            CartridgeHeader::parse(&[0; 0x14F]),
+            Err(CartridgeError::Truncated { size: 0x14F })
+        );
+
+        let mut rom = valid_rom();
+        rom[0x0148] = 0x09;
+        assert_eq!(
+            CartridgeHeader::parse(&rom),
+            Err(CartridgeError::UnknownRomSize { code: 0x09 })
+        );
+        rom[0x0148] = 0x02;
+        assert_eq!(
+            CartridgeHeader::parse(&rom),
+            Err(CartridgeError::RomSizeMismatch {
+                declared: 0x20000,
+                actual: 0x10000
+            })
+        );
+
+        let mut rom = valid_rom();
+        rom[0x0149] = 0x06;
+        assert_eq!(
+            CartridgeHeader::parse(&rom),
+            Err(CartridgeError::UnknownRamSize { code: 0x06 })
+        );
+    }
+
+    #[test]
+    fn test_verify() {
+        let mut rom = valid_rom();
+        rom[0x0104] = 0;
+        let header = CartridgeHeader::parse(&rom).unwrap();
+        assert_eq!(header.verify(&rom), Err(CartridgeError::InvalidLogo));
+
+        let mut rom = valid_rom();
+        rom[0x014D] ^= 0xFF;
+        let header = CartridgeHeader::parse(&rom).unwrap();
+        assert!(matches!(
+            header.verify(&rom),
+            Err(CartridgeError::HeaderChecksum { .. })
+        ));
+
+        let mut rom = valid_rom();
+        rom[0x4000] = 0x42;
+        let header = CartridgeHeader::parse(&rom).unwrap();
+        assert!(matches!(
+            header.verify(&rom),
+            Err(CartridgeError::GlobalChecksum { .. })
+        ));
+
+        // a header parsed from one ROM checked against a shorter slice
+        assert_eq!(
+            header.verify(&rom[..0x0100]),
+            Err(CartridgeError::Truncated { size: 0x0100 })
+        );
+    }
+}
@@ -0,0 +1,99 @@
+use super::mapper::{Banks, Mapper};
+
+// Hudson HuC1, writing 0x0E to the enable register maps the infrared port instead of RAM,
+// there is no RAM enable, RAM is mapped with any other value
+pub struct Huc1 {
+    banks: Banks,
+    ir_mode: bool,
+    ir_input: bool,
+    ir_output: bool,
+}
+
+impl Huc1 {
+    pub fn new(mut banks: Banks) -> Huc1 {
+        banks.ram_enabled = true;
+        Huc1 {
+            banks,
+            ir_mode: false,
+            ir_input: false,
+            ir_output: false,
+        }
+    }
+}
+
+impl Mapper for Huc1 {
+    fn banks(&self) -> &Banks {
+        &self.banks
+    }
+
+    fn banks_mut(&mut self) -> &mut Banks {
+        &mut self.banks
+    }
+
+    fn write_register(&mut self, addr: u16, val: u8) {
+        match addr {
+            0x0000..=0x1FFF => self.ir_mode = val == 0x0E,
+            0x2000..=0x3FFF => self.banks.select_rom_bank((val & 0x3F).max(1) as usize),
+            0x4000..=0x5FFF => self.banks.select_ram_bank((val & 0x03) as usize),
+            _ => {}
+        }
+    }
+
+    fn reset(&mut self) {
+        self.banks.reset();
+        self.banks.ram_enabled = true;
+        self.ir_mode = false;
+        self.ir_output = false;
+    }
+
+    // bit 0 is set while light is received
+    fn read_ram(&self, addr: u16) -> u8 {
+        if self.ir_mode {
+            0xC0 | self.ir_input as u8
+        } else {
+            self.banks.read_ram(addr)
+        }
+    }
+
+    fn write_ram(&mut self, addr: u16, val: u8) {
+        if self.ir_mode {
+            self.ir_output = val & 0x01 != 0;
+        } else {
+            self.banks.write_ram(addr, val);
+        }
+    }
+
+    fn set_ir_input(&mut self, light: bool) {
+        self.ir_input = light;
+    }
+
+    fn ir_output(&self) -> bool {
+        self.ir_output
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::tests::rom_with_ram;
+    use super::super::Cartridge;
+
+    #[test]
+    fn test_ir_and_ram() {
+        let mut cartridge = Cartridge::new(rom_with_ram(0xFF, 64, 0x03)).unwrap();
+        cartridge.write_byte(0x2000, 0x3F);
+        assert_eq!(cartridge.read_byte(0x4000), 0x3F);
+
+        cartridge.write_byte(0xA000, 0x42);
+        assert_eq!(cartridge.read_byte(0xA000), 0x42);
+
+        cartridge.write_byte(0x0000, 0x0E);
+        assert_eq!(cartridge.read_byte(0xA000), 0xC0);
+        cartridge.set_ir_input(true);
+        assert_eq!(cartridge.read_byte(0xA000), 0xC1);
+        cartridge.write_byte(0xA000, 0x01);
+        assert!(cartridge.ir_output());
+
+        cartridge.write_byte(0x0000, 0x00);
+        assert_eq!(cartridge.read_byte(0xA000), 0x42);
+    }
+}
@@ -0,0 +1,242 @@
+use super::mapper::{Banks, Mapper};
+use super::rtc::{SystemClock, TimeSource};
+
+// size of the clock state appended to the RAM in save files
+pub const HUC3_CLOCK_STATE_SIZE: usize = 8;
+const MINUTES_PER_DAY: u64 = 24 * 60;
+
+// Hudson HuC3, the register at 0x0000 selects what is mapped at 0xA000: RAM, the command
+// interface of the clock, a ready flag or the infrared port
+pub struct Huc3 {
+    banks: Banks,
+    mode: u8,
+    time_source: Box<dyn TimeSource>,
+    // unix time at which the clock read zero minutes and days
+    clock_base: u64,
+    // clock memory of 256 nibbles the game reads and writes through the command interface
+    memory: [u8; 0x100],
+    pointer: usize,
+    // last command and its result as read back in mode 0x0C
+    response: u8,
+    ir_input: bool,
+    ir_output: bool,
+}
+
+impl Huc3 {
+    pub fn new(banks: Banks) -> Huc3 {
+        let time_source: Box<dyn TimeSource> = Box::new(SystemClock);
+        let clock_base = time_source.unix_time();
+        Huc3 {
+            banks,
+            mode: 0,
+            time_source,
+            clock_base,
+            memory: [0; 0x100],
+            pointer: 0,
+            response: 0,
+            ir_input: false,
+            ir_output: false,
+        }
+    }
+
+    fn elapsed_minutes(&self) -> u64 {
+        self.time_source.unix_time().saturating_sub(self.clock_base) / 60
+    }
+
+    // commands have the command in the upper and the argument in the lower nibble
+    fn command(&mut self, val: u8) {
+        let argument = val & 0x0F;
+        let mut result = argument;
+        match val >> 4 {
+            // read the clock memory and advance
+            0x1 => {
+                result = self.memory[self.pointer];
+                self.pointer = (self.pointer + 1) & 0xFF;
+            }
+            // write the clock memory and advance
+            0x3 => {
+                self.memory[self.pointer] = argument;
+                self.pointer = (self.pointer + 1) & 0xFF;
+            }
+            0x4 => self.pointer = (self.pointer & 0xF0) | argument as usize,
+            0x5 => self.pointer = (self.pointer & 0x0F) | (argument as usize) << 4,
+            // copy the time to memory, or set the time from memory: minutes of the day and
+            // days are stored in three nibbles each, lowest first
+            0x6 => match argument {
+                0x0 => {
+                    let elapsed = self.elapsed_minutes();
+                    let minutes = elapsed % MINUTES_PER_DAY;
+                    let days = (elapsed / MINUTES_PER_DAY) & 0xFFF;
+                    for nibble in 0..3 {
+                        self.memory[nibble] = (minutes >> (nibble * 4)) as u8 & 0x0F;
+                        self.memory[3 + nibble] = (days >> (nibble * 4)) as u8 & 0x0F;
+                    }
+                }
+                0x1 => {
+                    let (minutes, days) = (0..3).fold((0, 0), |(minutes, days), nibble| {
+                        (
+                            minutes | (self.memory[nibble] as u64) << (nibble * 4),
+                            days | (self.memory[3 + nibble] as u64) << (nibble * 4),
+                        )
+                    });
+                    let elapsed = (days * MINUTES_PER_DAY + minutes) * 60;
+                    self.clock_base = self.time_source.unix_time().saturating_sub(elapsed);
+                    self.banks.mark_dirty();
+                }
+                _ => {}
+            },
+            _ => {}
+        }
+        self.response = (val & 0xF0) | (result & 0x0F);
+    }
+}
+
+impl Mapper for Huc3 {
+    fn banks(&self) -> &Banks {
+        &self.banks
+    }
+
+    fn banks_mut(&mut self) -> &mut Banks {
+        &mut self.banks
+    }
+
+    fn write_register(&mut self, addr: u16, val: u8) {
+        match addr {
+            0x0000..=0x1FFF => {
+                self.mode = val & 0x0F;
+                self.banks.ram_enabled = self.mode == 0x0A || self.mode == 0x00;
+            }
+            0x2000..=0x3FFF => self.banks.select_rom_bank((val & 0x7F) as usize),
+            0x4000..=0x5FFF => self.banks.select_ram_bank((val & 0x03) as usize),
+            _ => {}
+        }
+    }
+
+    fn reset(&mut self) {
+        self.banks.reset();
+        self.mode = 0;
+        self.pointer = 0;
+        self.response = 0;
+        self.ir_output = false;
+    }
+
+    fn read_ram(&self, addr: u16) -> u8 {
+        match self.mode {
+            0x0C => self.response,
+            // the clock is always ready for the next command
+            0x0D => 0x01,
+            0x0E => 0xC0 | self.ir_input as u8,
+            _ => self.banks.read_ram(addr),
+        }
+    }
+
+    fn write_ram(&mut self, addr: u16, val: u8) {
+        match self.mode {
+            // mode 0x00 maps RAM read only
+            0x0A => self.banks.write_ram(addr, val),
+            0x0B => self.command(val),
+            0x0E => self.ir_output = val & 0x01 != 0,
+            _ => {}
+        }
+    }
+
+    // RAM contents followed by the unix time the clock counts from, in little endian
+    fn save_data(&mut self) -> Vec<u8> {
+        let mut data = self.banks.ram().to_vec();
+        data.extend_from_slice(&self.clock_base.to_le_bytes());
+        data
+    }
+
+    fn load_save_data(&mut self, data: &[u8]) -> bool {
+        let ram_size = self.banks.ram().len();
+        if data.len() != ram_size && data.len() != ram_size + HUC3_CLOCK_STATE_SIZE {
+            return false;
+        }
+        let (ram, clock_state) = data.split_at(ram_size);
+        self.banks.ram_mut().copy_from_slice(ram);
+        if let Ok(clock_base) = clock_state.try_into() {
+            self.clock_base = u64::from_le_bytes(clock_base);
+        }
+        true
+    }
+
+    fn set_time_source(&mut self, time_source: Box<dyn TimeSource>) {
+        // keep the current time of the clock
+        let elapsed = self.time_source.unix_time().saturating_sub(self.clock_base);
+        self.clock_base = time_source.unix_time().saturating_sub(elapsed);
+        self.time_source = time_source;
+    }
+
+    fn set_ir_input(&mut self, light: bool) {
+        self.ir_input = light;
+    }
+
+    fn ir_output(&self) -> bool {
+        self.ir_output
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::rtc::tests::FakeClock;
+    use super::super::tests::rom_with_ram;
+    use super::super::Cartridge;
+
+    fn command(cartridge: &mut Cartridge, val: u8) -> u8 {
+        cartridge.write_byte(0x0000, 0x0B);
+        cartridge.write_byte(0xA000, val);
+        cartridge.write_byte(0x0000, 0x0C);
+        cartridge.read_byte(0xA000)
+    }
+
+    #[test]
+    fn test_clock() {
+        let clock = FakeClock::default();
+        let mut cartridge = Cartridge::new(rom_with_ram(0xFE, 4, 0x03)).unwrap();
+        cartridge.set_time_source(Box::new(clock.clone()));
+        clock.advance((2 * 24 * 60 + 90) * 60);
+
+        command(&mut cartridge, 0x60);
+        command(&mut cartridge, 0x40);
+        command(&mut cartridge, 0x50);
+        let nibbles: Vec<u8> = (0..6)
+            .map(|_| command(&mut cartridge, 0x10) & 0x0F)
+            .collect();
+        assert_eq!(nibbles, [0xA, 0x5, 0x0, 0x2, 0x0, 0x0]);
+
+        // set the clock to one minute
+        command(&mut cartridge, 0x40);
+        for nibble in [0x1, 0x0, 0x0, 0x0, 0x0, 0x0] {
+            command(&mut cartridge, 0x30 | nibble);
+        }
+        command(&mut cartridge, 0x61);
+        clock.advance(60);
+        command(&mut cartridge, 0x60);
+        command(&mut cartridge, 0x40);
+        assert_eq!(command(&mut cartridge, 0x10), 0x12);
+
+        cartridge.write_byte(0x0000, 0x0D);
+        assert_eq!(cartridge.read_byte(0xA000), 0x01);
+    }
+
+    #[test]
+    fn test_ram_modes() {
+        let mut cartridge = Cartridge::new(rom_with_ram(0xFE, 4, 0x03)).unwrap();
+        cartridge.write_byte(0x0000, 0x0A);
+        cartridge.write_byte(0xA000, 0x42);
+        // mode 0 maps RAM read only
+        cartridge.write_byte(0x0000, 0x00);
+        cartridge.write_byte(0xA000, 0x00);
+        assert_eq!(cartridge.read_byte(0xA000), 0x42);
+
+        cartridge.write_byte(0x0000, 0x0E);
+        cartridge.set_ir_input(true);
+        assert_eq!(cartridge.read_byte(0xA000), 0xC1);
+
+        let data = cartridge.save_data().unwrap();
+        let mut cartridge = Cartridge::new(rom_with_ram(0xFE, 4, 0x03)).unwrap();
+        cartridge.load_save_data(&data).unwrap();
+        cartridge.write_byte(0x0000, 0x0A);
+        assert_eq!(cartridge.read_byte(0xA000), 0x42);
+    }
+}
@@ -0,0 +1,162 @@
+use super::rtc::TimeSource;
+
+pub const ROM_BANK_SIZE: usize = 0x4000;
+pub const RAM_BANK_SIZE: usize = 0x2000;
+
+// ROM and RAM of a cartridge together with the banks currently mapped, shared by all mappers
+pub struct Banks {
+    rom: Vec<u8>,
+    // external RAM, empty for cartridges without RAM
+    ram: Vec<u8>,
+    // ROM offsets of the banks mapped at 0x0000 and 0x4000
+    rom0_offset: usize,
+    romx_offset: usize,
+    ram_offset: usize,
+    pub ram_enabled: bool,
+    // set by RAM writes, cleared when the frontend collects them
+    ram_dirty: bool,
+}
+
+impl Banks {
+    pub fn new(rom: Vec<u8>, ram_size: usize) -> Banks {
+        Banks {
+            rom,
+            ram: vec![0; ram_size],
+            rom0_offset: 0,
+            romx_offset: ROM_BANK_SIZE,
+            ram_offset: 0,
+            ram_enabled: false,
+            ram_dirty: false,
+        }
+    }
+
+    // map bank 0 and 1 and disable RAM like at power on
+    pub fn reset(&mut self) {
+        self.rom0_offset = 0;
+        self.romx_offset = self.rom_offset(1, ROM_BANK_SIZE);
+        self.ram_offset = 0;
+        self.ram_enabled = false;
+    }
+
+    pub fn rom(&self) -> &[u8] {
+        &self.rom
+    }
+
+    pub fn ram(&self) -> &[u8] {
+        &self.ram
+    }
+
+    pub fn ram_mut(&mut self) -> &mut [u8] {
+        &mut self.ram
+    }
+
+    // banks beyond the ROM size wrap around like on hardware with unconnected address lines
+    pub fn rom_offset(&self, bank: usize, bank_size: usize) -> usize {
+        let banks = (self.rom.len() / bank_size).max(1);
+        (bank % banks) * bank_size
+    }
+
+    pub fn select_rom0_bank(&mut self, bank: usize) {
+        self.rom0_offset = self.rom_offset(bank, ROM_BANK_SIZE);
+    }
+
+    pub fn select_rom_bank(&mut self, bank: usize) {
+        self.romx_offset = self.rom_offset(bank, ROM_BANK_SIZE);
+    }
+
+    pub fn select_ram_bank(&mut self, bank: usize) {
+        let banks = (self.ram.len() / RAM_BANK_SIZE).max(1);
+        self.ram_offset = (bank % banks) * RAM_BANK_SIZE;
+    }
+
+    pub fn read_rom(&self, addr: u16) -> u8 {
+        match addr {
+            0x0000..=0x3FFF => self.rom[self.rom0_offset + addr as usize],
+            _ => self.rom[self.romx_offset + (addr as usize & 0x3FFF)],
+        }
+    }
+
+    // disabled or missing RAM reads as open bus, RAM smaller than a bank is mirrored
+    pub fn read_ram(&self, addr: u16) -> u8 {
+        if !self.ram_enabled || self.ram.is_empty() {
+            return 0xFF;
+        }
+        self.ram[(self.ram_offset + (addr - 0xA000) as usize) % self.ram.len()]
+    }
+
+    pub fn write_ram(&mut self, addr: u16, val: u8) {
+        if self.ram_enabled && !self.ram.is_empty() {
+            let index = (self.ram_offset + (addr - 0xA000) as usize) % self.ram.len();
+            self.ram[index] = val;
+            self.ram_dirty = true;
+        }
+    }
+
+    // for mappers whose battery backed state lives outside of the RAM
+    pub fn mark_dirty(&mut self) {
+        self.ram_dirty = true;
+    }
+
+    pub fn take_dirty(&mut self) -> bool {
+        std::mem::take(&mut self.ram_dirty)
+    }
+}
+
+// the memory controller of a cartridge, it sees all accesses to 0x0000-0x7FFF and 0xA000-0xBFFF
+pub trait Mapper {
+    fn banks(&self) -> &Banks;
+    fn banks_mut(&mut self) -> &mut Banks;
+
+    // writes to the ROM area go to the mapper registers
+    fn write_register(&mut self, addr: u16, val: u8);
+
+    // restore the power-on state of the registers, memory contents are kept
+    fn reset(&mut self);
+
+    fn read_rom(&self, addr: u16) -> u8 {
+        self.banks().read_rom(addr)
+    }
+
+    fn read_ram(&self, addr: u16) -> u8 {
+        self.banks().read_ram(addr)
+    }
+
+    fn write_ram(&mut self, addr: u16, val: u8) {
+        self.banks_mut().write_ram(addr, val);
+    }
+
+    // battery backed state in the layout of .sav files
+    fn save_data(&mut self) -> Vec<u8> {
+        self.banks().ram().to_vec()
+    }
+
+    // false if the data does not fit the cartridge
+    fn load_save_data(&mut self, data: &[u8]) -> bool {
+        if data.len() != self.banks().ram().len() {
+            return false;
+        }
+        self.banks_mut().ram_mut().copy_from_slice(data);
+        true
+    }
+
+    // true if the battery backed state changed since the last call
+    fn take_save_dirty(&mut self) -> bool {
+        self.banks_mut().take_dirty()
+    }
+
+    // host side inputs of the cartridges that have the hardware, ignored by all others
+    fn set_time_source(&mut self, _time_source: Box<dyn TimeSource>) {}
+
+    // tilt in g along both axes, positive x is right and positive y is down
+    fn set_tilt(&mut self, _x: f32, _y: f32) {}
+
+    fn set_ir_input(&mut self, _light: bool) {}
+
+    // state of the infrared LED
+    fn ir_output(&self) -> bool {
+        false
+    }
+
+    // grayscale picture of 128x112 pixels for the camera sensor
+    fn set_camera_image(&mut self, _pixels: &[u8]) {}
+}
@@ -0,0 +1,82 @@
+use super::header::NINTENDO_LOGO;
+use super::mapper::{Banks, Mapper, ROM_BANK_SIZE};
+
+// bank1 is the 5 bit ROM bank register, bank2 the 2 bit register that extends the ROM bank
+// or selects the RAM bank, multicarts only wire up 4 bits of bank1
+pub struct Mbc1 {
+    banks: Banks,
+    bank1: u8,
+    bank2: u8,
+    advanced_mode: bool,
+    multicart: bool,
+}
+
+impl Mbc1 {
+    pub fn new(banks: Banks) -> Mbc1 {
+        let multicart = is_multicart(banks.rom());
+        Mbc1 {
+            banks,
+            bank1: 0,
+            bank2: 0,
+            advanced_mode: false,
+            multicart,
+        }
+    }
+
+    // recompute the mapped banks from the registers
+    fn update_banks(&mut self) {
+        // a zero in bank1 selects bank 1, this is checked before multicarts drop the fifth bit
+        let bank1 = if self.bank1 == 0 {
+            1
+        } else {
+            self.bank1 as usize
+        };
+        let (bank1, high_bank) = if self.multicart {
+            (bank1 & 0x0F, (self.bank2 as usize) << 4)
+        } else {
+            (bank1, (self.bank2 as usize) << 5)
+        };
+
+        self.banks.select_rom_bank(high_bank | bank1);
+        if self.advanced_mode {
+            self.banks.select_rom0_bank(high_bank);
+            self.banks.select_ram_bank(self.bank2 as usize);
+        } else {
+            self.banks.select_rom0_bank(0);
+            self.banks.select_ram_bank(0);
+        }
+    }
+}
+
+impl Mapper for Mbc1 {
+    fn banks(&self) -> &Banks {
+        &self.banks
+    }
+
+    fn banks_mut(&mut self) -> &mut Banks {
+        &mut self.banks
+    }
+
+    fn write_register(&mut self, addr: u16, val: u8) {
+        match addr {
+            0x0000..=0x1FFF => self.banks.ram_enabled = (val & 0xF) == 0xA,
+            0x2000..=0x3FFF => self.bank1 = val & 0x1F,
+            0x4000..=0x5FFF => self.bank2 = val & 0x03,
+            _ => self.advanced_mode = val & 0x01 != 0,
+        }
+        self.update_banks();
+    }
+
+    fn reset(&mut self) {
+        self.banks.reset();
+        self.bank1 = 0;
+        self.bank2 = 0;
+        self.advanced_mode = false;
+    }
+}
+
+// MBC1M multicarts are 1 MiB ROMs that repeat the Nintendo logo in the header of the game at bank 0x10
+fn is_multicart(rom: &[u8]) -> bool {
+    let header = 0x10 * ROM_BANK_SIZE + 0x0104;
+    rom.len() == 0x100000 && rom[header..header + NINTENDO_LOGO.len()] == NINTENDO_LOGO
+}
@@ -0,0 +1,48 @@
+use log::trace;
+
+use super::mapper::{Banks, Mapper};
+
+// MBC2 has 512 4 bit cells built in, the header declares no RAM for it
+pub const MBC2_RAM_SIZE: usize = 0x200;
+
+pub struct Mbc2 {
+    banks: Banks,
+}
+
+impl Mbc2 {
+    pub fn new(banks: Banks) -> Mbc2 {
+        Mbc2 { banks }
+    }
+}
+
+impl Mapper for Mbc2 {
+    fn banks(&self) -> &Banks {
+        &self.banks
+    }
+
+    fn banks_mut(&mut self) -> &mut Banks {
+        &mut self.banks
+    }
+
+    // address bit 8 selects between RAM enable and ROM bank in the whole lower area
+    fn write_register(&mut self, addr: u16, val: u8) {
+        match addr {
+            0x0000..=0x3FFF if (addr & 0x100) == 0 => self.banks.ram_enabled = (val & 0xF) == 0xA,
+            0x0000..=0x3FFF => self.banks.select_rom_bank((val & 0xF).max(1) as usize),
+            _ => trace!("ignored write to MBC2 at {:X} => {:X}", addr, val),
+        }
+    }
+
+    fn reset(&mut self) {
+        self.banks.reset();
+    }
+
+    // only the lower nibble exists, the 512 cells repeat across the whole area
+    fn read_ram(&self, addr: u16) -> u8 {
+        0xF0 | self.banks.read_ram(addr)
+    }
+
+    fn write_ram(&mut self, addr: u16, val: u8) {
+        self.banks.write_ram(addr, val & 0x0F);
+    }
+}
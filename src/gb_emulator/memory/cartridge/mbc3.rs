@@ -0,0 +1,105 @@
+use super::mapper::{Banks, Mapper};
+use super::rtc::{Rtc, SystemClock, TimeSource};
+
+pub struct Mbc3 {
+    banks: Banks,
+    rtc: Option<Rtc>,
+    // the clock register mapped instead of RAM, if any
+    rtc_register: Option<u8>,
+}
+
+impl Mbc3 {
+    pub fn new(banks: Banks, has_rtc: bool) -> Mbc3 {
+        Mbc3 {
+            banks,
+            rtc: has_rtc.then(|| Rtc::new(Box::new(SystemClock))),
+            rtc_register: None,
+        }
+    }
+}
+
+impl Mapper for Mbc3 {
+    fn banks(&self) -> &Banks {
+        &self.banks
+    }
+
+    fn banks_mut(&mut self) -> &mut Banks {
+        &mut self.banks
+    }
+
+    fn write_register(&mut self, addr: u16, val: u8) {
+        match addr {
+            0x0000..=0x1FFF => self.banks.ram_enabled = (val & 0xF) == 0xA,
+            0x2000..=0x3FFF => self.banks.select_rom_bank((val & 0x7F).max(1) as usize),
+            // the MBC30 variant has eight RAM banks, 0x08-0x0C select the clock registers
+            0x4000..=0x5FFF => match val {
+                0x00..=0x07 => {
+                    self.rtc_register = None;
+                    self.banks.select_ram_bank(val as usize);
+                }
+                0x08..=0x0C if self.rtc.is_some() => self.rtc_register = Some(val),
+                _ => {}
+            },
+            _ => {
+                if let Some(rtc) = &mut self.rtc {
+                    rtc.write_latch(val);
+                }
+            }
+        }
+    }
+
+    fn reset(&mut self) {
+        self.banks.reset();
+        self.rtc_register = None;
+    }
+
+    fn read_ram(&self, addr: u16) -> u8 {
+        match (&self.rtc, self.rtc_register) {
+            (Some(rtc), Some(register)) if self.banks.ram_enabled => rtc.read_byte(register),
+            _ => self.banks.read_ram(addr),
+        }
+    }
+
+    fn write_ram(&mut self, addr: u16, val: u8) {
+        match (&mut self.rtc, self.rtc_register) {
+            (Some(rtc), Some(register)) => {
+                if self.banks.ram_enabled {
+                    rtc.write_byte(register, val);
+                    self.banks.mark_dirty();
+                }
+            }
+            _ => self.banks.write_ram(addr, val),
+        }
+    }
+
+    // RAM contents followed by the clock state
+    fn save_data(&mut self) -> Vec<u8> {
+        let mut data = self.banks.ram().to_vec();
+        if let Some(rtc) = &mut self.rtc {
+            data.extend_from_slice(&rtc.save_state());
+        }
+        data
+    }
+
+    // a missing clock state is not an error
+    fn load_save_data(&mut self, data: &[u8]) -> bool {
+        if data.len() < self.banks.ram().len() {
+            return false;
+        }
+        let (ram, clock_state) = data.split_at(self.banks.ram().len());
+        let clock_loaded = match &mut self.rtc {
+            Some(rtc) if !clock_state.is_empty() => rtc.load_state(clock_state),
+            _ => clock_state.is_empty(),
+        };
+        if clock_loaded {
+            self.banks.ram_mut().copy_from_slice(ram);
+        }
+        clock_loaded
+    }
+
+    fn set_time_source(&mut self, time_source: Box<dyn TimeSource>) {
+        if let Some(rtc) = &mut self.rtc {
+            rtc.set_time_source(time_source);
+        }
+    }
+}
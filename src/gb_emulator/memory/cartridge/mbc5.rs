@@ -0,0 +1,47 @@
+use log::trace;
+
+use super::mapper::{Banks, Mapper};
+
+pub struct Mbc5 {
+    banks: Banks,
+    // 9 bit ROM bank, written in two parts
+    rom_bank: u16,
+}
+
+impl Mbc5 {
+    pub fn new(banks: Banks) -> Mbc5 {
+        // the bank register powers on as 1, like the bank mapped by Banks
+        Mbc5 { banks, rom_bank: 1 }
+    }
+}
+
+impl Mapper for Mbc5 {
+    fn banks(&self) -> &Banks {
+        &self.banks
+    }
+
+    fn banks_mut(&mut self) -> &mut Banks {
+        &mut self.banks
+    }
+
+    fn write_register(&mut self, addr: u16, val: u8) {
+        match addr {
+            0x0000..=0x1FFF => self.banks.ram_enabled = (val & 0xF) == 0xA,
+            0x2000..=0x2FFF => {
+                self.rom_bank = (self.rom_bank & 0xFF00) | val as u16;
+                self.banks.select_rom_bank(self.rom_bank as usize);
+            }
+            0x3000..=0x3FFF => {
+                self.rom_bank = (self.rom_bank & 0xFF) | ((val & 1) as u16) << 8;
+                self.banks.select_rom_bank(self.rom_bank as usize);
+            }
+            0x4000..=0x5FFF => self.banks.select_ram_bank((val & 0x0F) as usize),
+            _ => trace!("ignored write to MBC5 at {:X} => {:X}", addr, val),
+        }
+    }
+
+    fn reset(&mut self) {
+        self.banks.reset();
+        self.rom_bank = 1;
+    }
+}
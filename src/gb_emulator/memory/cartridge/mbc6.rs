@@ -0,0 +1,186 @@
+use super::mapper::{Banks, Mapper};
+
+// 32 KiB of RAM in eight banks of 4 KiB, the header does not declare it
+pub const MBC6_RAM_SIZE: usize = 0x8000;
+// the 1 MiB flash chip that Net de Get downloads its minigames to
+const FLASH_SIZE: usize = 0x100000;
+const HALF_BANK_SIZE: usize = 0x2000;
+const RAM_HALF_BANK_SIZE: usize = 0x1000;
+
+// MBC6 splits both areas in two halves with their own bank registers, each ROM half maps
+// either ROM or flash, the flash command protocol is not emulated: while enabled for writing
+// the flash is programmed directly, which is enough for the game to keep its downloads
+pub struct Mbc6 {
+    banks: Banks,
+    flash: Vec<u8>,
+    flash_enabled: bool,
+    flash_write_enabled: bool,
+    // 8 KiB bank numbers and whether flash is mapped for 0x4000 and 0x6000, they start out
+    // mapping the ROM that follows the fixed bank
+    rom_banks: [u8; 2],
+    flash_mapped: [bool; 2],
+    // 4 KiB bank numbers for 0xA000 and 0xB000
+    ram_banks: [u8; 2],
+}
+
+impl Mbc6 {
+    pub fn new(banks: Banks) -> Mbc6 {
+        Mbc6 {
+            banks,
+            flash: vec![0xFF; FLASH_SIZE],
+            flash_enabled: false,
+            flash_write_enabled: false,
+            rom_banks: [2, 3],
+            flash_mapped: [false, false],
+            ram_banks: [0, 0],
+        }
+    }
+
+    fn flash_index(&self, half: usize, addr: u16) -> usize {
+        let bank = self.rom_banks[half] as usize % (FLASH_SIZE / HALF_BANK_SIZE);
+        bank * HALF_BANK_SIZE + (addr as usize & 0x1FFF)
+    }
+
+    fn ram_index(&self, addr: u16) -> usize {
+        let half = (addr as usize >> 12) & 1;
+        let bank = self.ram_banks[half] as usize % (MBC6_RAM_SIZE / RAM_HALF_BANK_SIZE);
+        bank * RAM_HALF_BANK_SIZE + (addr as usize & 0x0FFF)
+    }
+}
+
+impl Mapper for Mbc6 {
+    fn banks(&self) -> &Banks {
+        &self.banks
+    }
+
+    fn banks_mut(&mut self) -> &mut Banks {
+        &mut self.banks
+    }
+
+    fn write_register(&mut self, addr: u16, val: u8) {
+        match addr {
+            0x0000..=0x03FF => self.banks.ram_enabled = (val & 0xF) == 0xA,
+            0x0400..=0x07FF => self.ram_banks[0] = val & 0x07,
+            0x0800..=0x0BFF => self.ram_banks[1] = val & 0x07,
+            0x0C00..=0x0FFF => self.flash_enabled = val & 0x01 != 0,
+            0x1000 => self.flash_write_enabled = val & 0x01 != 0,
+            0x2000..=0x27FF => self.rom_banks[0] = val,
+            0x2800..=0x2FFF => self.flash_mapped[0] = val == 0x08,
+            0x3000..=0x37FF => self.rom_banks[1] = val,
+            0x3800..=0x3FFF => self.flash_mapped[1] = val == 0x08,
+            0x4000..=0x7FFF => {
+                let half = (addr as usize >> 13) & 1;
+                if self.flash_mapped[half] && self.flash_enabled && self.flash_write_enabled {
+                    // programming can only clear bits
+                    let index = self.flash_index(half, addr);
+                    self.flash[index] &= val;
+                    self.banks.mark_dirty();
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn reset(&mut self) {
+        self.banks.reset();
+        self.flash_enabled = false;
+        self.flash_write_enabled = false;
+        self.rom_banks = [2, 3];
+        self.flash_mapped = [false, false];
+        self.ram_banks = [0, 0];
+    }
+
+    fn read_rom(&self, addr: u16) -> u8 {
+        match addr {
+            0x0000..=0x3FFF => self.banks.rom()[addr as usize],
+            _ => {
+                let half = (addr as usize >> 13) & 1;
+                if self.flash_mapped[half] {
+                    if !self.flash_enabled {
+                        return 0xFF;
+                    }
+                    self.flash[self.flash_index(half, addr)]
+                } else {
+                    let offset = self
+                        .banks
+                        .rom_offset(self.rom_banks[half] as usize, HALF_BANK_SIZE);
+                    self.banks.rom()[offset + (addr as usize & 0x1FFF)]
+                }
+            }
+        }
+    }
+
+    fn read_ram(&self, addr: u16) -> u8 {
+        if !self.banks.ram_enabled {
+            return 0xFF;
+        }
+        self.banks.ram()[self.ram_index(addr)]
+    }
+
+    fn write_ram(&mut self, addr: u16, val: u8) {
+        if self.banks.ram_enabled {
+            let index = self.ram_index(addr);
+            self.banks.ram_mut()[index] = val;
+            self.banks.mark_dirty();
+        }
+    }
+
+    // RAM contents followed by the flash
+    fn save_data(&mut self) -> Vec<u8> {
+        let mut data = self.banks.ram().to_vec();
+        data.extend_from_slice(&self.flash);
+        data
+    }
+
+    // saves of other emulators may only contain the RAM
+    fn load_save_data(&mut self, data: &[u8]) -> bool {
+        let (ram, flash) = match data.len() {
+            MBC6_RAM_SIZE => (data, None),
+            len if len == MBC6_RAM_SIZE + FLASH_SIZE => {
+                let (ram, flash) = data.split_at(MBC6_RAM_SIZE);
+                (ram, Some(flash))
+            }
+            _ => return false,
+        };
+        self.banks.ram_mut().copy_from_slice(ram);
+        if let Some(flash) = flash {
+            self.flash.copy_from_slice(flash);
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::tests::rom_with_ram;
+    use super::super::Cartridge;
+
+    #[test]
+    fn test_split_banks() {
+        let mut rom = rom_with_ram(0x20, 8, 0x00);
+        rom[0x2000 * 5] = 0x55;
+        let mut cartridge = Cartridge::new(rom).unwrap();
+        assert_eq!(cartridge.read_byte(0x4000), 1);
+        cartridge.write_byte(0x3000, 5);
+        assert_eq!(cartridge.read_byte(0x6000), 0x55);
+        assert_eq!(cartridge.read_byte(0x4000), 1);
+
+        cartridge.write_byte(0x0000, 0x0A);
+        cartridge.write_byte(0x0400, 1);
+        cartridge.write_byte(0x0800, 2);
+        cartridge.write_byte(0xA000, 0x11);
+        cartridge.write_byte(0xB000, 0x22);
+        cartridge.write_byte(0x0400, 2);
+        assert_eq!(cartridge.read_byte(0xA000), 0x22);
+
+        // erased flash reads 0xFF and programming clears bits
+        cartridge.write_byte(0x0C00, 1);
+        cartridge.write_byte(0x1000, 1);
+        cartridge.write_byte(0x2800, 0x08);
+        assert_eq!(cartridge.read_byte(0x4000), 0xFF);
+        cartridge.write_byte(0x4000, 0x3C);
+        assert_eq!(cartridge.read_byte(0x4000), 0x3C);
+        cartridge.write_byte(0x2800, 0x00);
+        assert_eq!(cartridge.read_byte(0x4000), 1);
+    }
+}
@@ -0,0 +1,387 @@
+use super::mapper::{Banks, Mapper};
+
+// 93LC56 EEPROM organized as 128 words of 16 bits
+const EEPROM_WORDS: usize = 128;
+pub const EEPROM_SIZE: usize = EEPROM_WORDS * 2;
+// accelerometer reading at rest and the change per g of tilt
+const ACCEL_CENTER: f32 = 0x81D0 as f32;
+const ACCEL_PER_G: f32 = 0x70 as f32;
+// value of the latched readings after an erase
+const ACCEL_ERASED: u16 = 0x8000;
+// start bit, two opcode bits and eight address bits
+const COMMAND_BITS: u8 = 11;
+
+enum EepromState {
+    // shifting in the start bit, opcode and address
+    Command,
+    // shifting out the selected word, continuing with the next one
+    Reading { addr: usize, bit: u8 },
+    // shifting in the word for one cell or for all of them
+    Writing { addr: Option<usize>, bit: u8 },
+    // done until chip select drops
+    Finished,
+}
+
+// serial EEPROM driven by bit banging, bit 7 is chip select, bit 6 clock, bit 1 data in
+// and bit 0 data out, input bits are sampled on the rising clock edge
+struct Eeprom {
+    words: [u16; EEPROM_WORDS],
+    select: bool,
+    clock: bool,
+    data_in: bool,
+    data_out: bool,
+    write_enabled: bool,
+    state: EepromState,
+    // bits shifted in for the current command or word
+    shift: u16,
+    shift_len: u8,
+}
+
+impl Eeprom {
+    fn new() -> Eeprom {
+        Eeprom {
+            words: [0xFFFF; EEPROM_WORDS],
+            select: false,
+            clock: false,
+            data_in: false,
+            data_out: true,
+            write_enabled: false,
+            state: EepromState::Command,
+            shift: 0,
+            shift_len: 0,
+        }
+    }
+
+    fn read(&self) -> u8 {
+        (self.select as u8) << 7
+            | (self.clock as u8) << 6
+            | (self.data_in as u8) << 1
+            | self.data_out as u8
+    }
+
+    // returns true if a cell was changed
+    fn write(&mut self, val: u8) -> bool {
+        let select = val & 0x80 != 0;
+        let clock = val & 0x40 != 0;
+        self.data_in = val & 0x02 != 0;
+        let rising_edge = clock && !self.clock;
+        self.clock = clock;
+
+        if !select {
+            // deselecting aborts the command, the chip reports ready
+            self.select = false;
+            self.state = EepromState::Command;
+            self.shift = 0;
+            self.shift_len = 0;
+            self.data_out = true;
+            return false;
+        }
+        self.select = true;
+        if rising_edge {
+            self.clock_bit()
+        } else {
+            false
+        }
+    }
+
+    fn clock_bit(&mut self) -> bool {
+        match self.state {
+            EepromState::Command => {
+                // leading zeros before the start bit are ignored
+                if self.shift_len == 0 && !self.data_in {
+                    return false;
+                }
+                self.shift = self.shift << 1 | self.data_in as u16;
+                self.shift_len += 1;
+                if self.shift_len == COMMAND_BITS {
+                    return self.execute();
+                }
+                false
+            }
+            EepromState::Reading { addr, bit } => {
+                self.data_out = self.words[addr] & (0x8000 >> bit) != 0;
+                self.state = if bit == 15 {
+                    EepromState::Reading {
+                        addr: (addr + 1) % EEPROM_WORDS,
+                        bit: 0,
+                    }
+                } else {
+                    EepromState::Reading { addr, bit: bit + 1 }
+                };
+                false
+            }
+            EepromState::Writing { addr, bit } => {
+                self.shift = self.shift << 1 | self.data_in as u16;
+                if bit < 15 {
+                    self.state = EepromState::Writing { addr, bit: bit + 1 };
+                    return false;
+                }
+                self.state = EepromState::Finished;
+                self.data_out = true;
+                if !self.write_enabled {
+                    return false;
+                }
+                match addr {
+                    Some(addr) => self.words[addr] = self.shift,
+                    None => self.words = [self.shift; EEPROM_WORDS],
+                }
+                true
+            }
+            EepromState::Finished => false,
+        }
+    }
+
+    fn execute(&mut self) -> bool {
+        let opcode = (self.shift >> 8) & 0x03;
+        let addr = self.shift as usize & (EEPROM_WORDS - 1);
+        let extended = (self.shift >> 6) & 0x03;
+        self.shift = 0;
+        self.state = EepromState::Finished;
+        match (opcode, extended) {
+            // READ starts with a dummy zero bit
+            (0b10, _) => {
+                self.data_out = false;
+                self.state = EepromState::Reading { addr, bit: 0 };
+                false
+            }
+            // WRITE
+            (0b01, _) => {
+                self.state = EepromState::Writing {
+                    addr: Some(addr),
+                    bit: 0,
+                };
+                false
+            }
+            // ERASE
+            (0b11, _) => {
+                self.data_out = true;
+                if self.write_enabled {
+                    self.words[addr] = 0xFFFF;
+                }
+                self.write_enabled
+            }
+            // EWDS
+            (0b00, 0b00) => {
+                self.write_enabled = false;
+                false
+            }
+            // WRAL
+            (0b00, 0b01) => {
+                self.state = EepromState::Writing { addr: None, bit: 0 };
+                false
+            }
+            // ERAL
+            (0b00, 0b10) => {
+                self.data_out = true;
+                if self.write_enabled {
+                    self.words = [0xFFFF; EEPROM_WORDS];
+                }
+                self.write_enabled
+            }
+            // EWEN
+            _ => {
+                self.write_enabled = true;
+                false
+            }
+        }
+    }
+}
+
+// MBC7 of Kirby Tilt 'n' Tumble, the accelerometer and EEPROM replace the RAM at 0xA000-0xAFFF
+// and are only accessible with both enable registers set
+pub struct Mbc7 {
+    banks: Banks,
+    ram_enabled2: bool,
+    // latest tilt from the host and the readings latched by the game
+    tilt: (f32, f32),
+    accel_x: u16,
+    accel_y: u16,
+    // an erase arms the latch, reading the accelerometer only works once per erase
+    latch_armed: bool,
+    eeprom: Eeprom,
+}
+
+impl Mbc7 {
+    pub fn new(banks: Banks) -> Mbc7 {
+        Mbc7 {
+            banks,
+            ram_enabled2: false,
+            tilt: (0.0, 0.0),
+            accel_x: ACCEL_ERASED,
+            accel_y: ACCEL_ERASED,
+            latch_armed: false,
+            eeprom: Eeprom::new(),
+        }
+    }
+
+    fn accessible(&self) -> bool {
+        self.banks.ram_enabled && self.ram_enabled2
+    }
+}
+
+impl Mapper for Mbc7 {
+    fn banks(&self) -> &Banks {
+        &self.banks
+    }
+
+    fn banks_mut(&mut self) -> &mut Banks {
+        &mut self.banks
+    }
+
+    fn write_register(&mut self, addr: u16, val: u8) {
+        match addr {
+            0x0000..=0x1FFF => self.banks.ram_enabled = (val & 0xF) == 0xA,
+            0x2000..=0x3FFF => self.banks.select_rom_bank((val & 0x7F) as usize),
+            0x4000..=0x5FFF => self.ram_enabled2 = val == 0x40,
+            _ => {}
+        }
+    }
+
+    fn reset(&mut self) {
+        self.banks.reset();
+        self.ram_enabled2 = false;
+        self.accel_x = ACCEL_ERASED;
+        self.accel_y = ACCEL_ERASED;
+        self.latch_armed = false;
+    }
+
+    fn read_ram(&self, addr: u16) -> u8 {
+        if !self.accessible() || addr >= 0xB000 {
+            return 0xFF;
+        }
+        match (addr >> 4) & 0x0F {
+            0x2 => self.accel_x as u8,
+            0x3 => (self.accel_x >> 8) as u8,
+            0x4 => self.accel_y as u8,
+            0x5 => (self.accel_y >> 8) as u8,
+            0x6 => 0x00,
+            0x8 => self.eeprom.read(),
+            _ => 0xFF,
+        }
+    }
+
+    fn write_ram(&mut self, addr: u16, val: u8) {
+        if !self.accessible() || addr >= 0xB000 {
+            return;
+        }
+        match (addr >> 4) & 0x0F {
+            0x0 if val == 0x55 => {
+                self.accel_x = ACCEL_ERASED;
+                self.accel_y = ACCEL_ERASED;
+                self.latch_armed = true;
+            }
+            0x1 if val == 0xAA && self.latch_armed => {
+                let (x, y) = self.tilt;
+                self.accel_x = (ACCEL_CENTER + x * ACCEL_PER_G) as u16;
+                self.accel_y = (ACCEL_CENTER + y * ACCEL_PER_G) as u16;
+                self.latch_armed = false;
+            }
+            0x8 => {
+                let changed = self.eeprom.write(val);
+                if changed {
+                    self.banks.mark_dirty();
+                }
+            }
+            _ => {}
+        }
+    }
+
+    // the EEPROM words in little endian
+    fn save_data(&mut self) -> Vec<u8> {
+        self.eeprom
+            .words
+            .iter()
+            .flat_map(|word| word.to_le_bytes())
+            .collect()
+    }
+
+    fn load_save_data(&mut self, data: &[u8]) -> bool {
+        if data.len() != EEPROM_SIZE {
+            return false;
+        }
+        for (word, bytes) in self.eeprom.words.iter_mut().zip(data.chunks(2)) {
+            *word = u16::from_le_bytes([bytes[0], bytes[1]]);
+        }
+        true
+    }
+
+    fn set_tilt(&mut self, x: f32, y: f32) {
+        self.tilt = (x.clamp(-4.0, 4.0), y.clamp(-4.0, 4.0));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::tests::rom_with_ram;
+    use super::super::Cartridge;
+    use super::*;
+
+    fn enabled_cartridge() -> Cartridge {
+        let mut cartridge = Cartridge::new(rom_with_ram(0x22, 8, 0x00)).unwrap();
+        cartridge.write_byte(0x0000, 0x0A);
+        cartridge.write_byte(0x4000, 0x40);
+        cartridge
+    }
+
+    // shift bits into the EEPROM MSB first, returning the data out bits seen after each clock
+    fn shift_bits(cartridge: &mut Cartridge, bits: u32, count: u32) -> u32 {
+        let mut out = 0;
+        for bit in (0..count).rev() {
+            let data_in = (((bits >> bit) & 1) << 1) as u8;
+            cartridge.write_byte(0xA080, 0x80 | data_in);
+            cartridge.write_byte(0xA080, 0xC0 | data_in);
+            out = out << 1 | (cartridge.read_byte(0xA080) & 0x01) as u32;
+        }
+        out
+    }
+
+    #[test]
+    fn test_accelerometer() {
+        let mut cartridge = enabled_cartridge();
+        cartridge.set_tilt(1.0, -0.5);
+        cartridge.write_byte(0xA000, 0x55);
+        assert_eq!(cartridge.read_byte(0xA020), 0x00);
+        assert_eq!(cartridge.read_byte(0xA030), 0x80);
+        cartridge.write_byte(0xA010, 0xAA);
+        let x = u16::from_le_bytes([cartridge.read_byte(0xA020), cartridge.read_byte(0xA030)]);
+        let y = u16::from_le_bytes([cartridge.read_byte(0xA040), cartridge.read_byte(0xA050)]);
+        assert_eq!(x, 0x81D0 + 0x70);
+        assert_eq!(y, 0x81D0 - 0x38);
+
+        // the reading stays latched until the next erase
+        cartridge.set_tilt(0.0, 0.0);
+        cartridge.write_byte(0xA010, 0xAA);
+        assert_eq!(cartridge.read_byte(0xA020), 0x40);
+
+        // the second enable register gates the whole area
+        cartridge.write_byte(0x4000, 0x00);
+        assert_eq!(cartridge.read_byte(0xA020), 0xFF);
+    }
+
+    #[test]
+    fn test_eeprom() {
+        let mut cartridge = enabled_cartridge();
+        // writes are ignored until EWEN
+        shift_bits(&mut cartridge, 0b101_0000_0011, 11);
+        shift_bits(&mut cartridge, 0xBEEF, 16);
+        cartridge.write_byte(0xA080, 0x00);
+        assert!(!cartridge.take_ram_dirty());
+
+        shift_bits(&mut cartridge, 0b100_1100_0000, 11);
+        cartridge.write_byte(0xA080, 0x00);
+        shift_bits(&mut cartridge, 0b101_0000_0011, 11);
+        shift_bits(&mut cartridge, 0xBEEF, 16);
+        cartridge.write_byte(0xA080, 0x00);
+        assert!(cartridge.take_ram_dirty());
+
+        shift_bits(&mut cartridge, 0b110_0000_0011, 11);
+        assert_eq!(shift_bits(&mut cartridge, 0, 16), 0xBEEF);
+        // sequential reads continue with the next word
+        assert_eq!(shift_bits(&mut cartridge, 0, 16), 0xFFFF);
+        cartridge.write_byte(0xA080, 0x00);
+
+        let data = cartridge.save_data().unwrap();
+        assert_eq!(data.len(), EEPROM_SIZE);
+        assert_eq!(data[6..8], [0xEF, 0xBE]);
+    }
+}
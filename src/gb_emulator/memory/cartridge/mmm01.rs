@@ -0,0 +1,147 @@
+use super::mapper::{Banks, Mapper};
+
+// MMM01 multicarts start with the menu in the last 32 KiB of the ROM mapped, the menu sets up
+// the registers for a game and then maps it, which also locks the outer bank bits
+pub struct Mmm01 {
+    banks: Banks,
+    mapped: bool,
+    // bits 0-4, 5-6 and 7-8 of the ROM bank, the mask selects which bits of the lower part
+    // are fixed to the game once mapped
+    rom_bank_low: u8,
+    rom_bank_mid: u8,
+    rom_bank_high: u8,
+    rom_bank_mask: u8,
+    ram_bank_low: u8,
+    ram_bank_high: u8,
+}
+
+impl Mmm01 {
+    pub fn new(banks: Banks) -> Mmm01 {
+        let mut mmm01 = Mmm01 {
+            banks,
+            mapped: false,
+            rom_bank_low: 0,
+            rom_bank_mid: 0,
+            rom_bank_high: 0,
+            rom_bank_mask: 0,
+            ram_bank_low: 0,
+            ram_bank_high: 0,
+        };
+        mmm01.update_banks();
+        mmm01
+    }
+
+    fn update_banks(&mut self) {
+        if !self.mapped {
+            // all bank bits read as set, mapping the last two banks
+            self.banks.select_rom0_bank(0x1FE);
+            self.banks.select_rom_bank(0x1FF);
+            self.banks.select_ram_bank(0);
+            return;
+        }
+        let fixed = self.rom_bank_mask << 1;
+        let outer = (self.rom_bank_high as usize) << 7 | (self.rom_bank_mid as usize) << 5;
+        let mut low = self.rom_bank_low;
+        // the zero translation only looks at the bits the game controls
+        if low & !fixed & 0x1F == 0 {
+            low |= 1;
+        }
+        self.banks
+            .select_rom0_bank(outer | (self.rom_bank_low & fixed) as usize);
+        self.banks.select_rom_bank(outer | low as usize);
+        self.banks
+            .select_ram_bank((self.ram_bank_high << 2 | self.ram_bank_low) as usize);
+    }
+}
+
+impl Mapper for Mmm01 {
+    fn banks(&self) -> &Banks {
+        &self.banks
+    }
+
+    fn banks_mut(&mut self) -> &mut Banks {
+        &mut self.banks
+    }
+
+    fn write_register(&mut self, addr: u16, val: u8) {
+        match addr {
+            0x0000..=0x1FFF => {
+                self.banks.ram_enabled = (val & 0xF) == 0xA;
+                if !self.mapped {
+                    self.mapped = val & 0x40 != 0;
+                }
+            }
+            0x2000..=0x3FFF => {
+                let fixed = if self.mapped {
+                    self.rom_bank_mask << 1
+                } else {
+                    self.rom_bank_mid = (val >> 5) & 0x03;
+                    0
+                };
+                self.rom_bank_low = (self.rom_bank_low & fixed) | (val & 0x1F & !fixed);
+            }
+            0x4000..=0x5FFF => {
+                self.ram_bank_low = val & 0x03;
+                if !self.mapped {
+                    self.ram_bank_high = (val >> 2) & 0x03;
+                    self.rom_bank_high = (val >> 4) & 0x03;
+                }
+            }
+            _ => {
+                if !self.mapped {
+                    self.rom_bank_mask = (val >> 2) & 0x0F;
+                }
+            }
+        }
+        self.update_banks();
+    }
+
+    // the reset line also returns to the menu
+    fn reset(&mut self) {
+        self.banks.reset();
+        self.mapped = false;
+        self.rom_bank_low = 0;
+        self.rom_bank_mid = 0;
+        self.rom_bank_high = 0;
+        self.rom_bank_mask = 0;
+        self.ram_bank_low = 0;
+        self.ram_bank_high = 0;
+        self.update_banks();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::header::NINTENDO_LOGO;
+    use super::super::tests::rom_with_ram;
+    use super::super::Cartridge;
+
+    #[test]
+    fn test_menu_and_game() {
+        // 64 banks with the menu header in the last 32 KiB
+        let mut rom = rom_with_ram(0x01, 64, 0x00);
+        let menu = rom.len() - 0x8000;
+        rom[menu + 0x0104..menu + 0x0134].copy_from_slice(&NINTENDO_LOGO);
+        rom[menu + 0x0147] = 0x0B;
+        let mut cartridge = Cartridge::new(rom).unwrap();
+        assert_eq!(cartridge.read_byte(0x0000), 62);
+        assert_eq!(cartridge.read_byte(0x4000), 63);
+
+        // the menu picks the game at bank 0x20 with 16 banks and maps it
+        cartridge.write_byte(0x2000, 0x20);
+        cartridge.write_byte(0x6000, 0x3C);
+        cartridge.write_byte(0x0000, 0x40);
+        assert_eq!(cartridge.read_byte(0x0000), 0x20);
+        assert_eq!(cartridge.read_byte(0x4000), 0x21);
+
+        // the game only controls the bits outside the mask
+        cartridge.write_byte(0x2000, 0x03);
+        assert_eq!(cartridge.read_byte(0x4000), 0x21);
+        cartridge.write_byte(0x6000, 0x00);
+        cartridge.write_byte(0x2000, 0x01);
+        assert_eq!(cartridge.read_byte(0x4000), 0x21);
+
+        cartridge.reset();
+        assert_eq!(cartridge.read_byte(0x0000), 62);
+    }
+}
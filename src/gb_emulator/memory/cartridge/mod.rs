@@ -0,0 +1,560 @@
+mod camera;
+mod header;
+mod huc1;
+mod huc3;
+mod mapper;
+mod mbc1;
+mod mbc2;
+mod mbc3;
+mod mbc5;
+mod mbc6;
+mod mbc7;
+mod mmm01;
+mod rom_only;
+mod rtc;
+mod tama5;
+
+use std::error::Error;
+use std::fmt;
+
+use log::{trace, warn};
+
+use camera::PocketCamera;
+pub use camera::{CAMERA_HEIGHT, CAMERA_WIDTH};
+use header::NINTENDO_LOGO;
+pub use header::{CartridgeHeader, CgbSupport, Destination, Licensee};
+use huc1::Huc1;
+use huc3::Huc3;
+use mapper::{Banks, Mapper};
+use mbc1::Mbc1;
+use mbc2::{Mbc2, MBC2_RAM_SIZE};
+use mbc3::Mbc3;
+use mbc5::Mbc5;
+use mbc6::{Mbc6, MBC6_RAM_SIZE};
+use mbc7::Mbc7;
+use mmm01::Mmm01;
+use rom_only::RomOnly;
+pub use rtc::TimeSource;
+use tama5::{Tama5, TAMA5_RAM_SIZE};
+
+// the Game Boy Camera always has 128 KiB of RAM
+const CAMERA_RAM_SIZE: usize = 0x20000;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CartridgeError {
+    // the image is too small to contain a cartridge header
+    Truncated { size: usize },
+    // the header declares a ROM size that does not exist
+    UnknownRomSize { code: u8 },
+    // the header declares a RAM size that does not exist
+    UnknownRamSize { code: u8 },
+    // the image is smaller than the ROM size in the header
+    RomSizeMismatch { declared: usize, actual: usize },
+    // the boot ROM would lock up on these
+    InvalidLogo,
+    HeaderChecksum { expected: u8, actual: u8 },
+    GlobalChecksum { expected: u16, actual: u16 },
+    // a save file that does not fit the RAM of the cartridge
+    InvalidSave { size: usize },
+}
+
+impl fmt::Display for CartridgeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CartridgeError::Truncated { size } => {
+                write!(
+                    f,
+                    "cartridge image of {} bytes has no complete header",
+                    size
+                )
+            }
+            CartridgeError::UnknownRomSize { code } => {
+                write!(f, "unknown ROM size code {:02X} in the header", code)
+            }
+            CartridgeError::UnknownRamSize { code } => {
+                write!(f, "unknown RAM size code {:02X} in the header", code)
+            }
+            CartridgeError::RomSizeMismatch { declared, actual } => write!(
+                f,
+                "header declares {} bytes of ROM but the image has only {}",
+                declared, actual
+            ),
+            CartridgeError::InvalidLogo => write!(f, "Nintendo logo in the header is corrupted"),
+            CartridgeError::HeaderChecksum { expected, actual } => write!(
+                f,
+                "header checksum is {:02X} but the header sums up to {:02X}",
+                expected, actual
+            ),
+            CartridgeError::GlobalChecksum { expected, actual } => write!(
+                f,
+                "global checksum is {:04X} but the ROM sums up to {:04X}",
+                expected, actual
+            ),
+            CartridgeError::InvalidSave { size } => {
+                write!(
+                    f,
+                    "save file of {} bytes does not match the cartridge RAM",
+                    size
+                )
+            }
+        }
+    }
+}
+
+impl Error for CartridgeError {}
+
+pub struct Cartridge {
+    header: CartridgeHeader,
+    mapper: Box<dyn Mapper>,
+}
+
+impl Cartridge {
+    pub fn new(cartridge_buffer: Vec<u8>) -> Result<Cartridge, CartridgeError> {
+        let header = CartridgeHeader::parse(&cartridge_buffer)?;
+        // homebrew and test ROMs often skip the checksums, so these only warn
+        if let Err(err) = header.verify(&cartridge_buffer) {
+            warn!("{}", err);
+        }
+
+        // MMM01 multicarts boot the menu in the last banks, which carries the real header
+        let cartridge_type = if is_mmm01(&cartridge_buffer) {
+            0x0B
+        } else {
+            header.cartridge_type
+        };
+        let ram_size = header.ram_size;
+        let mapper: Box<dyn Mapper> = match cartridge_type {
+            0x01..=0x03 => Box::new(Mbc1::new(Banks::new(cartridge_buffer, ram_size))),
+            0x05 | 0x06 => Box::new(Mbc2::new(Banks::new(cartridge_buffer, MBC2_RAM_SIZE))),
+            0x0B..=0x0D => Box::new(Mmm01::new(Banks::new(cartridge_buffer, ram_size))),
+            0x0F..=0x13 => {
+                let has_rtc = matches!(cartridge_type, 0x0F | 0x10);
+                Box::new(Mbc3::new(Banks::new(cartridge_buffer, ram_size), has_rtc))
+            }
+            0x19..=0x1E => Box::new(Mbc5::new(Banks::new(cartridge_buffer, ram_size))),
+            0x20 => Box::new(Mbc6::new(Banks::new(cartridge_buffer, MBC6_RAM_SIZE))),
+            0x22 => Box::new(Mbc7::new(Banks::new(cartridge_buffer, 0))),
+            0xFC => {
+                let ram_size = ram_size.max(CAMERA_RAM_SIZE);
+                Box::new(PocketCamera::new(Banks::new(cartridge_buffer, ram_size)))
+            }
+            0xFD => Box::new(Tama5::new(Banks::new(cartridge_buffer, TAMA5_RAM_SIZE))),
+            0xFE => Box::new(Huc3::new(Banks::new(cartridge_buffer, ram_size))),
+            0xFF => Box::new(Huc1::new(Banks::new(cartridge_buffer, ram_size))),
+            _ => Box::new(RomOnly::new(Banks::new(cartridge_buffer, ram_size))),
+        };
+
+        Ok(Cartridge { header, mapper })
+    }
+
+    pub fn header(&self) -> &CartridgeHeader {
+        &self.header
+    }
+
+    // restore the power-on banking state, RAM contents are kept
+    pub fn reset(&mut self) {
+        self.mapper.reset();
+    }
+
+    pub fn read_byte(&self, addr: u16) -> u8 {
+        match addr {
+            0x0000..=0x7FFF => self.mapper.read_rom(addr),
+            0xA000..=0xBFFF => self.mapper.read_ram(addr),
+            // nothing drives the bus outside of the cartridge areas
+            _ => {
+                trace!("read from cartridge outside of its areas: {:X}", addr);
+                0xFF
+            }
+        }
+    }
+
+    pub fn write_byte(&mut self, addr: u16, val: u8) {
+        match addr {
+            0x0000..=0x7FFF => self.mapper.write_register(addr, val),
+            0xA000..=0xBFFF => self.mapper.write_ram(addr, val),
+            _ => trace!(
+                "ignored write to cartridge outside of its areas: {:X} => {:X}",
+                addr,
+                val
+            ),
+        }
+    }
+
+    // RAM contents followed by the state of extra hardware, None for cartridges without battery
+    pub fn save_data(&mut self) -> Option<Vec<u8>> {
+        if !self.header.has_battery() {
+            return None;
+        }
+        Some(self.mapper.save_data())
+    }
+
+    pub fn load_save_data(&mut self, data: &[u8]) -> Result<(), CartridgeError> {
+        if !self.mapper.load_save_data(data) {
+            return Err(CartridgeError::InvalidSave { size: data.len() });
+        }
+        Ok(())
+    }
+
+    // true if the battery backed state changed since the last call
+    pub fn take_ram_dirty(&mut self) -> bool {
+        self.mapper.take_save_dirty()
+    }
+
+    // replace the wall clock driving the real time clock of MBC3 and HuC3 cartridges
+    pub fn set_time_source(&mut self, time_source: Box<dyn TimeSource>) {
+        self.mapper.set_time_source(time_source);
+    }
+
+    pub fn set_tilt(&mut self, x: f32, y: f32) {
+        self.mapper.set_tilt(x, y);
+    }
+
+    pub fn set_ir_input(&mut self, light: bool) {
+        self.mapper.set_ir_input(light);
+    }
+
+    pub fn ir_output(&self) -> bool {
+        self.mapper.ir_output()
+    }
+
+    pub fn set_camera_image(&mut self, pixels: &[u8]) {
+        self.mapper.set_camera_image(pixels);
+    }
+}
+
+// the menu of MMM01 multicarts sits in the last 32 KiB, the header at the start belongs to a game
+fn is_mmm01(rom: &[u8]) -> bool {
+    let menu = rom.len() - 0x8000;
+    matches!(rom[menu + 0x0147], 0x0B..=0x0D) && rom[menu + 0x0104..menu + 0x0134] == NINTENDO_LOGO
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::mapper::{RAM_BANK_SIZE, ROM_BANK_SIZE};
+    use super::rtc::tests::FakeClock;
+    use super::rtc::RTC_STATE_SIZE;
+    use super::*;
+
+    // MBC1 ROM with RAM whose banks start with their bank number
+    fn mbc1_rom(banks: usize) -> Vec<u8> {
+        let mut rom = vec![0; banks * ROM_BANK_SIZE];
+        for bank in 0..banks {
+            rom[bank * ROM_BANK_SIZE] = bank as u8;
+        }
+        rom[0x0147] = 0x03;
+        rom[0x0149] = 0x03;
+        rom
+    }
+
+    pub fn rom_with_ram(cartridge_type: u8, rom_banks: usize, ram_code: u8) -> Vec<u8> {
+        let mut rom = vec![0; rom_banks * ROM_BANK_SIZE];
+        for bank in 0..rom_banks {
+            rom[bank * ROM_BANK_SIZE] = bank as u8;
+        }
+        rom[0x0147] = cartridge_type;
+        rom[0x0149] = ram_code;
+        rom
+    }
+
+    #[test]
+    fn test_mbc1_rom_banks() {
+        let mut cartridge = Cartridge::new(mbc1_rom(128)).unwrap();
+        assert_eq!(cartridge.read_byte(0x4000), 1);
+
+        cartridge.write_byte(0x2000, 0x05);
+        assert_eq!(cartridge.read_byte(0x4000), 5);
+        // bank 0 is translated to bank 1
+        cartridge.write_byte(0x2000, 0x00);
+        assert_eq!(cartridge.read_byte(0x4000), 1);
+        // only the lower 5 bits are used
+        cartridge.write_byte(0x2000, 0xE3);
+        assert_eq!(cartridge.read_byte(0x4000), 3);
+
+        cartridge.write_byte(0x4000, 0x02);
+        assert_eq!(cartridge.read_byte(0x4000), 0x43);
+        // the translation only looks at bank1, so bank 0x40 maps to 0x41
+        cartridge.write_byte(0x2000, 0x00);
+        assert_eq!(cartridge.read_byte(0x4000), 0x41);
+        assert_eq!(cartridge.read_byte(0x0000), 0);
+    }
+
+    #[test]
+    fn test_mbc1_advanced_mode() {
+        let mut cartridge = Cartridge::new(mbc1_rom(128)).unwrap();
+        cartridge.write_byte(0x4000, 0x01);
+        cartridge.write_byte(0x6000, 0x01);
+        assert_eq!(cartridge.read_byte(0x0000), 0x20);
+        assert_eq!(cartridge.read_byte(0x4000), 0x21);
+
+        cartridge.write_byte(0x6000, 0x00);
+        assert_eq!(cartridge.read_byte(0x0000), 0);
+    }
+
+    #[test]
+    fn test_mbc1_bank_wrapping() {
+        let mut cartridge = Cartridge::new(mbc1_rom(8)).unwrap();
+        cartridge.write_byte(0x2000, 0x0A);
+        assert_eq!(cartridge.read_byte(0x4000), 2);
+        cartridge.write_byte(0x4000, 0x03);
+        cartridge.write_byte(0x6000, 0x01);
+        assert_eq!(cartridge.read_byte(0x0000), 0);
+    }
+
+    #[test]
+    fn test_mbc1_ram() {
+        let mut cartridge = Cartridge::new(mbc1_rom(4)).unwrap();
+        // disabled RAM ignores writes and reads as 0xFF
+        cartridge.write_byte(0xA000, 0x11);
+        assert_eq!(cartridge.read_byte(0xA000), 0xFF);
+
+        cartridge.write_byte(0x0000, 0x0A);
+        cartridge.write_byte(0xA000, 0x11);
+        assert_eq!(cartridge.read_byte(0xA000), 0x11);
+
+        // RAM banks are only switched in advanced mode
+        cartridge.write_byte(0x4000, 0x02);
+        assert_eq!(cartridge.read_byte(0xA000), 0x11);
+        cartridge.write_byte(0x6000, 0x01);
+        assert_eq!(cartridge.read_byte(0xA000), 0x00);
+        cartridge.write_byte(0xBFFF, 0x22);
+        cartridge.write_byte(0x4000, 0x00);
+        assert_eq!(cartridge.read_byte(0xA000), 0x11);
+        assert_eq!(cartridge.read_byte(0xBFFF), 0x00);
+
+        cartridge.write_byte(0x0000, 0x00);
+        assert_eq!(cartridge.read_byte(0xA000), 0xFF);
+    }
+
+    #[test]
+    fn test_mbc1_multicart() {
+        let mut rom = mbc1_rom(64);
+        let header = 0x10 * ROM_BANK_SIZE + 0x0104;
+        rom[header..header + NINTENDO_LOGO.len()].copy_from_slice(&NINTENDO_LOGO);
+        let mut cartridge = Cartridge::new(rom).unwrap();
+
+        // bank2 selects one of four 256 KiB games
+        cartridge.write_byte(0x4000, 0x01);
+        cartridge.write_byte(0x2000, 0x12);
+        assert_eq!(cartridge.read_byte(0x4000), 0x12);
+        cartridge.write_byte(0x6000, 0x01);
+        assert_eq!(cartridge.read_byte(0x0000), 0x10);
+
+        // without the second logo it is a regular MBC1 cartridge
+        let mut cartridge = Cartridge::new(mbc1_rom(64)).unwrap();
+        cartridge.write_byte(0x4000, 0x01);
+        cartridge.write_byte(0x2000, 0x12);
+        assert_eq!(cartridge.read_byte(0x4000), 0x32);
+    }
+
+    #[test]
+    fn test_ram_size() {
+        let mut cartridge = Cartridge::new(rom_with_ram(0x1B, 2, 0x04)).unwrap();
+        assert_eq!(cartridge.save_data().unwrap().len(), 0x20000);
+
+        // a cartridge without RAM reads open bus even when enabled
+        let mut cartridge = Cartridge::new(rom_with_ram(0x1B, 2, 0x00)).unwrap();
+        cartridge.write_byte(0x0000, 0x0A);
+        cartridge.write_byte(0xA000, 0x42);
+        assert_eq!(cartridge.read_byte(0xA000), 0xFF);
+    }
+
+    #[test]
+    fn test_small_ram_mirroring() {
+        let mut cartridge = Cartridge::new(rom_with_ram(0x03, 2, 0x01)).unwrap();
+        cartridge.write_byte(0x0000, 0x0A);
+        cartridge.write_byte(0xA000, 0x42);
+        assert_eq!(cartridge.read_byte(0xA800), 0x42);
+    }
+
+    #[test]
+    fn test_open_bus() {
+        let mut cartridge = Cartridge::new(rom_with_ram(0x00, 2, 0x00)).unwrap();
+        // Tetris selects bank 1 although it has no MBC
+        cartridge.write_byte(0x2000, 0x01);
+        cartridge.write_byte(0x0000, 0x0A);
+        assert_eq!(cartridge.read_byte(0x4000), 1);
+        assert_eq!(cartridge.read_byte(0xA000), 0xFF);
+        assert_eq!(cartridge.read_byte(0xC000), 0xFF);
+        cartridge.write_byte(0xC000, 0x42);
+
+        let mut cartridge = Cartridge::new(rom_with_ram(0x19, 4, 0x00)).unwrap();
+        cartridge.write_byte(0x6000, 0x01);
+        cartridge.write_byte(0x2000, 0x03);
+        assert_eq!(cartridge.read_byte(0x4000), 3);
+
+        let mut cartridge = Cartridge::new(rom_with_ram(0x05, 4, 0x00)).unwrap();
+        cartridge.write_byte(0x4100, 0x02);
+        assert_eq!(cartridge.read_byte(0x4000), 1);
+    }
+
+    #[test]
+    fn test_rom_only_ram() {
+        let mut cartridge = Cartridge::new(rom_with_ram(0x09, 2, 0x02)).unwrap();
+        cartridge.write_byte(0xBFFF, 0x42);
+        assert_eq!(cartridge.read_byte(0xBFFF), 0x42);
+    }
+
+    #[test]
+    fn test_mbc3_banks() {
+        let mut cartridge = Cartridge::new(rom_with_ram(0x13, 128, 0x03)).unwrap();
+        cartridge.write_byte(0x2000, 0x00);
+        assert_eq!(cartridge.read_byte(0x4000), 1);
+        cartridge.write_byte(0x2000, 0x7F);
+        assert_eq!(cartridge.read_byte(0x4000), 0x7F);
+
+        cartridge.write_byte(0x0000, 0x0A);
+        cartridge.write_byte(0xA000, 0x11);
+        cartridge.write_byte(0x4000, 0x03);
+        cartridge.write_byte(0xA000, 0x33);
+        cartridge.write_byte(0x4000, 0x00);
+        assert_eq!(cartridge.read_byte(0xA000), 0x11);
+        cartridge.write_byte(0x4000, 0x03);
+        assert_eq!(cartridge.read_byte(0xA000), 0x33);
+
+        cartridge.write_byte(0x0000, 0x00);
+        assert_eq!(cartridge.read_byte(0xA000), 0xFF);
+    }
+
+    #[test]
+    fn test_mbc5_banks() {
+        let mut rom = rom_with_ram(0x1B, 512, 0x04);
+        rom[0x100 * ROM_BANK_SIZE + 1] = 0xAB;
+        let mut cartridge = Cartridge::new(rom).unwrap();
+        // writing only the high bit keeps the bank 1 selected at power on
+        cartridge.write_byte(0x3000, 0x00);
+        assert_eq!(cartridge.read_byte(0x4000), 1);
+        // MBC5 can map bank 0 into the switchable area
+        cartridge.write_byte(0x2000, 0x00);
+        assert_eq!(cartridge.read_byte(0x4000), 0);
+        cartridge.write_byte(0x3000, 0x01);
+        assert_eq!(cartridge.read_byte(0x4001), 0xAB);
+
+        cartridge.write_byte(0x0000, 0x0A);
+        for bank in 0..16 {
+            cartridge.write_byte(0x4000, bank);
+            cartridge.write_byte(0xA000, bank + 1);
+        }
+        for bank in 0..16 {
+            cartridge.write_byte(0x4000, bank);
+            assert_eq!(cartridge.read_byte(0xA000), bank + 1);
+        }
+    }
+
+    #[test]
+    fn test_mbc3_rtc() {
+        let clock = FakeClock::default();
+        let mut cartridge = Cartridge::new(rom_with_ram(0x10, 2, 0x03)).unwrap();
+        cartridge.set_time_source(Box::new(clock.clone()));
+        cartridge.write_byte(0x0000, 0x0A);
+        cartridge.write_byte(0xA000, 0x42);
+
+        clock.advance(61);
+        cartridge.write_byte(0x6000, 0x00);
+        cartridge.write_byte(0x6000, 0x01);
+        cartridge.write_byte(0x4000, 0x08);
+        assert_eq!(cartridge.read_byte(0xA000), 1);
+        cartridge.write_byte(0x4000, 0x09);
+        assert_eq!(cartridge.read_byte(0xA000), 1);
+
+        cartridge.write_byte(0xA000, 0x20);
+        cartridge.write_byte(0x6000, 0x00);
+        cartridge.write_byte(0x6000, 0x01);
+        assert_eq!(cartridge.read_byte(0xA000), 0x20);
+
+        // selecting a RAM bank maps RAM again
+        cartridge.write_byte(0x4000, 0x00);
+        assert_eq!(cartridge.read_byte(0xA000), 0x42);
+    }
+
+    #[test]
+    fn test_mbc3_without_rtc() {
+        let mut cartridge = Cartridge::new(rom_with_ram(0x13, 2, 0x03)).unwrap();
+        cartridge.write_byte(0x0000, 0x0A);
+        cartridge.write_byte(0xA000, 0x42);
+        cartridge.write_byte(0x4000, 0x08);
+        assert_eq!(cartridge.read_byte(0xA000), 0x42);
+    }
+
+    #[test]
+    fn test_save_data() {
+        let mut cartridge = Cartridge::new(rom_with_ram(0x03, 2, 0x02)).unwrap();
+        cartridge.write_byte(0x0000, 0x0A);
+        assert!(!cartridge.take_ram_dirty());
+        cartridge.write_byte(0xA001, 0x42);
+        assert!(cartridge.take_ram_dirty());
+        assert!(!cartridge.take_ram_dirty());
+
+        let data = cartridge.save_data().unwrap();
+        assert_eq!(data.len(), RAM_BANK_SIZE);
+        assert_eq!(data[1], 0x42);
+
+        let mut cartridge = Cartridge::new(rom_with_ram(0x03, 2, 0x02)).unwrap();
+        cartridge.load_save_data(&data).unwrap();
+        cartridge.write_byte(0x0000, 0x0A);
+        assert_eq!(cartridge.read_byte(0xA001), 0x42);
+
+        assert_eq!(
+            cartridge.load_save_data(&data[1..]),
+            Err(CartridgeError::InvalidSave {
+                size: RAM_BANK_SIZE - 1
+            })
+        );
+    }
+
+    #[test]
+    fn test_no_battery() {
+        let mut cartridge = Cartridge::new(rom_with_ram(0x02, 2, 0x02)).unwrap();
+        assert_eq!(cartridge.save_data(), None);
+    }
+
+    #[test]
+    fn test_save_data_with_rtc() {
+        let clock = FakeClock::default();
+        let mut cartridge = Cartridge::new(rom_with_ram(0x10, 2, 0x03)).unwrap();
+        cartridge.set_time_source(Box::new(clock.clone()));
+        cartridge.write_byte(0x0000, 0x0A);
+        cartridge.write_byte(0x4000, 0x0A);
+        cartridge.write_byte(0xA000, 5);
+        let data = cartridge.save_data().unwrap();
+        assert_eq!(data.len(), 4 * RAM_BANK_SIZE + RTC_STATE_SIZE);
+
+        // the clock keeps running while the game is not played
+        clock.advance(3600);
+        let mut cartridge = Cartridge::new(rom_with_ram(0x10, 2, 0x03)).unwrap();
+        cartridge.set_time_source(Box::new(clock.clone()));
+        cartridge.load_save_data(&data).unwrap();
+        cartridge.write_byte(0x0000, 0x0A);
+        cartridge.write_byte(0x6000, 0x00);
+        cartridge.write_byte(0x6000, 0x01);
+        cartridge.write_byte(0x4000, 0x0A);
+        assert_eq!(cartridge.read_byte(0xA000), 6);
+
+        // saves without the clock footer are accepted
+        let mut cartridge = Cartridge::new(rom_with_ram(0x10, 2, 0x03)).unwrap();
+        assert!(cartridge.load_save_data(&data[..4 * RAM_BANK_SIZE]).is_ok());
+    }
+
+    #[test]
+    fn test_mbc2() {
+        let mut cartridge = Cartridge::new(rom_with_ram(0x06, 16, 0x00)).unwrap();
+        // bit 8 set selects the ROM bank, also below 0x2000
+        cartridge.write_byte(0x0100, 0x05);
+        assert_eq!(cartridge.read_byte(0x4000), 5);
+        cartridge.write_byte(0x3F00, 0x00);
+        assert_eq!(cartridge.read_byte(0x4000), 1);
+
+        // bit 8 clear enables RAM, also above 0x2000
+        assert_eq!(cartridge.read_byte(0xA000), 0xFF);
+        cartridge.write_byte(0x2000, 0x0A);
+        cartridge.write_byte(0xA000, 0x3C);
+        assert_eq!(cartridge.read_byte(0xA000), 0xFC);
+        assert_eq!(cartridge.read_byte(0xA200), 0xFC);
+        assert_eq!(cartridge.read_byte(0xBE00), 0xFC);
+        // the ROM bank is not changed by the RAM enable write
+        assert_eq!(cartridge.read_byte(0x4000), 1);
+
+        let data = cartridge.save_data().unwrap();
+        assert_eq!(data.len(), MBC2_RAM_SIZE);
+        assert_eq!(data[0], 0x0C);
+    }
+}
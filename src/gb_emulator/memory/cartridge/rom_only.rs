@@ -0,0 +1,39 @@
+use log::trace;
+
+use super::mapper::{Banks, Mapper};
+
+// cartridges without MBC, the RAM of the few that have some is always accessible
+pub struct RomOnly {
+    banks: Banks,
+}
+
+impl RomOnly {
+    pub fn new(mut banks: Banks) -> RomOnly {
+        banks.ram_enabled = true;
+        RomOnly { banks }
+    }
+}
+
+impl Mapper for RomOnly {
+    fn banks(&self) -> &Banks {
+        &self.banks
+    }
+
+    fn banks_mut(&mut self) -> &mut Banks {
+        &mut self.banks
+    }
+
+    // games like Tetris write bank numbers anyway, the ROM ignores them
+    fn write_register(&mut self, addr: u16, val: u8) {
+        trace!(
+            "ignored write to cartridge without MBC: {:X} => {:X}",
+            addr,
+            val
+        );
+    }
+
+    fn reset(&mut self) {
+        self.banks.reset();
+        self.banks.ram_enabled = true;
+    }
+}
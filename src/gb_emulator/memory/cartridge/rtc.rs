@@ -0,0 +1,312 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// size of the clock state appended to save files, the layout used by BGB, VBA-M and SameBoy
+pub const RTC_STATE_SIZE: usize = 48;
+
+// supplies the wall clock time the cartridge clock runs from
+pub trait TimeSource {
+    // seconds since the unix epoch
+    fn unix_time(&self) -> u64;
+}
+
+pub struct SystemClock;
+
+impl TimeSource for SystemClock {
+    fn unix_time(&self) -> u64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|time| time.as_secs())
+            .unwrap_or(0)
+    }
+}
+
+// the clock registers as selected by writing 0x08-0x0C to 0x4000
+#[derive(Clone, Copy, Default)]
+struct ClockRegisters {
+    seconds: u8,
+    minutes: u8,
+    hours: u8,
+    days: u16,
+    halted: bool,
+    day_carry: bool,
+}
+
+impl ClockRegisters {
+    fn read_byte(&self, register: u8) -> u8 {
+        match register {
+            0x08 => self.seconds,
+            0x09 => self.minutes,
+            0x0A => self.hours,
+            0x0B => self.days as u8,
+            0x0C => (self.day_carry as u8) << 7 | (self.halted as u8) << 6 | (self.days >> 8) as u8,
+            _ => 0xFF,
+        }
+    }
+
+    fn write_byte(&mut self, register: u8, val: u8) {
+        match register {
+            0x08 => self.seconds = val & 0x3F,
+            0x09 => self.minutes = val & 0x3F,
+            0x0A => self.hours = val & 0x1F,
+            0x0B => self.days = (self.days & 0x100) | val as u16,
+            0x0C => {
+                self.days = (self.days & 0xFF) | ((val & 0x01) as u16) << 8;
+                self.halted = val & 0x40 != 0;
+                self.day_carry = val & 0x80 != 0;
+            }
+            _ => {}
+        }
+    }
+
+    // advance by one second, registers written out of range count up to their bit width before wrapping
+    fn tick_second(&mut self) {
+        self.seconds = (self.seconds + 1) & 0x3F;
+        if self.seconds != 60 {
+            return;
+        }
+        self.seconds = 0;
+        self.minutes = (self.minutes + 1) & 0x3F;
+        if self.minutes != 60 {
+            return;
+        }
+        self.minutes = 0;
+        self.hours = (self.hours + 1) & 0x1F;
+        if self.hours != 24 {
+            return;
+        }
+        self.hours = 0;
+        self.add_days(1);
+    }
+
+    fn add_days(&mut self, days: u64) {
+        let days = self.days as u64 + days;
+        if days > 0x1FF {
+            self.day_carry = true;
+        }
+        self.days = (days & 0x1FF) as u16;
+    }
+
+    fn advance(&mut self, mut seconds: u64) {
+        // out of range values have to be stepped through one second at a time
+        while seconds > 0 && (self.seconds >= 60 || self.minutes >= 60 || self.hours >= 24) {
+            self.tick_second();
+            seconds -= 1;
+        }
+        if seconds == 0 {
+            return;
+        }
+
+        let total =
+            seconds + self.seconds as u64 + self.minutes as u64 * 60 + self.hours as u64 * 3600;
+        self.seconds = (total % 60) as u8;
+        self.minutes = (total / 60 % 60) as u8;
+        self.hours = (total / 3600 % 24) as u8;
+        self.add_days(total / 86400);
+    }
+}
+
+// MBC3 real time clock, the counters are brought up to date from the time source whenever they are accessed
+pub struct Rtc {
+    time_source: Box<dyn TimeSource>,
+    clock: ClockRegisters,
+    latched: ClockRegisters,
+    // unix time the clock registers were last advanced to
+    last_update: u64,
+    // the latch needs a write of 0x00 followed by 0x01
+    latch_armed: bool,
+}
+
+impl Rtc {
+    pub fn new(time_source: Box<dyn TimeSource>) -> Rtc {
+        let last_update = time_source.unix_time();
+        Rtc {
+            time_source,
+            clock: ClockRegisters::default(),
+            latched: ClockRegisters::default(),
+            last_update,
+            latch_armed: false,
+        }
+    }
+
+    // switch to another time source, time that passed on the old one is kept
+    pub fn set_time_source(&mut self, time_source: Box<dyn TimeSource>) {
+        self.update();
+        self.time_source = time_source;
+        self.last_update = self.time_source.unix_time();
+    }
+
+    fn update(&mut self) {
+        let now = self.time_source.unix_time();
+        if !self.clock.halted {
+            self.clock.advance(now.saturating_sub(self.last_update));
+        }
+        self.last_update = now;
+    }
+
+    pub fn write_latch(&mut self, val: u8) {
+        if self.latch_armed && val == 0x01 {
+            self.update();
+            self.latched = self.clock;
+        }
+        self.latch_armed = val == 0x00;
+    }
+
+    // reads return the latched copy of the counters
+    pub fn read_byte(&self, register: u8) -> u8 {
+        self.latched.read_byte(register)
+    }
+
+    pub fn write_byte(&mut self, register: u8, val: u8) {
+        self.update();
+        self.clock.write_byte(register, val);
+        self.latched.write_byte(register, val);
+    }
+
+    // clock and latched registers as 32 bit values followed by a 64 bit unix timestamp
+    pub fn save_state(&mut self) -> [u8; RTC_STATE_SIZE] {
+        self.update();
+        let mut state = [0; RTC_STATE_SIZE];
+        for (i, register) in (0x08..=0x0C).enumerate() {
+            state[i * 4] = self.clock.read_byte(register);
+            state[20 + i * 4] = self.latched.read_byte(register);
+        }
+        state[40..].copy_from_slice(&self.last_update.to_le_bytes());
+        state
+    }
+
+    // restore a saved clock and advance it by the time since it was saved, the older
+    // 44 byte variant with a 32 bit timestamp is accepted too
+    pub fn load_state(&mut self, state: &[u8]) -> bool {
+        let timestamp = match state.len() {
+            48 => u64::from_le_bytes(state[40..48].try_into().unwrap()),
+            44 => u32::from_le_bytes(state[40..44].try_into().unwrap()) as u64,
+            _ => return false,
+        };
+        for (i, register) in (0x08..=0x0C).enumerate() {
+            self.clock.write_byte(register, state[i * 4]);
+            self.latched.write_byte(register, state[20 + i * 4]);
+        }
+        self.last_update = timestamp;
+        self.update();
+        true
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use std::cell::Cell;
+    use std::rc::Rc;
+
+    use super::*;
+
+    // a clock that only moves when the test advances it
+    #[derive(Clone, Default)]
+    pub struct FakeClock(pub Rc<Cell<u64>>);
+
+    impl FakeClock {
+        pub fn advance(&self, seconds: u64) {
+            self.0.set(self.0.get() + seconds);
+        }
+    }
+
+    impl TimeSource for FakeClock {
+        fn unix_time(&self) -> u64 {
+            self.0.get()
+        }
+    }
+
+    fn latch(rtc: &mut Rtc) {
+        rtc.write_latch(0x00);
+        rtc.write_latch(0x01);
+    }
+
+    #[test]
+    fn test_counting() {
+        let clock = FakeClock::default();
+        let mut rtc = Rtc::new(Box::new(clock.clone()));
+        clock.advance(86400 + 3600 * 2 + 60 * 3 + 4);
+        latch(&mut rtc);
+        assert_eq!(rtc.read_byte(0x08), 4);
+        assert_eq!(rtc.read_byte(0x09), 3);
+        assert_eq!(rtc.read_byte(0x0A), 2);
+        assert_eq!(rtc.read_byte(0x0B), 1);
+        assert_eq!(rtc.read_byte(0x0C), 0);
+    }
+
+    #[test]
+    fn test_latch() {
+        let clock = FakeClock::default();
+        let mut rtc = Rtc::new(Box::new(clock.clone()));
+        clock.advance(5);
+        latch(&mut rtc);
+        clock.advance(5);
+        assert_eq!(rtc.read_byte(0x08), 5);
+
+        // writing 0x01 without a preceding 0x00 does not latch
+        rtc.write_latch(0x01);
+        assert_eq!(rtc.read_byte(0x08), 5);
+        latch(&mut rtc);
+        assert_eq!(rtc.read_byte(0x08), 10);
+    }
+
+    #[test]
+    fn test_halt() {
+        let clock = FakeClock::default();
+        let mut rtc = Rtc::new(Box::new(clock.clone()));
+        rtc.write_byte(0x0C, 0x40);
+        clock.advance(100);
+        latch(&mut rtc);
+        assert_eq!(rtc.read_byte(0x08), 0);
+
+        rtc.write_byte(0x0C, 0x00);
+        clock.advance(100);
+        latch(&mut rtc);
+        assert_eq!(rtc.read_byte(0x08), 40);
+        assert_eq!(rtc.read_byte(0x09), 1);
+    }
+
+    #[test]
+    fn test_day_carry() {
+        let clock = FakeClock::default();
+        let mut rtc = Rtc::new(Box::new(clock.clone()));
+        rtc.write_byte(0x0B, 0xFF);
+        rtc.write_byte(0x0C, 0x01);
+        clock.advance(86400);
+        latch(&mut rtc);
+        assert_eq!(rtc.read_byte(0x0B), 0);
+        assert_eq!(rtc.read_byte(0x0C), 0x80);
+    }
+
+    #[test]
+    fn test_out_of_range_seconds() {
+        let clock = FakeClock::default();
+        let mut rtc = Rtc::new(Box::new(clock.clone()));
+        // 63 wraps to 0 without incrementing the minutes
+        rtc.write_byte(0x08, 62);
+        clock.advance(2);
+        latch(&mut rtc);
+        assert_eq!(rtc.read_byte(0x08), 0);
+        assert_eq!(rtc.read_byte(0x09), 0);
+    }
+
+    #[test]
+    fn test_save_state() {
+        let clock = FakeClock::default();
+        clock.advance(1000);
+        let mut rtc = Rtc::new(Box::new(clock.clone()));
+        rtc.write_byte(0x09, 10);
+        let state = rtc.save_state();
+        assert_eq!(state[4], 10);
+        assert_eq!(&state[40..], &1000u64.to_le_bytes());
+
+        // an hour passes between the sessions
+        clock.advance(3600);
+        let mut rtc = Rtc::new(Box::new(clock.clone()));
+        assert!(rtc.load_state(&state));
+        latch(&mut rtc);
+        assert_eq!(rtc.read_byte(0x09), 10);
+        assert_eq!(rtc.read_byte(0x0A), 1);
+
+        assert!(!rtc.load_state(&state[..40]));
+    }
+}
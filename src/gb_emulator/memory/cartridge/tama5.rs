@@ -0,0 +1,148 @@
+use super::mapper::{Banks, Mapper};
+
+// TAMA5 has 32 bytes of battery backed memory behind its register interface
+pub const TAMA5_RAM_SIZE: usize = 0x20;
+
+// Bandai TAMA5 of Tamagotchi 3, all registers are 4 bits wide and accessed through 0xA001,
+// which selects a register, and 0xA000, which reads or writes it, the clock of the TAMA6
+// chip is not emulated
+pub struct Tama5 {
+    banks: Banks,
+    register: u8,
+    rom_bank: u8,
+    // byte to write, address and command of the next memory access
+    data: u8,
+    address: u8,
+    command: u8,
+    // byte read by the last memory access
+    result: u8,
+}
+
+impl Tama5 {
+    pub fn new(banks: Banks) -> Tama5 {
+        Tama5 {
+            banks,
+            register: 0,
+            rom_bank: 0,
+            data: 0,
+            address: 0,
+            command: 0,
+            result: 0,
+        }
+    }
+
+    fn execute(&mut self) {
+        let index = self.address as usize % TAMA5_RAM_SIZE;
+        match self.command {
+            0x0 => {
+                self.banks.ram_mut()[index] = self.data;
+                self.banks.mark_dirty();
+            }
+            0x1 => self.result = self.banks.ram()[index],
+            _ => {}
+        }
+    }
+}
+
+impl Mapper for Tama5 {
+    fn banks(&self) -> &Banks {
+        &self.banks
+    }
+
+    fn banks_mut(&mut self) -> &mut Banks {
+        &mut self.banks
+    }
+
+    // the ROM area has no registers
+    fn write_register(&mut self, _addr: u16, _val: u8) {}
+
+    fn reset(&mut self) {
+        self.banks.reset();
+        self.register = 0;
+        self.rom_bank = 0;
+        self.data = 0;
+        self.address = 0;
+        self.command = 0;
+        self.result = 0;
+    }
+
+    fn read_ram(&self, addr: u16) -> u8 {
+        if addr & 1 != 0 {
+            return 0xFF;
+        }
+        match self.register {
+            // the chip signals that it is ready
+            0xA => 0xF1,
+            0xC => 0xF0 | (self.result & 0x0F),
+            0xD => 0xF0 | (self.result >> 4),
+            _ => 0xFF,
+        }
+    }
+
+    fn write_ram(&mut self, addr: u16, val: u8) {
+        let val = val & 0x0F;
+        if addr & 1 != 0 {
+            self.register = val;
+            return;
+        }
+        match self.register {
+            0x0 => {
+                self.rom_bank = (self.rom_bank & 0x10) | val;
+                self.banks.select_rom_bank(self.rom_bank as usize);
+            }
+            0x1 => {
+                self.rom_bank = (self.rom_bank & 0x0F) | (val & 0x01) << 4;
+                self.banks.select_rom_bank(self.rom_bank as usize);
+            }
+            0x4 => self.data = (self.data & 0xF0) | val,
+            0x5 => self.data = (self.data & 0x0F) | val << 4,
+            0x6 => {
+                self.address = (self.address & 0x0F) | (val & 0x01) << 4;
+                self.command = val >> 1;
+            }
+            // writing the low address nibble starts the access
+            0x7 => {
+                self.address = (self.address & 0x10) | val;
+                self.execute();
+            }
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::tests::rom_with_ram;
+    use super::super::Cartridge;
+
+    fn write_register(cartridge: &mut Cartridge, register: u8, val: u8) {
+        cartridge.write_byte(0xA001, register);
+        cartridge.write_byte(0xA000, val);
+    }
+
+    #[test]
+    fn test_registers() {
+        let mut cartridge = Cartridge::new(rom_with_ram(0xFD, 32, 0x00)).unwrap();
+        write_register(&mut cartridge, 0x0, 0x3);
+        write_register(&mut cartridge, 0x1, 0x1);
+        assert_eq!(cartridge.read_byte(0x4000), 0x13);
+
+        cartridge.write_byte(0xA001, 0xA);
+        assert_eq!(cartridge.read_byte(0xA000) & 0x0F, 0x1);
+
+        // write 0x5A to address 0x12, then read it back
+        write_register(&mut cartridge, 0x4, 0xA);
+        write_register(&mut cartridge, 0x5, 0x5);
+        write_register(&mut cartridge, 0x6, 0x1);
+        write_register(&mut cartridge, 0x7, 0x2);
+        assert!(cartridge.take_ram_dirty());
+        write_register(&mut cartridge, 0x6, 0x3);
+        write_register(&mut cartridge, 0x7, 0x2);
+        cartridge.write_byte(0xA001, 0xC);
+        assert_eq!(cartridge.read_byte(0xA000), 0xFA);
+        cartridge.write_byte(0xA001, 0xD);
+        assert_eq!(cartridge.read_byte(0xA000), 0xF5);
+
+        assert_eq!(cartridge.save_data().unwrap()[0x12], 0x5A);
+    }
+}
@@ -0,0 +1,84 @@
+// bytes copied to OAM by one transfer, one per machine cycle
+const TRANSFER_LENGTH: u16 = 0xA0;
+// machine cycles between the write to 0xFF46 and the first byte being copied
+const START_DELAY: u8 = 1;
+
+// OAM DMA started by writing the upper byte of the source address to 0xFF46
+pub struct OamDma {
+    source: u8,
+    // next byte to copy, None while idle
+    index: Option<u16>,
+    delay: u8,
+}
+
+impl OamDma {
+    pub fn new() -> OamDma {
+        OamDma {
+            source: 0xFF,
+            index: None,
+            delay: 0,
+        }
+    }
+
+    // the register keeps the last value written
+    pub fn read_byte(&self) -> u8 {
+        self.source
+    }
+
+    // writing while a transfer runs restarts it from the new source
+    pub fn write_byte(&mut self, val: u8) {
+        self.source = val;
+        self.index = Some(0);
+        self.delay = START_DELAY;
+    }
+
+    // address of the byte being copied while a transfer is running
+    pub fn current_source(&self) -> Option<u16> {
+        let index = self.index.filter(|_| self.delay == 0)?;
+        // sources above 0xDFFF read the work RAM below them like echo RAM does
+        let addr = (self.source as u16) << 8 | index;
+        Some(if addr >= 0xE000 { addr - 0x2000 } else { addr })
+    }
+
+    // advance by one machine cycle, returns the source address and OAM offset to copy
+    pub fn step(&mut self) -> Option<(u16, usize)> {
+        if self.delay > 0 {
+            self.delay -= 1;
+            return None;
+        }
+        let source = self.current_source()?;
+        let index = self.index? + 1;
+        self.index = (index < TRANSFER_LENGTH).then_some(index);
+        Some((source, (source & 0xFF) as usize))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_transfer() {
+        let mut dma = OamDma::new();
+        assert_eq!(dma.step(), None);
+
+        dma.write_byte(0xC1);
+        assert_eq!(dma.read_byte(), 0xC1);
+        assert_eq!(dma.current_source(), None);
+        assert_eq!(dma.step(), None);
+        assert_eq!(dma.current_source(), Some(0xC100));
+        for index in 0..0xA0 {
+            assert_eq!(dma.step(), Some((0xC100 + index, index as usize)));
+        }
+        assert_eq!(dma.current_source(), None);
+        assert_eq!(dma.step(), None);
+    }
+
+    #[test]
+    fn test_echo_source() {
+        let mut dma = OamDma::new();
+        dma.write_byte(0xFE);
+        dma.step();
+        assert_eq!(dma.current_source(), Some(0xDE00));
+    }
+}
@@ -0,0 +1,79 @@
+// interrupt sources in priority order, the value is the bit in IE/IF
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Interrupt {
+    VBlank = 0b00001,
+    LcdStat = 0b00010,
+    Timer = 0b00100,
+    Serial = 0b01000,
+    Joypad = 0b10000,
+}
+
+const PRIORITY: [Interrupt; 5] = [
+    Interrupt::VBlank,
+    Interrupt::LcdStat,
+    Interrupt::Timer,
+    Interrupt::Serial,
+    Interrupt::Joypad,
+];
+
+impl Interrupt {
+    pub fn vector(self) -> u16 {
+        match self {
+            Interrupt::VBlank => 0x40,
+            Interrupt::LcdStat => 0x48,
+            Interrupt::Timer => 0x50,
+            Interrupt::Serial => 0x58,
+            Interrupt::Joypad => 0x60,
+        }
+    }
+}
+
+pub struct InterruptController {
+    // IF (0xFF0F)
+    flags: u8,
+    // IE (0xFFFF)
+    enable: u8,
+}
+
+impl InterruptController {
+    pub fn new() -> InterruptController {
+        InterruptController {
+            flags: 0,
+            enable: 0,
+        }
+    }
+
+    pub fn read_flags(&self) -> u8 {
+        // the upper three bits of IF are unused and always read as 1
+        self.flags | 0xE0
+    }
+
+    pub fn write_flags(&mut self, val: u8) {
+        self.flags = val & 0x1F;
+    }
+
+    pub fn read_enable(&self) -> u8 {
+        self.enable
+    }
+
+    pub fn write_enable(&mut self, val: u8) {
+        self.enable = val;
+    }
+
+    pub fn request(&mut self, interrupt: Interrupt) {
+        self.flags |= interrupt as u8;
+    }
+
+    pub fn acknowledge(&mut self, interrupt: Interrupt) {
+        self.flags &= !(interrupt as u8);
+    }
+
+    // highest priority interrupt that is both requested and enabled
+    pub fn pending(&self) -> Option<Interrupt> {
+        let active = self.flags & self.enable;
+        PRIORITY
+            .iter()
+            .copied()
+            .find(|interrupt| active & (*interrupt as u8) != 0)
+    }
+}
@@ -0,0 +1,116 @@
+// the d-pad occupies the low nibble and the action buttons the high nibble of the pressed mask,
+// both in the bit order of the P1 input lines
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Button {
+    Right,
+    Left,
+    Up,
+    Down,
+    A,
+    B,
+    Select,
+    Start,
+}
+
+pub struct Joypad {
+    // one bit per button, set while the button is held down
+    pressed: u8,
+    // P1 bits 4 (d-pad) and 5 (buttons), a group is selected when its bit is 0
+    select: u8,
+}
+
+impl Joypad {
+    pub fn new() -> Joypad {
+        Joypad {
+            pressed: 0,
+            select: 0x30,
+        }
+    }
+
+    // active low input lines of the selected button groups
+    fn lines(&self) -> u8 {
+        let mut lines = 0x0F;
+        if self.select & 0x10 == 0 {
+            lines &= !(self.pressed & 0x0F);
+        }
+        if self.select & 0x20 == 0 {
+            lines &= !(self.pressed >> 4);
+        }
+        lines
+    }
+
+    pub fn read_byte(&self) -> u8 {
+        0xC0 | self.select | self.lines()
+    }
+
+    // returns true if an input line went from high to low, which requests the joypad interrupt
+    pub fn write_byte(&mut self, val: u8) -> bool {
+        let old_lines = self.lines();
+        self.select = val & 0x30;
+        old_lines & !self.lines() != 0
+    }
+
+    // returns true if an input line went from high to low, which requests the joypad interrupt
+    pub fn set_button(&mut self, button: Button, pressed: bool) -> bool {
+        let old_lines = self.lines();
+        let mask = 1 << button as u8;
+        if pressed {
+            self.pressed |= mask;
+        } else {
+            self.pressed &= !mask;
+        }
+        old_lines & !self.lines() != 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_nothing_selected() {
+        let mut joypad = Joypad::new();
+        assert!(!joypad.set_button(Button::A, true));
+        assert!(!joypad.set_button(Button::Down, true));
+        assert_eq!(joypad.read_byte(), 0xFF);
+    }
+
+    #[test]
+    fn test_dpad() {
+        let mut joypad = Joypad::new();
+        joypad.write_byte(0x20);
+        assert_eq!(joypad.read_byte(), 0xEF);
+        assert!(joypad.set_button(Button::Left, true));
+        assert!(!joypad.set_button(Button::Start, true));
+        assert_eq!(joypad.read_byte(), 0xED);
+        assert!(joypad.set_button(Button::Down, true));
+        assert_eq!(joypad.read_byte(), 0xE5);
+        assert!(!joypad.set_button(Button::Left, false));
+        assert_eq!(joypad.read_byte(), 0xE7);
+    }
+
+    #[test]
+    fn test_buttons() {
+        let mut joypad = Joypad::new();
+        joypad.write_byte(0x10);
+        assert!(joypad.set_button(Button::A, true));
+        assert!(!joypad.set_button(Button::Up, true));
+        assert_eq!(joypad.read_byte(), 0xDE);
+        assert!(joypad.set_button(Button::Start, true));
+        assert_eq!(joypad.read_byte(), 0xD6);
+    }
+
+    #[test]
+    fn test_select_change_interrupt() {
+        let mut joypad = Joypad::new();
+        joypad.set_button(Button::B, true);
+        assert!(!joypad.write_byte(0x20));
+        // selecting the buttons pulls the B line low
+        assert!(joypad.write_byte(0x10));
+        assert_eq!(joypad.read_byte(), 0xDD);
+        // both groups selected, the lines are ANDed together
+        joypad.set_button(Button::Up, true);
+        assert!(joypad.write_byte(0x00));
+        assert_eq!(joypad.read_byte(), 0xC9);
+    }
+}
@@ -1,70 +1,803 @@
 mod cartridge;
-use cartridge::Cartridge;
+mod dma;
+mod interrupts;
+mod joypad;
+mod serial;
+mod timer;
+mod vram_dma;
+pub use cartridge::{
+    Cartridge, CartridgeError, CartridgeHeader, CgbSupport, Destination, Licensee, TimeSource,
+    CAMERA_HEIGHT, CAMERA_WIDTH,
+};
+use dma::OamDma;
+pub use interrupts::{Interrupt, InterruptController};
+pub use joypad::Button;
+use joypad::Joypad;
+use serial::Serial;
+pub use serial::{InProcessCable, LinkCable, LoopbackCable, SerialCapture, TcpCable};
+use timer::Timer;
+use vram_dma::{VramDma, BLOCK_SIZE};
 
+use super::apu::Apu;
+use super::model::Model;
+use super::ppu::{Ppu, SCREEN_HEIGHT, SCREEN_WIDTH};
+use super::sgb::{Sgb, SGB_SCREEN_HEIGHT, SGB_SCREEN_WIDTH};
 use super::utils::U16Ext;
 
+// regions of the memory map owned by the bus itself, the cartridge and PPU size their own
+const WORK_RAM_START: u16 = 0xC000;
+// 0xC000 always maps bank 0, 0xD000 maps bank 1 or on the CGB any of the banks 1-7
+const WORK_RAM_BANK_SIZE: usize = 0x1000;
+const WORK_RAM_BANKS: usize = 8;
+// 0xE000-0xFDFF mirrors the first 7.5 KiB of work RAM
+const ECHO_RAM_START: u16 = 0xE000;
+const IO_START: u16 = 0xFF00;
+const IO_SIZE: usize = 0x80;
+const HIGH_RAM_START: u16 = 0xFF80;
+const HIGH_RAM_SIZE: usize = 0x7F;
+
+#[derive(PartialEq)]
+enum Bus {
+    // cartridge and work RAM
+    External,
+    Video,
+}
+
+fn bus(addr: u16) -> Option<Bus> {
+    match addr {
+        0x0000..=0x7FFF | 0xA000..=0xFDFF => Some(Bus::External),
+        0x8000..=0x9FFF => Some(Bus::Video),
+        _ => None,
+    }
+}
+
+// IO registers as every boot ROM leaves them, sound is powered on first since the other sound
+// registers ignore writes while it is off, channel 1 is not triggered again as its boot sound
+// has already faded out
+const POST_BOOT_IO: [(u16, u8); 26] = [
+    (0xFF00, 0xCF),
+    (0xFF07, 0xF8),
+    (0xFF0F, 0xE1),
+    (0xFF26, 0x80),
+    (0xFF10, 0x80),
+    (0xFF11, 0xBF),
+    (0xFF12, 0xF3),
+    (0xFF13, 0xFF),
+    (0xFF14, 0x3F),
+    (0xFF16, 0x3F),
+    (0xFF17, 0x00),
+    (0xFF18, 0xFF),
+    (0xFF19, 0x3F),
+    (0xFF1A, 0x7F),
+    (0xFF1B, 0xFF),
+    (0xFF1C, 0x9F),
+    (0xFF1D, 0xFF),
+    (0xFF1E, 0x3F),
+    (0xFF20, 0xFF),
+    (0xFF21, 0x00),
+    (0xFF22, 0x00),
+    (0xFF23, 0x3F),
+    (0xFF24, 0x77),
+    (0xFF25, 0xF3),
+    (0xFF40, 0x91),
+    (0xFF47, 0xFC),
+];
+
+// registers the models leave differently, on top of POST_BOOT_IO
+const POST_BOOT_IO_DMG: [(u16, u8); 1] = [(0xFF02, 0x7E)];
+// the CGB leaves SC with its fast clock bit set, the palette indexes auto incrementing after
+// the palettes it wrote and the first VRAM and work RAM banks selected, registers of CGB mode
+// ignore the writes for DMG games
+const POST_BOOT_IO_CGB: [(u16, u8); 6] = [
+    (0xFF02, 0x7F),
+    (0xFF4D, 0x00),
+    (0xFF4F, 0x00),
+    (0xFF68, 0x80),
+    (0xFF6A, 0x80),
+    (0xFF70, 0x00),
+];
+
 pub struct MemoryBus {
     cartridge: Cartridge,
-    tile_ram: [u8; 0x17FF],
-    working_ram: [u8; 0x1FFF],
-    object_attribute_memory: [u8; 0x9F],
-    io_registers: [u8; 0x7F],
-    high_ram: [u8; 0x7E],
-    interrupt_enable_register: u8,
+    working_ram: [u8; WORK_RAM_BANK_SIZE * WORK_RAM_BANKS],
+    // selected through SVBK (0xFF70) in CGB mode, bank 0 maps bank 1
+    work_ram_bank: usize,
+    io_registers: [u8; IO_SIZE],
+    high_ram: [u8; HIGH_RAM_SIZE],
+    interrupts: InterruptController,
+    timer: Timer,
+    serial: Serial,
+    ppu: Ppu,
+    joypad: Joypad,
+    // listens to the joypad register for command packets on a Super Game Boy
+    sgb: Option<Sgb>,
+    apu: Apu,
+    dma: OamDma,
+    vram_dma: VramDma,
+    // machine cycles the CPU has to wait for VRAM DMA transfers
+    dma_stall: u32,
+    boot_rom: Option<Vec<u8>>,
+    // cleared for good by a write to 0xFF50
+    boot_rom_mapped: bool,
+    // CGB mode enables the banking, palette and speed registers, a CGB picks it while booting
+    cgb_mode: bool,
+    // KEY1 (0xFF4D), STOP switches the speed once armed
+    double_speed: bool,
+    speed_switch_armed: bool,
 }
 
 impl MemoryBus {
-    pub fn new() -> MemoryBus {
-        //ROM dummy of bigges possible size (8Mb)
-        let dummy_rom = vec![0; 0x7a1200];
-
+    pub fn new(cartridge: Cartridge) -> MemoryBus {
         MemoryBus {
-            cartridge: Cartridge::new(dummy_rom),
-            tile_ram: [0; 0x17FF],
-            working_ram: [0; 0x1FFF],
-            object_attribute_memory: [0; 0x9F],
-            io_registers: [0; 0x7F],
-            high_ram: [0; 0x7E],
-            interrupt_enable_register: 0,
+            cartridge,
+            working_ram: [0; WORK_RAM_BANK_SIZE * WORK_RAM_BANKS],
+            work_ram_bank: 0,
+            io_registers: [0; IO_SIZE],
+            high_ram: [0; HIGH_RAM_SIZE],
+            interrupts: InterruptController::new(),
+            timer: Timer::new(),
+            serial: Serial::new(),
+            ppu: Ppu::new(),
+            joypad: Joypad::new(),
+            sgb: None,
+            apu: Apu::new(),
+            dma: OamDma::new(),
+            vram_dma: VramDma::new(),
+            dma_stall: 0,
+            boot_rom: None,
+            boot_rom_mapped: false,
+            cgb_mode: false,
+            double_speed: false,
+            speed_switch_armed: false,
+        }
+    }
+
+    // overlay the boot ROM over the cartridge until the boot ROM unmaps itself, the CGB boot
+    // ROM starts out in CGB mode and drops to DMG mode through KEY0 for DMG games
+    pub fn load_boot_rom(&mut self, boot_rom: Vec<u8>) {
+        self.boot_rom = Some(boot_rom);
+        self.boot_rom_mapped = true;
+        self.set_cgb_mode(self.has_cgb_boot_rom());
+    }
+
+    // for SGB games on a Super Game Boy
+    pub fn enable_sgb(&mut self) {
+        self.sgb = Some(Sgb::new());
+    }
+
+    fn has_cgb_boot_rom(&self) -> bool {
+        self.boot_rom
+            .as_ref()
+            .is_some_and(|boot_rom| boot_rom.len() == Model::Cgb.boot_rom_size())
+    }
+
+    // put the IO registers into the state the boot ROM of the model leaves behind, a CGB
+    // stays in CGB mode for games that declare support in the header
+    pub fn skip_boot(&mut self, model: Model) {
+        self.boot_rom_mapped = false;
+        let cgb_game = self.cartridge.header().cgb_support != CgbSupport::None;
+        self.set_cgb_mode(model == Model::Cgb && cgb_game);
+        let model_io: &[(u16, u8)] = match model {
+            Model::Cgb => &POST_BOOT_IO_CGB,
+            Model::Dmg | Model::Mgb | Model::Sgb | Model::Sgb2 => &POST_BOOT_IO_DMG,
+        };
+        for &(addr, val) in POST_BOOT_IO.iter().chain(model_io) {
+            self.write_byte(addr, val);
+        }
+        self.timer.set_counter(model.post_boot_divider());
+    }
+
+    fn set_cgb_mode(&mut self, cgb_mode: bool) {
+        self.cgb_mode = cgb_mode;
+        self.ppu.set_cgb_mode(cgb_mode);
+        self.serial.set_cgb_mode(cgb_mode);
+        if !cgb_mode {
+            self.work_ram_bank = 0;
+        }
+    }
+
+    pub fn double_speed(&self) -> bool {
+        self.double_speed
+    }
+
+    // called by STOP, returns true if it switched the CPU speed
+    pub fn switch_speed(&mut self) -> bool {
+        if !self.cgb_mode || !self.speed_switch_armed {
+            return false;
+        }
+        self.speed_switch_armed = false;
+        self.double_speed = !self.double_speed;
+        self.timer.set_double_speed(self.double_speed);
+        true
+    }
+
+    // index into work RAM for an offset from the start of work or echo RAM
+    fn work_ram_index(&self, offset: u16) -> usize {
+        let offset = offset as usize;
+        if offset < WORK_RAM_BANK_SIZE {
+            offset
+        } else {
+            self.work_ram_bank.max(1) * WORK_RAM_BANK_SIZE + offset - WORK_RAM_BANK_SIZE
+        }
+    }
+
+    // the DMA unit reads past the restrictions the CPU sees
+    fn dma_read(&self, addr: u16) -> u8 {
+        match addr {
+            0x8000..=0x9FFF => self.ppu.read_vram(addr),
+            0xC000..=0xDFFF => self.working_ram[self.work_ram_index(addr - WORK_RAM_START)],
+            _ => self.cartridge.read_byte(addr),
+        }
+    }
+
+    // while a transfer runs OAM is inaccessible and the CPU reads the byte in flight on the
+    // bus the DMA uses, writes to that bus are lost, IO and HRAM stay accessible
+    fn dma_conflict(&self, addr: u16) -> Option<u8> {
+        let source = self.dma.current_source()?;
+        match addr {
+            0xFE00..=0xFEFF => Some(0xFF),
+            _ if bus(addr).is_some() && bus(addr) == bus(source) => Some(self.dma_read(source)),
+            _ => None,
+        }
+    }
+
+    // copy one block of a VRAM DMA transfer into the current VRAM bank, the CPU waits for 32
+    // clocks of the PPU while it runs, which are twice the machine cycles in double speed
+    fn copy_vram_block(&mut self, (source, destination): (u16, u16)) {
+        for offset in 0..BLOCK_SIZE {
+            let val = self.dma_read(source.wrapping_add(offset));
+            self.ppu.write_vram(0x8000 + destination + offset, val);
+        }
+        self.dma_stall += if self.double_speed { 16 } else { 8 };
+    }
+
+    // machine cycles the CPU stalled on VRAM DMA since the last call
+    pub fn take_dma_stall(&mut self) -> u32 {
+        std::mem::take(&mut self.dma_stall)
+    }
+
+    fn read_boot_rom(&self, addr: u16) -> Option<u8> {
+        let boot_rom = self.boot_rom.as_ref().filter(|_| self.boot_rom_mapped)?;
+        match addr as usize {
+            addr @ 0x0000..=0x00FF => Some(boot_rom[addr]),
+            addr @ 0x0200..=0x08FF if addr < boot_rom.len() => Some(boot_rom[addr]),
+            _ => None,
         }
     }
 
+    // power cycle everything except the cartridge RAM
+    pub fn reset(&mut self) {
+        self.cartridge.reset();
+        self.working_ram = [0; WORK_RAM_BANK_SIZE * WORK_RAM_BANKS];
+        self.work_ram_bank = 0;
+        self.io_registers = [0; IO_SIZE];
+        self.high_ram = [0; HIGH_RAM_SIZE];
+        self.interrupts = InterruptController::new();
+        self.timer = Timer::new();
+        self.serial.reset();
+        self.ppu = Ppu::new();
+        self.joypad = Joypad::new();
+        if self.sgb.is_some() {
+            self.sgb = Some(Sgb::new());
+        }
+        self.apu = Apu::new();
+        self.dma = OamDma::new();
+        self.vram_dma = VramDma::new();
+        self.dma_stall = 0;
+        self.boot_rom_mapped = self.boot_rom.is_some();
+        self.set_cgb_mode(self.has_cgb_boot_rom());
+        self.double_speed = false;
+        self.speed_switch_armed = false;
+    }
+
     pub fn read_byte(&self, addr: u16) -> u8 {
+        if let Some(val) = self.dma_conflict(addr) {
+            return val;
+        }
         match addr {
-            0x0000..=0x7FFF => self.cartridge.read_byte(addr),
-            0x8000..=0x9FFF => self.tile_ram[(addr - 0x8000) as usize],
+            0x0000..=0x7FFF => self
+                .read_boot_rom(addr)
+                .unwrap_or_else(|| self.cartridge.read_byte(addr)),
+            0x8000..=0x9FFF => self.ppu.read_vram(addr),
             0xA000..=0xBFFF => self.cartridge.read_byte(addr),
-            0xC000..=0xDFFF => self.working_ram[(addr - 0xC000) as usize],
-            0xE000..=0xFDFF => self.working_ram[(addr - 0xE000) as usize],
-            0xFE00..=0xFE9F => self.object_attribute_memory[(addr - 0xFE00) as usize],
+            0xC000..=0xDFFF => self.working_ram[self.work_ram_index(addr - WORK_RAM_START)],
+            0xE000..=0xFDFF => self.working_ram[self.work_ram_index(addr - ECHO_RAM_START)],
+            0xFE00..=0xFE9F => self.ppu.read_oam(addr),
             0xFEA0..=0xFEFF => 0,
-            0xFF00..=0xFF7F => self.io_registers[(addr - 0xFF00) as usize],
-            0xFF80..=0xFFFE => self.high_ram[(addr - 0xFF80) as usize],
-            0xFFFF => self.interrupt_enable_register,
+            0xFF00..=0xFF7F => self.read_io(addr),
+            0xFF80..=0xFFFE => self.high_ram[(addr - HIGH_RAM_START) as usize],
+            0xFFFF => self.interrupts.read_enable(),
+        }
+    }
+
+    fn read_io(&self, addr: u16) -> u8 {
+        match addr {
+            0xFF00 => {
+                let val = self.joypad.read_byte();
+                self.sgb.as_ref().map_or(val, |sgb| sgb.read_joypad(val))
+            }
+            0xFF01..=0xFF02 => self.serial.read_byte(addr),
+            0xFF04..=0xFF07 => self.timer.read_byte(addr),
+            0xFF0F => self.interrupts.read_flags(),
+            0xFF10..=0xFF3F => self.apu.read_byte(addr),
+            0xFF40..=0xFF45 | 0xFF47..=0xFF4B | 0xFF4F | 0xFF68..=0xFF6B => {
+                self.ppu.read_byte(addr)
+            }
+            0xFF46 => self.dma.read_byte(),
+            // KEY0 can only be written by the boot ROM
+            0xFF4C | 0xFF50 => 0xFF,
+            0xFF4D | 0xFF51..=0xFF55 | 0xFF70 if !self.cgb_mode => 0xFF,
+            0xFF4D => (self.double_speed as u8) << 7 | 0x7E | self.speed_switch_armed as u8,
+            0xFF51..=0xFF55 => self.vram_dma.read_byte(addr),
+            0xFF70 => 0xF8 | self.work_ram_bank as u8,
+            _ => self.io_registers[(addr - IO_START) as usize],
         }
     }
 
     pub fn write_byte(&mut self, addr: u16, val: u8) {
+        if self.dma_conflict(addr).is_some() {
+            return;
+        }
         match addr {
             0x0000..=0x7FFF => self.cartridge.write_byte(addr, val),
-            0x8000..=0x9FFF => self.tile_ram[(addr - 0x8000) as usize] = val,
+            0x8000..=0x9FFF => self.ppu.write_vram(addr, val),
             0xA000..=0xBFFF => self.cartridge.write_byte(addr, val),
-            0xC000..=0xDFFF => self.working_ram[(addr - 0xC000) as usize] = val,
-            0xE000..=0xFDFF => self.working_ram[(addr - 0xE000) as usize] = val,
-            0xFE00..=0xFE9F => self.object_attribute_memory[(addr - 0xFE00) as usize] = val,
+            0xC000..=0xDFFF => {
+                let index = self.work_ram_index(addr - WORK_RAM_START);
+                self.working_ram[index] = val;
+            }
+            0xE000..=0xFDFF => {
+                let index = self.work_ram_index(addr - ECHO_RAM_START);
+                self.working_ram[index] = val;
+            }
+            0xFE00..=0xFE9F => self.ppu.write_oam(addr, val),
             0xFEA0..=0xFEFF => {}
-            0xFF00..=0xFF7F => self.io_registers[(addr - 0xFF00) as usize] = val,
-            0xFF80..=0xFFFE => self.high_ram[(addr - 0xFF80) as usize] = val,
-            0xFFFF => self.interrupt_enable_register = val,
+            0xFF00..=0xFF7F => self.write_io(addr, val),
+            0xFF80..=0xFFFE => self.high_ram[(addr - HIGH_RAM_START) as usize] = val,
+            0xFFFF => self.interrupts.write_enable(val),
+        }
+    }
+
+    fn write_io(&mut self, addr: u16, val: u8) {
+        match addr {
+            0xFF00 => {
+                let old_val = self.joypad.read_byte();
+                if self.joypad.write_byte(val) {
+                    self.request_interrupt(Interrupt::Joypad);
+                }
+                if let Some(sgb) = &mut self.sgb {
+                    sgb.write_joypad(val, old_val, &self.ppu);
+                }
+            }
+            0xFF01..=0xFF02 => self.serial.write_byte(addr, val),
+            0xFF04..=0xFF07 => self.timer.write_byte(addr, val),
+            0xFF0F => self.interrupts.write_flags(val),
+            0xFF10..=0xFF3F => self.apu.write_byte(addr, val),
+            0xFF40..=0xFF45 | 0xFF47..=0xFF4B | 0xFF4F | 0xFF68..=0xFF6B => {
+                self.ppu.write_byte(addr, val, &mut self.interrupts)
+            }
+            0xFF46 => self.dma.write_byte(val),
+            // the CGB boot ROM selects DMG mode for DMG games before unmapping itself
+            0xFF4C => {
+                if self.boot_rom_mapped && self.has_cgb_boot_rom() {
+                    self.set_cgb_mode(val & 0x04 == 0);
+                }
+            }
+            0xFF4D | 0xFF51..=0xFF55 | 0xFF70 if !self.cgb_mode => {}
+            0xFF4D => self.speed_switch_armed = val & 0x01 != 0,
+            0xFF51..=0xFF55 => {
+                self.vram_dma.write_byte(addr, val);
+                // a general purpose transfer copies everything at once
+                while self.vram_dma.general_pending() {
+                    if let Some(block) = self.vram_dma.next_block() {
+                        self.copy_vram_block(block);
+                    }
+                }
+            }
+            0xFF70 => self.work_ram_bank = (val & 0x07) as usize,
+            0xFF50 => {
+                if val != 0 {
+                    self.boot_rom_mapped = false;
+                }
+            }
+            _ => self.io_registers[(addr - IO_START) as usize] = val,
         }
     }
 
+    // advance all peripherals by the given amount of machine cycles
+    pub fn tick(&mut self, cycles: u32) {
+        for _ in 0..cycles {
+            if let Some((source, offset)) = self.dma.step() {
+                let val = self.dma_read(source);
+                self.ppu.write_oam_dma(offset, val);
+            }
+        }
+        if self.timer.tick(cycles * 4) {
+            self.request_interrupt(Interrupt::Timer);
+        }
+        if self.serial.tick(cycles * 4) {
+            self.request_interrupt(Interrupt::Serial);
+        }
+        // the PPU and APU keep their speed in double speed mode, so they see half the clocks
+        let clocks = if self.double_speed {
+            cycles * 2
+        } else {
+            cycles * 4
+        };
+        let frame_clocks = self.timer.take_apu_clocks();
+        self.apu.tick(clocks, frame_clocks);
+        self.ppu.tick(clocks, &mut self.interrupts);
+        if self.ppu.take_vblank_started() {
+            if let Some(sgb) = &mut self.sgb {
+                sgb.render(self.ppu.shades());
+            }
+        }
+        if self.ppu.take_hblank_started() && self.vram_dma.hblank_active() {
+            if let Some(block) = self.vram_dma.next_block() {
+                self.copy_vram_block(block);
+            }
+        }
+    }
+
+    pub fn framebuffer(&self) -> &[u8] {
+        match &self.sgb {
+            Some(sgb) => sgb.framebuffer(),
+            None => self.ppu.framebuffer(),
+        }
+    }
+
+    // width and height of the framebuffer, the SGB adds a border around the screen
+    pub fn screen_size(&self) -> (usize, usize) {
+        match self.sgb {
+            Some(_) => (SGB_SCREEN_WIDTH, SGB_SCREEN_HEIGHT),
+            None => (SCREEN_WIDTH, SCREEN_HEIGHT),
+        }
+    }
+
+    pub fn take_frame_complete(&mut self) -> bool {
+        self.ppu.take_frame_complete()
+    }
+
+    pub fn set_link_cable(&mut self, cable: Box<dyn LinkCable>) {
+        self.serial.set_link_cable(cable);
+    }
+
+    pub fn cartridge(&mut self) -> &mut Cartridge {
+        &mut self.cartridge
+    }
+
+    pub fn take_audio_samples(&mut self) -> Vec<f32> {
+        self.apu.take_samples()
+    }
+
+    pub fn set_button(&mut self, button: Button, pressed: bool) {
+        if self.joypad.set_button(button, pressed) {
+            self.request_interrupt(Interrupt::Joypad);
+        }
+    }
+
+    // used by peripherals to raise their bit in IF
+    pub fn request_interrupt(&mut self, interrupt: Interrupt) {
+        self.interrupts.request(interrupt);
+    }
+
+    pub fn acknowledge_interrupt(&mut self, interrupt: Interrupt) {
+        self.interrupts.acknowledge(interrupt);
+    }
+
+    pub fn pending_interrupt(&self) -> Option<Interrupt> {
+        self.interrupts.pending()
+    }
+
     pub fn write_word(&mut self, addr: u16, val: u16) {
-        self.write_byte(addr, val.hi());
-        self.write_byte(addr + 1, val.hi());
+        self.write_byte(addr, val.lo());
+        self.write_byte(addr.wrapping_add(1), val.hi());
     }
 
     pub fn read_word(&self, addr: u16) -> u16 {
-        (self.read_byte(addr) as u16) | ((self.read_byte(addr + 1) as u16) << 8)
+        (self.read_byte(addr) as u16) | ((self.read_byte(addr.wrapping_add(1)) as u16) << 8)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_bus() -> MemoryBus {
+        let mut rom = vec![0; 0x8000];
+        rom[0x0000] = 0x11;
+        rom[0x0100] = 0x22;
+        rom[0x0200] = 0x33;
+        MemoryBus::new(Cartridge::new(rom).unwrap())
+    }
+
+    // a CGB game on a CGB past the boot ROM
+    fn cgb_bus() -> MemoryBus {
+        let mut rom = vec![0; 0x8000];
+        rom[0x0143] = 0x80;
+        let mut bus = MemoryBus::new(Cartridge::new(rom).unwrap());
+        bus.skip_boot(Model::Cgb);
+        bus
+    }
+
+    #[test]
+    fn test_work_ram_banks() {
+        let mut bus = cgb_bus();
+        assert_eq!(bus.read_byte(0xFF70), 0xF8);
+        for bank in 1..8 {
+            bus.write_byte(0xFF70, bank);
+            bus.write_byte(0xD000, bank * 0x11);
+        }
+        bus.write_byte(0xFF70, 0x03);
+        assert_eq!(bus.read_byte(0xD000), 0x33);
+        // echo RAM follows the selected bank
+        assert_eq!(bus.read_byte(0xF000), 0x33);
+        // bank 0 selects bank 1
+        bus.write_byte(0xFF70, 0x00);
+        assert_eq!(bus.read_byte(0xFF70), 0xF8);
+        assert_eq!(bus.read_byte(0xD000), 0x11);
+
+        // no banking in DMG mode
+        let mut bus = test_bus();
+        bus.skip_boot(Model::Cgb);
+        bus.write_byte(0xD000, 0x42);
+        bus.write_byte(0xFF70, 0x02);
+        assert_eq!(bus.read_byte(0xFF70), 0xFF);
+        assert_eq!(bus.read_byte(0xD000), 0x42);
+    }
+
+    #[test]
+    fn test_speed_switch() {
+        let mut bus = cgb_bus();
+        assert_eq!(bus.read_byte(0xFF4D), 0x7E);
+        assert!(!bus.switch_speed());
+        bus.write_byte(0xFF4D, 0x01);
+        assert_eq!(bus.read_byte(0xFF4D), 0x7F);
+        assert!(bus.switch_speed());
+        assert_eq!(bus.read_byte(0xFF4D), 0xFE);
+        assert!(bus.double_speed());
+
+        // a scanline now takes twice the machine cycles
+        bus.write_byte(0xFF40, 0x00);
+        bus.write_byte(0xFF40, 0x91);
+        bus.tick(114);
+        assert_eq!(bus.read_byte(0xFF44), 0);
+        bus.tick(114);
+        assert_eq!(bus.read_byte(0xFF44), 1);
+
+        let mut bus = test_bus();
+        bus.skip_boot(Model::Dmg);
+        bus.write_byte(0xFF4D, 0x01);
+        assert_eq!(bus.read_byte(0xFF4D), 0xFF);
+        assert!(!bus.switch_speed());
+    }
+
+    #[test]
+    fn test_general_vram_dma() {
+        let mut bus = cgb_bus();
+        bus.write_byte(0xFF40, 0x00);
+        for offset in 0..0x20 {
+            bus.write_byte(0xC100 + offset, offset as u8 + 1);
+        }
+        bus.write_byte(0xFF4F, 0x01);
+        bus.write_byte(0xFF51, 0xC1);
+        bus.write_byte(0xFF52, 0x00);
+        bus.write_byte(0xFF53, 0x10);
+        bus.write_byte(0xFF54, 0x00);
+        bus.write_byte(0xFF55, 0x01);
+        assert_eq!(bus.read_byte(0xFF55), 0xFF);
+        assert_eq!(bus.take_dma_stall(), 16);
+        assert_eq!(bus.read_byte(0x9000), 0x01);
+        assert_eq!(bus.read_byte(0x901F), 0x20);
+        bus.write_byte(0xFF4F, 0x00);
+        assert_eq!(bus.read_byte(0x9000), 0x00);
+
+        // the same copy takes twice the machine cycles in double speed
+        bus.write_byte(0xFF4D, 0x01);
+        bus.switch_speed();
+        bus.write_byte(0xFF55, 0x00);
+        assert_eq!(bus.take_dma_stall(), 16);
+    }
+
+    #[test]
+    fn test_hblank_vram_dma() {
+        let mut bus = cgb_bus();
+        for offset in 0..0x30 {
+            bus.write_byte(0xC000 + offset, 0x42);
+        }
+        bus.write_byte(0xFF40, 0x00);
+        bus.write_byte(0xFF40, 0x91);
+        bus.write_byte(0xFF51, 0xC0);
+        bus.write_byte(0xFF52, 0x00);
+        bus.write_byte(0xFF53, 0x00);
+        bus.write_byte(0xFF54, 0x00);
+        bus.write_byte(0xFF55, 0x82);
+        assert_eq!(bus.read_byte(0xFF55), 0x02);
+
+        // one block at the start of the first HBlank
+        bus.tick(63);
+        assert_eq!(bus.take_dma_stall(), 8);
+        assert_eq!(bus.read_byte(0xFF55), 0x01);
+        assert_eq!(bus.read_byte(0x800F), 0x42);
+        assert_eq!(bus.read_byte(0x8010), 0x00);
+
+        // stopped before the next HBlank
+        bus.write_byte(0xFF55, 0x00);
+        assert_eq!(bus.read_byte(0xFF55), 0x81);
+        bus.tick(114);
+        assert_eq!(bus.take_dma_stall(), 0);
+        assert_eq!(bus.read_byte(0x8010), 0x00);
+    }
+
+    #[test]
+    fn test_cgb_boot_rom_selects_mode() {
+        let mut bus = test_bus();
+        bus.load_boot_rom(vec![0; 0x900]);
+        assert_eq!(bus.read_byte(0xFF70), 0xF8);
+        // the boot ROM drops to DMG mode for a DMG game
+        bus.write_byte(0xFF4C, 0x04);
+        assert_eq!(bus.read_byte(0xFF70), 0xFF);
+        bus.write_byte(0xFF50, 0x01);
+        bus.write_byte(0xFF4C, 0x80);
+        assert_eq!(bus.read_byte(0xFF70), 0xFF);
+
+        // the DMG boot ROM never enters CGB mode
+        let mut bus = test_bus();
+        bus.load_boot_rom(vec![0; 0x100]);
+        assert_eq!(bus.read_byte(0xFF70), 0xFF);
+    }
+
+    #[test]
+    fn test_cgb_boot_rom_overlay() {
+        let mut bus = test_bus();
+        bus.load_boot_rom(vec![0xAA; 0x900]);
+        assert_eq!(bus.read_byte(0x0000), 0xAA);
+        // the cartridge header shows through the gap
+        assert_eq!(bus.read_byte(0x0100), 0x22);
+        assert_eq!(bus.read_byte(0x0200), 0xAA);
+        assert_eq!(bus.read_byte(0x0900), 0x00);
+
+        bus.write_byte(0xFF50, 0x00);
+        assert_eq!(bus.read_byte(0x0000), 0xAA);
+        bus.write_byte(0xFF50, 0x11);
+        assert_eq!(bus.read_byte(0x0000), 0x11);
+        assert_eq!(bus.read_byte(0x0200), 0x33);
+        // there is no way back short of a reset
+        bus.write_byte(0xFF50, 0x00);
+        assert_eq!(bus.read_byte(0x0000), 0x11);
+        bus.reset();
+        assert_eq!(bus.read_byte(0x0000), 0xAA);
+    }
+
+    #[test]
+    fn test_oam_dma() {
+        let mut bus = test_bus();
+        for offset in 0..0xA0 {
+            bus.write_byte(0xC100 + offset, offset as u8);
+        }
+        bus.write_byte(0xFF46, 0xC1);
+        assert_eq!(bus.read_byte(0xFF46), 0xC1);
+        bus.tick(2);
+        // the CPU sees the byte in flight on the external bus and nothing in OAM
+        assert_eq!(bus.read_byte(0x0000), 0x01);
+        assert_eq!(bus.read_byte(0xC000), 0x01);
+        assert_eq!(bus.read_byte(0xFE00), 0xFF);
+        bus.write_byte(0xC000, 0x42);
+        bus.write_byte(0xFF80, 0x42);
+        assert_eq!(bus.read_byte(0xFF80), 0x42);
+
+        bus.tick(159);
+        assert_eq!(bus.read_byte(0xC000), 0x00);
+        assert_eq!(bus.read_byte(0xFE00), 0x00);
+        assert_eq!(bus.read_byte(0xFE9F), 0x9F);
+    }
+
+    #[test]
+    fn test_every_address() {
+        // fixed bit patterns and pseudo random bytes from a few seeds
+        let mut patterns: Vec<Vec<u8>> = [0x00, 0xFF, 0x55, 0xAA]
+            .iter()
+            .map(|val| vec![*val; 0x10000])
+            .collect();
+        for seed in [1u32, 0xBEEF, 0x1234_5678] {
+            let mut state = seed;
+            patterns.push(
+                (0..0x10000)
+                    .map(|_| {
+                        state = state.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+                        (state >> 16) as u8
+                    })
+                    .collect(),
+            );
+        }
+
+        for values in patterns {
+            let mut bus = test_bus();
+            for addr in 0x0000..=0xFFFF {
+                bus.write_byte(addr, values[addr as usize]);
+                bus.read_byte(addr);
+            }
+            // echo RAM writes land in work RAM after the direct ones
+            let expected = |addr: u16| match addr {
+                0xC000..=0xDDFF => values[addr as usize + 0x2000],
+                _ => values[addr as usize],
+            };
+            let regions = [0x8000..=0x9FFF, 0xC000..=0xDFFF, 0xFF80..=0xFFFE];
+            for addr in regions.into_iter().flatten() {
+                assert_eq!(bus.read_byte(addr), expected(addr), "{:04X}", addr);
+            }
+            for addr in [0x9FFF, 0xDFFF, 0xFFFE] {
+                assert_eq!(bus.read_byte(addr), values[addr as usize], "{:04X}", addr);
+            }
+        }
+    }
+
+    #[test]
+    fn test_ram_regions() {
+        let mut bus = test_bus();
+        let pattern = |addr: u16| (addr ^ (addr >> 8)) as u8;
+        let regions = [0x8000..=0x9FFF, 0xC000..=0xDFFF, 0xFF80..=0xFFFE];
+        for addr in regions.clone().into_iter().flatten() {
+            bus.write_byte(addr, pattern(addr));
+        }
+        for addr in regions.into_iter().flatten() {
+            assert_eq!(bus.read_byte(addr), pattern(addr), "{:04X}", addr);
+        }
+        // unused IO registers read back what was written
+        bus.write_byte(0xFF7F, 0x42);
+        assert_eq!(bus.read_byte(0xFF7F), 0x42);
+    }
+
+    #[test]
+    fn test_echo_ram() {
+        let mut bus = test_bus();
+        for addr in 0xE000..=0xFDFF {
+            bus.write_byte(addr, addr as u8);
+        }
+        for addr in 0xC000..=0xDDFF {
+            assert_eq!(bus.read_byte(addr), addr as u8);
+            assert_eq!(bus.read_byte(addr + 0x2000), addr as u8);
+        }
+        // the mirror ends at 0xFDFF, the last 512 bytes of work RAM have none
+        bus.write_byte(0xDFFF, 0x42);
+        assert_eq!(bus.read_byte(0xDFFF), 0x42);
+        assert_eq!(bus.read_byte(0xFDFF), bus.read_byte(0xDDFF));
+    }
+
+    #[test]
+    fn test_skip_boot() {
+        let mut bus = test_bus();
+        bus.skip_boot(Model::Dmg);
+        assert_eq!(bus.read_byte(0xFF04), 0xAB);
+        assert_eq!(bus.read_byte(0xFF07), 0xF8);
+        assert_eq!(bus.read_byte(0xFF0F), 0xE1);
+        assert_eq!(bus.read_byte(0xFF24), 0x77);
+        assert_eq!(bus.read_byte(0xFF25), 0xF3);
+        assert_eq!(bus.read_byte(0xFF26) & 0x80, 0x80);
+        assert_eq!(bus.read_byte(0xFF40), 0x91);
+        assert_eq!(bus.read_byte(0xFF47), 0xFC);
+        assert_eq!(bus.read_byte(0xFF02), 0x7E);
+    }
+
+    #[test]
+    fn test_skip_boot_cgb() {
+        let bus = cgb_bus();
+        assert_eq!(bus.read_byte(0xFF04), 0x1E);
+        assert_eq!(bus.read_byte(0xFF02), 0x7F);
+        assert_eq!(bus.read_byte(0xFF07), 0xF8);
+        assert_eq!(bus.read_byte(0xFF40), 0x91);
+        assert_eq!(bus.read_byte(0xFF4D), 0x7E);
+        assert_eq!(bus.read_byte(0xFF4F), 0xFE);
+        assert_eq!(bus.read_byte(0xFF55), 0xFF);
+        assert_eq!(bus.read_byte(0xFF68), 0xC0);
+        assert_eq!(bus.read_byte(0xFF6A), 0xC0);
+        assert_eq!(bus.read_byte(0xFF70), 0xF8);
+
+        // a DMG game only sees the DMG registers
+        let mut bus = test_bus();
+        bus.skip_boot(Model::Cgb);
+        assert_eq!(bus.read_byte(0xFF02), 0x7F);
+        assert_eq!(bus.read_byte(0xFF4D), 0xFF);
+        assert_eq!(bus.read_byte(0xFF70), 0xFF);
     }
 }
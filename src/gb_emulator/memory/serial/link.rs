@@ -0,0 +1,319 @@
+use std::io::{self, ErrorKind, Read, Write};
+use std::net::{TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::{Arc, Mutex};
+
+use log::warn;
+
+// one end of a link cable, bytes are exchanged whole: the end with the internal clock shifts
+// its byte out and the byte of the other end in, the other end sees the transfer complete on
+// its external clock
+pub trait LinkCable {
+    // internal clock: returns the byte shifted in for `val`, None if the other end is not
+    // waiting for a transfer, which reads as a disconnected cable
+    fn exchange(&mut self, val: u8) -> Option<u8>;
+
+    // external clock: called while waiting for the other end with the byte to answer with,
+    // returns the byte the other end shifted in
+    fn poll(&mut self, val: u8) -> Option<u8>;
+}
+
+// the serial output wired to its own input, every byte comes back as it was sent
+pub struct LoopbackCable;
+
+impl LinkCable for LoopbackCable {
+    fn exchange(&mut self, val: u8) -> Option<u8> {
+        Some(val)
+    }
+
+    // nothing else drives the clock
+    fn poll(&mut self, _val: u8) -> Option<u8> {
+        None
+    }
+}
+
+// records every byte the game sends without anything plugged in on the other end, test ROMs
+// like Blargg's report their results this way, clones share the recorded bytes
+#[derive(Clone, Default)]
+pub struct SerialCapture {
+    bytes: Arc<Mutex<Vec<u8>>>,
+}
+
+impl SerialCapture {
+    pub fn new() -> SerialCapture {
+        SerialCapture::default()
+    }
+
+    pub fn bytes(&self) -> Vec<u8> {
+        self.bytes.lock().unwrap().clone()
+    }
+
+    // the bytes sent so far as text
+    pub fn output(&self) -> String {
+        String::from_utf8_lossy(&self.bytes.lock().unwrap()).into_owned()
+    }
+}
+
+impl LinkCable for SerialCapture {
+    fn exchange(&mut self, val: u8) -> Option<u8> {
+        self.bytes.lock().unwrap().push(val);
+        None
+    }
+
+    fn poll(&mut self, _val: u8) -> Option<u8> {
+        None
+    }
+}
+
+// what one end offers the other, shared by both in-process and TCP cables
+#[derive(Default)]
+struct End {
+    // the byte a waiting external clock transfer answers with
+    waiting: Option<u8>,
+    // the byte the other end shifted in
+    received: Option<u8>,
+}
+
+impl End {
+    fn poll(&mut self, val: u8) -> Option<u8> {
+        match self.received.take() {
+            Some(byte) => {
+                self.waiting = None;
+                Some(byte)
+            }
+            None => {
+                self.waiting = Some(val);
+                None
+            }
+        }
+    }
+}
+
+// connects two emulators of the same process, they can run on different threads
+pub struct InProcessCable {
+    ends: Arc<Mutex<[End; 2]>>,
+    side: usize,
+}
+
+impl InProcessCable {
+    // both ends of a new cable
+    pub fn pair() -> (InProcessCable, InProcessCable) {
+        let ends = Arc::new(Mutex::new([End::default(), End::default()]));
+        (
+            InProcessCable {
+                ends: ends.clone(),
+                side: 0,
+            },
+            InProcessCable { ends, side: 1 },
+        )
+    }
+}
+
+impl LinkCable for InProcessCable {
+    fn exchange(&mut self, val: u8) -> Option<u8> {
+        let mut ends = self.ends.lock().unwrap();
+        let other = &mut ends[1 - self.side];
+        let reply = other.waiting.take()?;
+        other.received = Some(val);
+        Some(reply)
+    }
+
+    fn poll(&mut self, val: u8) -> Option<u8> {
+        self.ends.lock().unwrap()[self.side].poll(val)
+    }
+}
+
+// messages of the TCP cable, a kind byte followed by the serial byte
+const MESSAGE_WAITING: u8 = 0x00;
+const MESSAGE_DATA: u8 = 0x01;
+
+// connects two emulator processes over a TCP connection, one of them listens
+pub struct TcpCable {
+    // None after the connection broke
+    stream: Option<TcpStream>,
+    buffer: Vec<u8>,
+    // messages the socket did not take yet, whole messages only leave in order
+    outgoing: Vec<u8>,
+    // the byte the other end waits to answer with, as announced by its last message
+    other_waiting: Option<u8>,
+    end: End,
+    // the byte announced to the other end while our external clock transfer waits
+    announced: Option<u8>,
+}
+
+impl TcpCable {
+    // wait for the other emulator to connect
+    pub fn listen(addr: impl ToSocketAddrs) -> io::Result<TcpCable> {
+        let (stream, _) = TcpListener::bind(addr)?.accept()?;
+        TcpCable::new(stream)
+    }
+
+    pub fn connect(addr: impl ToSocketAddrs) -> io::Result<TcpCable> {
+        TcpCable::new(TcpStream::connect(addr)?)
+    }
+
+    fn new(stream: TcpStream) -> io::Result<TcpCable> {
+        stream.set_nodelay(true)?;
+        stream.set_nonblocking(true)?;
+        Ok(TcpCable {
+            stream: Some(stream),
+            buffer: Vec::new(),
+            outgoing: Vec::new(),
+            other_waiting: None,
+            end: End::default(),
+            announced: None,
+        })
+    }
+
+    fn disconnect(&mut self, err: io::Error) {
+        warn!("Link cable disconnected: {}", err);
+        self.stream = None;
+        self.other_waiting = None;
+        self.outgoing.clear();
+    }
+
+    fn send(&mut self, kind: u8, val: u8) {
+        self.outgoing.extend_from_slice(&[kind, val]);
+        self.flush_outgoing();
+    }
+
+    // write as much of the queued messages as the socket takes without blocking, a slow
+    // other end just leaves the rest for later
+    fn flush_outgoing(&mut self) {
+        while let Some(stream) = &mut self.stream {
+            if self.outgoing.is_empty() {
+                break;
+            }
+            match stream.write(&self.outgoing) {
+                Ok(0) => self.disconnect(ErrorKind::WriteZero.into()),
+                Ok(len) => {
+                    self.outgoing.drain(..len);
+                }
+                Err(err) if err.kind() == ErrorKind::WouldBlock => break,
+                Err(err) if err.kind() == ErrorKind::Interrupted => continue,
+                Err(err) => self.disconnect(err),
+            }
+        }
+    }
+
+    // pick up the messages that arrived without blocking
+    fn receive(&mut self) {
+        self.flush_outgoing();
+        let mut data = [0; 1024];
+        while let Some(stream) = &mut self.stream {
+            match stream.read(&mut data) {
+                Ok(0) => self.disconnect(ErrorKind::UnexpectedEof.into()),
+                Ok(len) => self.buffer.extend_from_slice(&data[..len]),
+                Err(err) if err.kind() == ErrorKind::WouldBlock => break,
+                Err(err) if err.kind() == ErrorKind::Interrupted => continue,
+                Err(err) => self.disconnect(err),
+            }
+        }
+        let messages = self.buffer.len() / 2 * 2;
+        for message in self.buffer.drain(..messages).collect::<Vec<_>>().chunks(2) {
+            match message[0] {
+                MESSAGE_WAITING => self.other_waiting = Some(message[1]),
+                MESSAGE_DATA => self.end.received = Some(message[1]),
+                kind => warn!("unknown link cable message {:02X}", kind),
+            }
+        }
+    }
+}
+
+impl LinkCable for TcpCable {
+    fn exchange(&mut self, val: u8) -> Option<u8> {
+        self.receive();
+        let reply = self.other_waiting.take()?;
+        self.send(MESSAGE_DATA, val);
+        Some(reply)
+    }
+
+    fn poll(&mut self, val: u8) -> Option<u8> {
+        self.receive();
+        let received = self.end.poll(val);
+        if received.is_some() {
+            self.announced = None;
+        } else if self.announced != Some(val) {
+            // the other end learns about the waiting transfer and the byte it answers with
+            self.send(MESSAGE_WAITING, val);
+            self.announced = Some(val);
+        }
+        received
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_capture() {
+        let capture = SerialCapture::new();
+        let mut cable = capture.clone();
+        for byte in b"ok\n" {
+            assert_eq!(cable.exchange(*byte), None);
+        }
+        assert_eq!(capture.output(), "ok\n");
+        assert_eq!(capture.bytes(), b"ok\n");
+    }
+
+    #[test]
+    fn test_in_process() {
+        let (mut first, mut second) = InProcessCable::pair();
+        // nobody waits on the other end
+        assert_eq!(first.exchange(0x12), None);
+        assert_eq!(second.poll(0x34), None);
+        assert_eq!(first.exchange(0x12), Some(0x34));
+        assert_eq!(second.poll(0x34), Some(0x12));
+        assert_eq!(first.exchange(0x56), None);
+    }
+
+    #[test]
+    fn test_tcp() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let mut first = TcpCable::connect(addr).unwrap();
+        let mut second = TcpCable::new(listener.accept().unwrap().0).unwrap();
+
+        assert_eq!(second.poll(0x34), None);
+        // messages take a moment to travel
+        let reply = (0..1000).find_map(|_| {
+            std::thread::sleep(std::time::Duration::from_millis(1));
+            first.exchange(0x12)
+        });
+        assert_eq!(reply, Some(0x34));
+        let received = (0..1000).find_map(|_| {
+            std::thread::sleep(std::time::Duration::from_millis(1));
+            second.poll(0x34)
+        });
+        assert_eq!(received, Some(0x12));
+
+        // a full send buffer keeps the messages queued instead of dropping the connection
+        let mut sent = 0u32;
+        while first.outgoing.is_empty() {
+            for _ in 0..0x10000 {
+                first.send(MESSAGE_DATA, sent as u8);
+                sent += 1;
+            }
+        }
+        first.send(MESSAGE_WAITING, 0x56);
+        assert!(first.stream.is_some());
+        let drained = (0..10000).any(|_| {
+            second.receive();
+            first.receive();
+            first.outgoing.is_empty() && second.other_waiting.is_some()
+        });
+        assert!(drained);
+        assert!(second.stream.is_some());
+        // the messages arrived whole and in order
+        assert_eq!(second.end.received, Some((sent - 1) as u8));
+        assert_eq!(second.other_waiting, Some(0x56));
+
+        drop(first);
+        let disconnected = (0..1000).any(|_| {
+            std::thread::sleep(std::time::Duration::from_millis(1));
+            second.poll(0x00);
+            second.stream.is_none()
+        });
+        assert!(disconnected);
+    }
+}
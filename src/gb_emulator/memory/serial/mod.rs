@@ -0,0 +1,208 @@
+mod link;
+
+pub use link::{InProcessCable, LinkCable, LoopbackCable, SerialCapture, TcpCable};
+
+// clock cycles per shifted bit with the internal clock, 8192 Hz and 262144 Hz with the CGB
+// fast clock, both scale with double speed mode
+const CYCLES_PER_BIT: u32 = 512;
+const CYCLES_PER_BIT_FAST: u32 = 16;
+
+// the serial controller behind SB (0xFF01) and SC (0xFF02), the bytes travel through the
+// plugged in link cable
+pub struct Serial {
+    sb: u8,
+    // transfer running
+    active: bool,
+    internal_clock: bool,
+    // CGB only, SC bit 1
+    fast_clock: bool,
+    cgb: bool,
+    // bits left to shift out and the byte they are replaced with
+    bits: u8,
+    incoming: u8,
+    // clock cycles until the next bit with the internal clock
+    countdown: u32,
+    cable: Option<Box<dyn LinkCable>>,
+}
+
+impl Serial {
+    pub fn new() -> Serial {
+        Serial {
+            sb: 0,
+            active: false,
+            internal_clock: false,
+            fast_clock: false,
+            cgb: false,
+            bits: 0,
+            incoming: 0,
+            countdown: 0,
+            cable: None,
+        }
+    }
+
+    pub fn set_cgb_mode(&mut self, cgb: bool) {
+        self.cgb = cgb;
+        self.fast_clock &= cgb;
+    }
+
+    pub fn set_link_cable(&mut self, cable: Box<dyn LinkCable>) {
+        self.cable = Some(cable);
+    }
+
+    // the cable stays plugged in when the emulator resets
+    pub fn reset(&mut self) {
+        let cable = self.cable.take();
+        *self = Serial::new();
+        self.cable = cable;
+    }
+
+    pub fn read_byte(&self, addr: u16) -> u8 {
+        match addr {
+            0xFF01 => self.sb,
+            0xFF02 => {
+                let unused = if self.cgb { 0x7C } else { 0x7E };
+                (self.active as u8) << 7
+                    | unused
+                    | (self.fast_clock as u8) << 1
+                    | self.internal_clock as u8
+            }
+            _ => panic!("access to serial in non mapped memory space: {:X}", addr),
+        }
+    }
+
+    pub fn write_byte(&mut self, addr: u16, val: u8) {
+        match addr {
+            0xFF01 => self.sb = val,
+            0xFF02 => {
+                self.active = val & 0x80 != 0;
+                self.internal_clock = val & 0x01 != 0;
+                self.fast_clock = self.cgb && val & 0x02 != 0;
+                if self.active && self.internal_clock {
+                    self.start_transfer();
+                }
+            }
+            _ => panic!("access to serial in non mapped memory space: {:X}", addr),
+        }
+    }
+
+    // the byte of the other end is known as soon as the transfer starts, it is shifted in
+    // bit by bit over the transfer, nothing plugged in reads as all ones
+    fn start_transfer(&mut self) {
+        let sb = self.sb;
+        self.incoming = self
+            .cable
+            .as_mut()
+            .and_then(|cable| cable.exchange(sb))
+            .unwrap_or(0xFF);
+        self.bits = 8;
+        self.countdown = self.cycles_per_bit();
+    }
+
+    fn cycles_per_bit(&self) -> u32 {
+        if self.fast_clock {
+            CYCLES_PER_BIT_FAST
+        } else {
+            CYCLES_PER_BIT
+        }
+    }
+
+    // advance by the given amount of clock cycles, returns true if the serial interrupt fired
+    pub fn tick(&mut self, cycles: u32) -> bool {
+        if !self.active {
+            return false;
+        }
+        if !self.internal_clock {
+            // the other end drives the clock and delivers its byte in one go
+            let sb = self.sb;
+            return match self.cable.as_mut().and_then(|cable| cable.poll(sb)) {
+                Some(byte) => {
+                    self.sb = byte;
+                    self.active = false;
+                    true
+                }
+                None => false,
+            };
+        }
+
+        let mut cycles = cycles;
+        while cycles >= self.countdown {
+            cycles -= self.countdown;
+            self.countdown = self.cycles_per_bit();
+            self.bits -= 1;
+            self.sb = self.sb << 1 | (self.incoming >> self.bits) & 0x01;
+            if self.bits == 0 {
+                self.active = false;
+                return true;
+            }
+        }
+        self.countdown -= cycles;
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_registers() {
+        let mut serial = Serial::new();
+        assert_eq!(serial.read_byte(0xFF02), 0x7E);
+        serial.write_byte(0xFF02, 0x03);
+        // the fast clock only exists on the CGB
+        assert_eq!(serial.read_byte(0xFF02), 0x7F);
+        serial.set_cgb_mode(true);
+        assert_eq!(serial.read_byte(0xFF02), 0x7D);
+        serial.write_byte(0xFF02, 0x02);
+        assert_eq!(serial.read_byte(0xFF02), 0x7E);
+    }
+
+    #[test]
+    fn test_internal_clock() {
+        let mut serial = Serial::new();
+        serial.set_link_cable(Box::new(LoopbackCable));
+        serial.write_byte(0xFF01, 0xA5);
+        serial.write_byte(0xFF02, 0x81);
+        assert!(!serial.tick(CYCLES_PER_BIT * 4));
+        // half of the byte came back
+        assert_eq!(serial.read_byte(0xFF01), 0x5A);
+        assert_eq!(serial.read_byte(0xFF02), 0xFF);
+        assert!(!serial.tick(CYCLES_PER_BIT * 4 - 1));
+        assert!(serial.tick(1));
+        assert_eq!(serial.read_byte(0xFF01), 0xA5);
+        assert_eq!(serial.read_byte(0xFF02), 0x7F);
+    }
+
+    #[test]
+    fn test_disconnected() {
+        let mut serial = Serial::new();
+        serial.set_cgb_mode(true);
+        serial.write_byte(0xFF01, 0x12);
+        serial.write_byte(0xFF02, 0x83);
+        assert!(serial.tick(CYCLES_PER_BIT_FAST * 8));
+        assert_eq!(serial.read_byte(0xFF01), 0xFF);
+
+        // an external clock transfer waits forever
+        serial.write_byte(0xFF02, 0x80);
+        assert!(!serial.tick(CYCLES_PER_BIT * 100));
+        assert_eq!(serial.read_byte(0xFF02), 0xFC);
+    }
+
+    #[test]
+    fn test_linked() {
+        let (first, second) = InProcessCable::pair();
+        let mut master = Serial::new();
+        let mut slave = Serial::new();
+        master.set_link_cable(Box::new(first));
+        slave.set_link_cable(Box::new(second));
+        slave.write_byte(0xFF01, 0x34);
+        slave.write_byte(0xFF02, 0x80);
+        assert!(!slave.tick(4));
+        master.write_byte(0xFF01, 0x12);
+        master.write_byte(0xFF02, 0x81);
+        assert!(slave.tick(4));
+        assert_eq!(slave.read_byte(0xFF01), 0x12);
+        assert!(master.tick(CYCLES_PER_BIT * 8));
+        assert_eq!(master.read_byte(0xFF01), 0x34);
+    }
+}
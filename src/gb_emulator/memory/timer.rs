@@ -0,0 +1,241 @@
+// bit of the internal divider that clocks TIMA for each TAC frequency setting
+const TAC_DIVIDER_BITS: [u16; 4] = [9, 3, 5, 7];
+
+// falling edges of this divider bit (bit 4 of DIV) clock the APU frame sequencer, in CGB
+// double speed mode the next bit keeps the sequencer at 512 Hz
+const APU_DIVIDER_BIT: u16 = 12;
+const APU_DIVIDER_BIT_DOUBLE_SPEED: u16 = 13;
+
+pub struct Timer {
+    // the 16 bit internal divider, DIV exposes the upper byte
+    counter: u16,
+    tima: u8,
+    tma: u8,
+    tac: u8,
+    // cycles left until TIMA is reloaded from TMA after an overflow
+    reload_delay: u8,
+    // frame sequencer clocks not yet picked up by the APU
+    apu_clocks: u32,
+    apu_divider_bit: u16,
+}
+
+impl Timer {
+    pub fn new() -> Timer {
+        Timer {
+            counter: 0,
+            tima: 0,
+            tma: 0,
+            tac: 0,
+            reload_delay: 0,
+            apu_clocks: 0,
+            apu_divider_bit: APU_DIVIDER_BIT,
+        }
+    }
+
+    pub fn set_double_speed(&mut self, double_speed: bool) {
+        self.apu_divider_bit = if double_speed {
+            APU_DIVIDER_BIT_DOUBLE_SPEED
+        } else {
+            APU_DIVIDER_BIT
+        };
+    }
+
+    // set the internal divider to the value the boot ROM leaves behind
+    pub fn set_counter(&mut self, counter: u16) {
+        self.counter = counter;
+    }
+
+    // advance by the given amount of clock cycles, returns true if the timer interrupt fired
+    pub fn tick(&mut self, cycles: u32) -> bool {
+        let mut interrupt = false;
+        for _ in 0..cycles {
+            interrupt |= self.step();
+        }
+        interrupt
+    }
+
+    fn step(&mut self) -> bool {
+        let mut interrupt = false;
+        if self.reload_delay > 0 {
+            self.reload_delay -= 1;
+            if self.reload_delay == 0 {
+                self.tima = self.tma;
+                interrupt = true;
+            }
+        }
+
+        let old_signal = self.signal();
+        let old_counter = self.counter;
+        self.counter = self.counter.wrapping_add(1);
+        if old_signal && !self.signal() {
+            self.increment_tima();
+        }
+        self.check_apu_edge(old_counter);
+        interrupt
+    }
+
+    fn check_apu_edge(&mut self, old_counter: u16) {
+        let mask = 1 << self.apu_divider_bit;
+        if old_counter & mask != 0 && self.counter & mask == 0 {
+            self.apu_clocks += 1;
+        }
+    }
+
+    // number of frame sequencer clocks since the last call
+    pub fn take_apu_clocks(&mut self) -> u32 {
+        std::mem::take(&mut self.apu_clocks)
+    }
+
+    // TIMA is clocked by the falling edge of the selected divider bit ANDed with the enable bit
+    fn signal(&self) -> bool {
+        let bit = TAC_DIVIDER_BITS[(self.tac & 0x03) as usize];
+        self.tac & 0x04 != 0 && self.counter & (1 << bit) != 0
+    }
+
+    fn increment_tima(&mut self) {
+        let (tima, overflow) = self.tima.overflowing_add(1);
+        self.tima = tima;
+        if overflow {
+            // TIMA stays 0x00 for one machine cycle before the reload
+            self.reload_delay = 4;
+        }
+    }
+
+    pub fn read_byte(&self, addr: u16) -> u8 {
+        match addr {
+            0xFF04 => (self.counter >> 8) as u8,
+            0xFF05 => self.tima,
+            0xFF06 => self.tma,
+            0xFF07 => self.tac | 0xF8,
+            _ => panic!("access to timer in non mapped memory space: {:X}", addr),
+        }
+    }
+
+    pub fn write_byte(&mut self, addr: u16, val: u8) {
+        let old_signal = self.signal();
+        let old_counter = self.counter;
+        match addr {
+            // resetting the divider can produce a falling edge and increment TIMA
+            0xFF04 => self.counter = 0,
+            0xFF05 => {
+                // writing TIMA during the delay cancels the reload
+                self.reload_delay = 0;
+                self.tima = val;
+            }
+            0xFF06 => self.tma = val,
+            0xFF07 => self.tac = val & 0x07,
+            _ => panic!("access to timer in non mapped memory space: {:X}", addr),
+        }
+        if old_signal && !self.signal() {
+            self.increment_tima();
+        }
+        self.check_apu_edge(old_counter);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_div() {
+        let mut timer = Timer::new();
+        timer.tick(255);
+        assert_eq!(timer.read_byte(0xFF04), 0x00);
+        timer.tick(1);
+        assert_eq!(timer.read_byte(0xFF04), 0x01);
+        timer.tick(256 * 0xFF);
+        assert_eq!(timer.read_byte(0xFF04), 0x00);
+
+        timer.tick(512);
+        timer.write_byte(0xFF04, 0x42);
+        assert_eq!(timer.read_byte(0xFF04), 0x00);
+    }
+
+    #[test]
+    fn test_tima_frequencies() {
+        for (tac, period) in [(0x04, 1024), (0x05, 16), (0x06, 64), (0x07, 256)] {
+            let mut timer = Timer::new();
+            timer.write_byte(0xFF07, tac);
+            timer.tick(period - 1);
+            assert_eq!(timer.read_byte(0xFF05), 0x00, "TAC {:02X}", tac);
+            timer.tick(1);
+            assert_eq!(timer.read_byte(0xFF05), 0x01, "TAC {:02X}", tac);
+            timer.tick(period * 4);
+            assert_eq!(timer.read_byte(0xFF05), 0x05, "TAC {:02X}", tac);
+        }
+    }
+
+    #[test]
+    fn test_tima_disabled() {
+        let mut timer = Timer::new();
+        timer.write_byte(0xFF07, 0x01);
+        timer.tick(4096);
+        assert_eq!(timer.read_byte(0xFF05), 0x00);
+        assert_eq!(timer.read_byte(0xFF07), 0xF9);
+    }
+
+    #[test]
+    fn test_overflow_reload() {
+        let mut timer = Timer::new();
+        timer.write_byte(0xFF06, 0xAB);
+        timer.write_byte(0xFF05, 0xFF);
+        timer.write_byte(0xFF07, 0x05);
+
+        assert!(!timer.tick(16));
+        assert_eq!(timer.read_byte(0xFF05), 0x00);
+        assert!(!timer.tick(3));
+        assert_eq!(timer.read_byte(0xFF05), 0x00);
+        assert!(timer.tick(1));
+        assert_eq!(timer.read_byte(0xFF05), 0xAB);
+    }
+
+    #[test]
+    fn test_overflow_reload_cancelled() {
+        let mut timer = Timer::new();
+        timer.write_byte(0xFF06, 0xAB);
+        timer.write_byte(0xFF05, 0xFF);
+        timer.write_byte(0xFF07, 0x05);
+
+        timer.tick(17);
+        timer.write_byte(0xFF05, 0x10);
+        assert!(!timer.tick(8));
+        assert_eq!(timer.read_byte(0xFF05), 0x10);
+    }
+
+    #[test]
+    fn test_div_write_glitch() {
+        let mut timer = Timer::new();
+        timer.write_byte(0xFF07, 0x05);
+        // bit 3 of the divider is set, resetting it is a falling edge
+        timer.tick(8);
+        timer.write_byte(0xFF04, 0);
+        assert_eq!(timer.read_byte(0xFF05), 0x01);
+
+        // bit 3 is clear, no increment
+        timer.tick(4);
+        timer.write_byte(0xFF04, 0);
+        assert_eq!(timer.read_byte(0xFF05), 0x01);
+    }
+
+    #[test]
+    fn test_tac_write_glitch() {
+        let mut timer = Timer::new();
+        timer.write_byte(0xFF07, 0x05);
+        timer.tick(8);
+        timer.write_byte(0xFF07, 0x00);
+        assert_eq!(timer.read_byte(0xFF05), 0x01);
+    }
+
+    #[test]
+    fn test_apu_clocks() {
+        let mut timer = Timer::new();
+        timer.tick(8192 * 3 - 1);
+        assert_eq!(timer.take_apu_clocks(), 2);
+        assert_eq!(timer.take_apu_clocks(), 0);
+
+        // resetting DIV while bit 4 is set clocks the frame sequencer early
+        timer.write_byte(0xFF04, 0);
+        assert_eq!(timer.take_apu_clocks(), 1);
+    }
+}
@@ -0,0 +1,131 @@
+// bytes copied per block, one block per HBlank for HBlank DMA
+pub const BLOCK_SIZE: u16 = 0x10;
+// destinations past the end of VRAM end the transfer
+const VRAM_SIZE: u16 = 0x2000;
+
+// CGB DMA from ROM or RAM into VRAM, programmed through HDMA1-HDMA5 (0xFF51-0xFF55)
+pub struct VramDma {
+    source: u16,
+    // offset into VRAM
+    destination: u16,
+    // blocks left to copy, zero when the last transfer completed
+    remaining: u8,
+    active: bool,
+    // copy one block per HBlank instead of everything at once
+    hblank: bool,
+}
+
+impl VramDma {
+    pub fn new() -> VramDma {
+        VramDma {
+            source: 0,
+            destination: 0,
+            remaining: 0,
+            active: false,
+            hblank: false,
+        }
+    }
+
+    // only HDMA5 is readable, it holds the blocks left minus one and bit 7 set while idle
+    pub fn read_byte(&self, addr: u16) -> u8 {
+        match addr {
+            0xFF55 => {
+                let idle = if self.active { 0x00 } else { 0x80 };
+                idle | (self.remaining.wrapping_sub(1) & 0x7F)
+            }
+            _ => 0xFF,
+        }
+    }
+
+    pub fn write_byte(&mut self, addr: u16, val: u8) {
+        match addr {
+            0xFF51 => self.source = (val as u16) << 8 | (self.source & 0x00FF),
+            0xFF52 => self.source = (self.source & 0xFF00) | (val & 0xF0) as u16,
+            0xFF53 => self.destination = ((val & 0x1F) as u16) << 8 | (self.destination & 0x00FF),
+            0xFF54 => self.destination = (self.destination & 0xFF00) | (val & 0xF0) as u16,
+            // clearing bit 7 while an HBlank transfer runs stops it instead of starting one
+            0xFF55 if self.active && self.hblank && val & 0x80 == 0 => self.active = false,
+            0xFF55 => {
+                self.remaining = (val & 0x7F) + 1;
+                self.active = true;
+                self.hblank = val & 0x80 != 0;
+            }
+            _ => panic!("access to vram dma in non mapped memory space: {:X}", addr),
+        }
+    }
+
+    // a general purpose transfer waiting to be copied in one go
+    pub fn general_pending(&self) -> bool {
+        self.active && !self.hblank
+    }
+
+    pub fn hblank_active(&self) -> bool {
+        self.active && self.hblank
+    }
+
+    // source address and VRAM offset of the next block, advancing past it
+    pub fn next_block(&mut self) -> Option<(u16, u16)> {
+        if !self.active {
+            return None;
+        }
+        let block = (self.source, self.destination);
+        self.source = self.source.wrapping_add(BLOCK_SIZE);
+        self.destination += BLOCK_SIZE;
+        self.remaining -= 1;
+        if self.destination >= VRAM_SIZE {
+            self.destination &= VRAM_SIZE - 1;
+            self.remaining = 0;
+        }
+        self.active = self.remaining > 0;
+        Some(block)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_registers() {
+        let mut dma = VramDma::new();
+        assert_eq!(dma.read_byte(0xFF55), 0xFF);
+        dma.write_byte(0xFF51, 0x12);
+        dma.write_byte(0xFF52, 0x3F);
+        dma.write_byte(0xFF53, 0xE4);
+        dma.write_byte(0xFF54, 0x5F);
+        assert_eq!(dma.read_byte(0xFF51), 0xFF);
+        dma.write_byte(0xFF55, 0x01);
+        assert!(dma.general_pending());
+        assert_eq!(dma.read_byte(0xFF55), 0x01);
+        // the low nibbles are ignored and the destination stays within VRAM
+        assert_eq!(dma.next_block(), Some((0x1230, 0x0450)));
+        assert_eq!(dma.next_block(), Some((0x1240, 0x0460)));
+        assert_eq!(dma.next_block(), None);
+        assert_eq!(dma.read_byte(0xFF55), 0xFF);
+    }
+
+    #[test]
+    fn test_hblank_cancel() {
+        let mut dma = VramDma::new();
+        dma.write_byte(0xFF55, 0x83);
+        assert!(dma.hblank_active());
+        assert!(!dma.general_pending());
+        dma.next_block();
+        assert_eq!(dma.read_byte(0xFF55), 0x02);
+        dma.write_byte(0xFF55, 0x00);
+        assert!(!dma.hblank_active());
+        assert_eq!(dma.read_byte(0xFF55), 0x82);
+        assert_eq!(dma.next_block(), None);
+    }
+
+    #[test]
+    fn test_destination_overflow() {
+        let mut dma = VramDma::new();
+        dma.write_byte(0xFF53, 0x1F);
+        dma.write_byte(0xFF54, 0xE0);
+        dma.write_byte(0xFF55, 0x7F);
+        assert_eq!(dma.next_block(), Some((0x0000, 0x1FE0)));
+        assert_eq!(dma.next_block(), Some((0x0010, 0x1FF0)));
+        assert_eq!(dma.next_block(), None);
+    }
+}
@@ -1,8 +1,337 @@
+mod apu;
 mod cpu;
 mod memory;
+mod model;
+mod ppu;
 mod registers;
+mod sgb;
 mod utils;
 
-pub fn start_emulation() {
-    cpu::Z80CPU::new();
+use std::error::Error;
+use std::fmt;
+
+pub use apu::SAMPLE_RATE;
+pub use cpu::CpuError;
+use cpu::Z80CPU;
+pub use memory::{
+    Button, CartridgeError, CartridgeHeader, CgbSupport, Destination, InProcessCable, Licensee,
+    LinkCable, LoopbackCable, SerialCapture, TcpCable, TimeSource, CAMERA_HEIGHT, CAMERA_WIDTH,
+};
+use memory::{Cartridge, MemoryBus};
+pub use model::Model;
+pub use ppu::{SCREEN_HEIGHT, SCREEN_WIDTH};
+pub use sgb::{SGB_SCREEN_HEIGHT, SGB_SCREEN_WIDTH};
+
+// machine cycles the CPU runs during one frame of 154 scanlines
+const CYCLES_PER_FRAME: u32 = 70224 / 4;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EmulatorError {
+    Cartridge(CartridgeError),
+    Cpu(CpuError),
+    // the boot ROM image does not match the size of the boot ROM of the model
+    InvalidBootRom { expected: usize, actual: usize },
+}
+
+impl fmt::Display for EmulatorError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EmulatorError::Cartridge(err) => write!(f, "invalid cartridge: {}", err),
+            EmulatorError::Cpu(err) => write!(f, "cpu error: {}", err),
+            EmulatorError::InvalidBootRom { expected, actual } => write!(
+                f,
+                "boot ROM has {} bytes but the model expects {}",
+                actual, expected
+            ),
+        }
+    }
+}
+
+impl Error for EmulatorError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            EmulatorError::Cartridge(err) => Some(err),
+            EmulatorError::Cpu(err) => Some(err),
+            EmulatorError::InvalidBootRom { .. } => None,
+        }
+    }
+}
+
+impl From<CartridgeError> for EmulatorError {
+    fn from(err: CartridgeError) -> EmulatorError {
+        EmulatorError::Cartridge(err)
+    }
+}
+
+impl From<CpuError> for EmulatorError {
+    fn from(err: CpuError) -> EmulatorError {
+        EmulatorError::Cpu(err)
+    }
+}
+
+pub struct Emulator {
+    cpu: Z80CPU,
+    header: CartridgeHeader,
+    model: Model,
+    // without a boot ROM every reset skips straight to the cartridge
+    has_boot_rom: bool,
+}
+
+impl Emulator {
+    // skips the boot ROM, the CGB flag in the header picks between a DMG and a CGB
+    pub fn from_rom_bytes(rom: &[u8]) -> Result<Emulator, EmulatorError> {
+        let header = CartridgeHeader::parse(rom)?;
+        Emulator::new(rom, Model::for_cartridge(&header), None)
+    }
+
+    // run the given boot ROM first, or start at the cartridge entry point in the state the
+    // boot ROM of the model leaves behind
+    pub fn new(
+        rom: &[u8],
+        model: Model,
+        boot_rom: Option<&[u8]>,
+    ) -> Result<Emulator, EmulatorError> {
+        let cartridge = Cartridge::new(rom.to_vec())?;
+        let header = cartridge.header().clone();
+        let mut bus = MemoryBus::new(cartridge);
+        // the SGB only talks to games that declare support in the header
+        if model.is_sgb() && header.sgb_support {
+            bus.enable_sgb();
+        }
+        let mut cpu = Z80CPU::new(bus);
+        match boot_rom {
+            Some(boot_rom) if boot_rom.len() != model.boot_rom_size() => {
+                return Err(EmulatorError::InvalidBootRom {
+                    expected: model.boot_rom_size(),
+                    actual: boot_rom.len(),
+                });
+            }
+            Some(boot_rom) => cpu.load_boot_rom(boot_rom.to_vec()),
+            None => cpu.skip_boot(model),
+        }
+        Ok(Emulator {
+            cpu,
+            header,
+            model,
+            has_boot_rom: boot_rom.is_some(),
+        })
+    }
+
+    pub fn model(&self) -> Model {
+        self.model
+    }
+
+    pub fn header(&self) -> &CartridgeHeader {
+        &self.header
+    }
+
+    // run a single instruction, returns the machine cycles it took
+    pub fn step_instruction(&mut self) -> Result<u32, EmulatorError> {
+        Ok(self.cpu.cycle()?)
+    }
+
+    // run the CPU until the PPU enters VBlank, or for one frame worth of cycles while the LCD is off
+    pub fn step_frame(&mut self) -> Result<(), EmulatorError> {
+        // in double speed mode a frame takes twice the machine cycles
+        let frame_cycles = if self.cpu.double_speed() {
+            CYCLES_PER_FRAME * 2
+        } else {
+            CYCLES_PER_FRAME
+        };
+        let mut cycles = 0;
+        while cycles < frame_cycles {
+            cycles += self.cpu.cycle()?;
+            if self.cpu.take_frame_complete() {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    // RGBA pixels of the last rendered frame in the size screen_size returns
+    pub fn framebuffer(&self) -> &[u8] {
+        self.cpu.framebuffer()
+    }
+
+    // SCREEN_WIDTH x SCREEN_HEIGHT, or SGB_SCREEN_WIDTH x SGB_SCREEN_HEIGHT with the border
+    // of a Super Game Boy
+    pub fn screen_size(&self) -> (usize, usize) {
+        self.cpu.screen_size()
+    }
+
+    pub fn set_button(&mut self, button: Button, pressed: bool) {
+        self.cpu.set_button(button, pressed);
+    }
+
+    // plug a link cable into the serial port, replacing the previous one
+    pub fn set_link_cable(&mut self, cable: Box<dyn LinkCable>) {
+        self.cpu.set_link_cable(cable);
+    }
+
+    // interleaved stereo samples at SAMPLE_RATE produced since the last call
+    pub fn audio_samples(&mut self) -> Vec<f32> {
+        self.cpu.take_audio_samples()
+    }
+
+    // drive the cartridge real time clock from another clock than the system time
+    pub fn set_time_source(&mut self, time_source: Box<dyn TimeSource>) {
+        self.cpu.cartridge().set_time_source(time_source);
+    }
+
+    // tilt of MBC7 cartridges in g, positive x is right and positive y is down
+    pub fn set_tilt(&mut self, x: f32, y: f32) {
+        self.cpu.cartridge().set_tilt(x, y);
+    }
+
+    // infrared light received by HuC1 and HuC3 cartridges
+    pub fn set_ir_input(&mut self, light: bool) {
+        self.cpu.cartridge().set_ir_input(light);
+    }
+
+    // true while the infrared LED of the cartridge is on
+    pub fn ir_output(&mut self) -> bool {
+        self.cpu.cartridge().ir_output()
+    }
+
+    // grayscale picture of CAMERA_WIDTH x CAMERA_HEIGHT pixels seen by the Game Boy Camera
+    pub fn set_camera_image(&mut self, pixels: &[u8]) {
+        self.cpu.cartridge().set_camera_image(pixels);
+    }
+
+    // battery backed RAM and clock state in the format of .sav files, None without battery
+    pub fn save_data(&mut self) -> Option<Vec<u8>> {
+        self.cpu.cartridge().save_data()
+    }
+
+    pub fn load_save_data(&mut self, data: &[u8]) -> Result<(), EmulatorError> {
+        Ok(self.cpu.cartridge().load_save_data(data)?)
+    }
+
+    // true if the game wrote to cartridge RAM since the last call
+    pub fn take_save_dirty(&mut self) -> bool {
+        self.cpu.cartridge().take_ram_dirty()
+    }
+
+    // power cycle the console, cartridge RAM survives
+    pub fn reset(&mut self) {
+        self.cpu.reset();
+        if !self.has_boot_rom {
+            self.cpu.skip_boot(self.model);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_truncated_rom() {
+        let result = Emulator::from_rom_bytes(&[0; 0x100]);
+        assert_eq!(
+            result.err(),
+            Some(EmulatorError::Cartridge(CartridgeError::Truncated {
+                size: 0x100
+            }))
+        );
+    }
+
+    #[test]
+    fn test_step() {
+        // a ROM full of NOPs
+        let mut emulator = Emulator::from_rom_bytes(&[0; 0x8000]).unwrap();
+        assert_eq!(emulator.step_instruction(), Ok(1));
+        emulator.step_frame().unwrap();
+        assert_eq!(
+            emulator.framebuffer().len(),
+            SCREEN_WIDTH * SCREEN_HEIGHT * 4
+        );
+        emulator.reset();
+        assert_eq!(emulator.step_instruction(), Ok(1));
+    }
+
+    #[test]
+    fn test_serial_capture() {
+        let mut rom = vec![0; 0x8000];
+        // jump past the header to a program that sends "o" over the serial port, then loops
+        rom[0x0100..0x0103].copy_from_slice(&[0xC3, 0x50, 0x01]);
+        rom[0x0150..0x015A]
+            .copy_from_slice(&[0x3E, b'o', 0xE0, 0x01, 0x3E, 0x81, 0xE0, 0x02, 0x18, 0xFE]);
+        let mut emulator = Emulator::from_rom_bytes(&rom).unwrap();
+        let capture = SerialCapture::new();
+        emulator.set_link_cable(Box::new(capture.clone()));
+        emulator.step_frame().unwrap();
+        assert_eq!(capture.output(), "o");
+    }
+
+    #[test]
+    fn test_model_from_header() {
+        let mut rom = vec![0; 0x8000];
+        assert_eq!(Emulator::from_rom_bytes(&rom).unwrap().model(), Model::Dmg);
+        rom[0x0143] = 0x80;
+        assert_eq!(Emulator::from_rom_bytes(&rom).unwrap().model(), Model::Cgb);
+    }
+
+    #[test]
+    fn test_sgb_screen_size() {
+        let mut rom = vec![0; 0x8000];
+        let emulator = Emulator::new(&rom, Model::Sgb, None).unwrap();
+        assert_eq!(emulator.screen_size(), (SCREEN_WIDTH, SCREEN_HEIGHT));
+        rom[0x0146] = 0x03;
+        let mut emulator = Emulator::new(&rom, Model::Sgb2, None).unwrap();
+        assert_eq!(
+            emulator.screen_size(),
+            (SGB_SCREEN_WIDTH, SGB_SCREEN_HEIGHT)
+        );
+        emulator.step_frame().unwrap();
+        assert_eq!(
+            emulator.framebuffer().len(),
+            SGB_SCREEN_WIDTH * SGB_SCREEN_HEIGHT * 4
+        );
+    }
+
+    #[test]
+    fn test_illegal_opcode() {
+        let mut rom = vec![0; 0x8000];
+        rom[0x0100] = 0xDD;
+        let mut emulator = Emulator::from_rom_bytes(&rom).unwrap();
+        assert_eq!(
+            emulator.step_frame(),
+            Err(EmulatorError::Cpu(CpuError::IllegalOpcode {
+                pc: 0x0100,
+                opcode: 0xDD
+            }))
+        );
+    }
+
+    #[test]
+    fn test_boot_rom() {
+        // LD A,1 and LDH (0x50),A unmap the boot ROM, the cartridge continues at 0x0004
+        let mut boot_rom = vec![0; 0x100];
+        boot_rom[..4].copy_from_slice(&[0x3E, 0x01, 0xE0, 0x50]);
+        let mut rom = vec![0; 0x8000];
+        rom[0x0004] = 0xDD;
+        let mut emulator = Emulator::new(&rom, Model::Dmg, Some(&boot_rom)).unwrap();
+        emulator.step_instruction().unwrap();
+        emulator.step_instruction().unwrap();
+        let illegal_opcode = Err(EmulatorError::Cpu(CpuError::IllegalOpcode {
+            pc: 0x0004,
+            opcode: 0xDD,
+        }));
+        assert_eq!(emulator.step_instruction(), illegal_opcode);
+
+        // a reset maps the boot ROM again
+        emulator.reset();
+        emulator.step_instruction().unwrap();
+        emulator.step_instruction().unwrap();
+        assert_eq!(emulator.step_instruction(), illegal_opcode);
+
+        assert_eq!(
+            Emulator::new(&rom, Model::Cgb, Some(&boot_rom)).err(),
+            Some(EmulatorError::InvalidBootRom {
+                expected: 0x900,
+                actual: 0x100
+            })
+        );
+    }
 }
@@ -0,0 +1,44 @@
+use super::memory::{CartridgeHeader, CgbSupport};
+
+// the console being emulated, this decides the boot ROM and the state it leaves behind
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Model {
+    Dmg,
+    // Game Boy Pocket and Light
+    Mgb,
+    Sgb,
+    Sgb2,
+    Cgb,
+}
+
+impl Model {
+    // CGB games get a CGB to show their colors, everything else the original Game Boy
+    pub fn for_cartridge(header: &CartridgeHeader) -> Model {
+        match header.cgb_support {
+            CgbSupport::None => Model::Dmg,
+            _ => Model::Cgb,
+        }
+    }
+
+    pub fn is_sgb(self) -> bool {
+        matches!(self, Model::Sgb | Model::Sgb2)
+    }
+
+    // the CGB boot ROM also covers 0x0200-0x08FF, the cartridge header stays visible in between
+    pub fn boot_rom_size(self) -> usize {
+        match self {
+            Model::Cgb => 0x900,
+            _ => 0x100,
+        }
+    }
+
+    // internal divider when the boot ROM hands over to the cartridge, the SGB boot ROM waits
+    // for the SNES and the CGB one takes longer for DMG games, so theirs are typical values
+    pub fn post_boot_divider(self) -> u16 {
+        match self {
+            Model::Dmg | Model::Mgb => 0xABCC,
+            Model::Sgb | Model::Sgb2 => 0xD85C,
+            Model::Cgb => 0x1EA0,
+        }
+    }
+}
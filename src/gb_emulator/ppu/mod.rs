@@ -0,0 +1,893 @@
+mod palette;
+
+pub use palette::rgb555;
+use palette::PaletteRam;
+
+use super::memory::{Interrupt, InterruptController};
+
+pub const SCREEN_WIDTH: usize = 160;
+pub const SCREEN_HEIGHT: usize = 144;
+
+const VRAM_SIZE: usize = 0x2000;
+// the CGB has a second bank for tile data and the background map attributes
+const VRAM_BANKS: usize = 2;
+const OAM_SIZE: usize = 0xA0;
+
+// clock cycles spent in each part of a scanline
+const OAM_SCAN_CYCLES: u32 = 80;
+const DRAWING_CYCLES: u32 = 172;
+const LINE_CYCLES: u32 = 456;
+const LINES_PER_FRAME: u8 = 154;
+
+const MAX_SPRITES_PER_LINE: usize = 10;
+
+// RGBA colors for the four DMG shades, from lightest to darkest
+const DMG_COLORS: [[u8; 4]; 4] = [
+    [0xE0, 0xF8, 0xD0, 0xFF],
+    [0x88, 0xC0, 0x70, 0xFF],
+    [0x34, 0x68, 0x56, 0xFF],
+    [0x08, 0x18, 0x20, 0xFF],
+];
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Mode {
+    HBlank = 0,
+    VBlank = 1,
+    OamScan = 2,
+    Drawing = 3,
+}
+
+#[derive(Copy, Clone)]
+struct Sprite {
+    y: i16,
+    x: i16,
+    tile: u8,
+    flags: u8,
+}
+
+// a pixel of the current line before the palette lookup
+#[derive(Copy, Clone, Default)]
+struct Pixel {
+    color: u8,
+    // CGB palette number, on the DMG 0 for BGP or 0 and 1 for OBP0 and OBP1
+    palette: u8,
+    sprite: bool,
+    // CGB map attribute bit 7, the background covers the sprites
+    priority: bool,
+}
+
+pub struct Ppu {
+    // CGB mode enables the second VRAM bank, the map attributes and the color palettes
+    cgb: bool,
+    vram: [u8; VRAM_SIZE * VRAM_BANKS],
+    vram_bank: usize,
+    bg_palettes: PaletteRam,
+    obj_palettes: PaletteRam,
+    oam: [u8; OAM_SIZE],
+    lcdc: u8,
+    stat: u8,
+    scy: u8,
+    scx: u8,
+    ly: u8,
+    lyc: u8,
+    bgp: u8,
+    obp0: u8,
+    obp1: u8,
+    wy: u8,
+    wx: u8,
+    mode: Mode,
+    // position within the current scanline
+    line_cycles: u32,
+    // the window keeps its own line counter that only advances on lines it was drawn on
+    window_line: u8,
+    // STAT interrupts fire on the rising edge of the OR of all enabled sources
+    stat_line: bool,
+    frame_complete: bool,
+    // set on entering VBlank, the SGB picks up the finished frame
+    vblank_started: bool,
+    // set on entering HBlank of a visible line, HBlank DMA copies a block each time
+    hblank_started: bool,
+    framebuffer: Vec<u8>,
+    // DMG shades (0-3) of the last frame for the SGB to color
+    shades: Vec<u8>,
+}
+
+impl Ppu {
+    pub fn new() -> Ppu {
+        Ppu {
+            cgb: false,
+            vram: [0; VRAM_SIZE * VRAM_BANKS],
+            vram_bank: 0,
+            bg_palettes: PaletteRam::new(),
+            obj_palettes: PaletteRam::new(),
+            oam: [0; OAM_SIZE],
+            lcdc: 0,
+            stat: 0,
+            scy: 0,
+            scx: 0,
+            ly: 0,
+            lyc: 0,
+            bgp: 0,
+            obp0: 0,
+            obp1: 0,
+            wy: 0,
+            wx: 0,
+            mode: Mode::HBlank,
+            line_cycles: 0,
+            window_line: 0,
+            stat_line: false,
+            frame_complete: false,
+            vblank_started: false,
+            hblank_started: false,
+            framebuffer: DMG_COLORS[0].repeat(SCREEN_WIDTH * SCREEN_HEIGHT),
+            shades: vec![0; SCREEN_WIDTH * SCREEN_HEIGHT],
+        }
+    }
+
+    // chosen at boot, DMG games on a CGB run in DMG mode with the original palette registers
+    pub fn set_cgb_mode(&mut self, cgb: bool) {
+        self.cgb = cgb;
+        if !cgb {
+            self.vram_bank = 0;
+        }
+    }
+
+    // RGBA pixels of the last rendered frame, row by row
+    pub fn framebuffer(&self) -> &[u8] {
+        &self.framebuffer
+    }
+
+    // returns true once after every frame that entered VBlank
+    pub fn take_frame_complete(&mut self) -> bool {
+        std::mem::take(&mut self.frame_complete)
+    }
+
+    pub fn shades(&self) -> &[u8] {
+        &self.shades
+    }
+
+    // returns true once after every frame that entered VBlank, separate from the frontend flag
+    pub fn take_vblank_started(&mut self) -> bool {
+        std::mem::take(&mut self.vblank_started)
+    }
+
+    // returns true once after every visible line that entered HBlank
+    pub fn take_hblank_started(&mut self) -> bool {
+        std::mem::take(&mut self.hblank_started)
+    }
+
+    fn lcd_enabled(&self) -> bool {
+        self.lcdc & 0x80 != 0
+    }
+
+    pub fn read_vram(&self, addr: u16) -> u8 {
+        if self.mode == Mode::Drawing {
+            return 0xFF;
+        }
+        self.vram[self.vram_bank * VRAM_SIZE + (addr - 0x8000) as usize]
+    }
+
+    pub fn write_vram(&mut self, addr: u16, val: u8) {
+        if self.mode != Mode::Drawing {
+            self.vram[self.vram_bank * VRAM_SIZE + (addr - 0x8000) as usize] = val;
+        }
+    }
+
+    pub fn read_oam(&self, addr: u16) -> u8 {
+        match self.mode {
+            Mode::OamScan | Mode::Drawing => 0xFF,
+            _ => self.oam[(addr - 0xFE00) as usize],
+        }
+    }
+
+    pub fn write_oam(&mut self, addr: u16, val: u8) {
+        match self.mode {
+            Mode::OamScan | Mode::Drawing => {}
+            _ => self.oam[(addr - 0xFE00) as usize] = val,
+        }
+    }
+
+    // OAM DMA writes even while the PPU uses OAM
+    pub fn write_oam_dma(&mut self, offset: usize, val: u8) {
+        self.oam[offset] = val;
+    }
+
+    pub fn read_byte(&self, addr: u16) -> u8 {
+        match addr {
+            // the CGB registers read 0xFF in DMG mode
+            0xFF4F | 0xFF68..=0xFF6B if !self.cgb => 0xFF,
+            0xFF40 => self.lcdc,
+            0xFF41 => {
+                let coincidence = if self.ly == self.lyc { 0x04 } else { 0x00 };
+                0x80 | self.stat | coincidence | self.mode as u8
+            }
+            0xFF42 => self.scy,
+            0xFF43 => self.scx,
+            0xFF44 => self.ly,
+            0xFF45 => self.lyc,
+            0xFF47 => self.bgp,
+            0xFF48 => self.obp0,
+            0xFF49 => self.obp1,
+            0xFF4A => self.wy,
+            0xFF4B => self.wx,
+            0xFF4F => 0xFE | self.vram_bank as u8,
+            0xFF68 => self.bg_palettes.read_index(),
+            0xFF6A => self.obj_palettes.read_index(),
+            // palette RAM is in use while the PPU draws
+            0xFF69 | 0xFF6B if self.mode == Mode::Drawing => 0xFF,
+            0xFF69 => self.bg_palettes.read_data(),
+            0xFF6B => self.obj_palettes.read_data(),
+            _ => panic!("access to ppu in non mapped memory space: {:X}", addr),
+        }
+    }
+
+    pub fn write_byte(&mut self, addr: u16, val: u8, interrupts: &mut InterruptController) {
+        match addr {
+            0xFF4F | 0xFF68..=0xFF6B if !self.cgb => {}
+            0xFF40 => {
+                let was_enabled = self.lcd_enabled();
+                self.lcdc = val;
+                if was_enabled && !self.lcd_enabled() {
+                    self.ly = 0;
+                    self.line_cycles = 0;
+                    self.window_line = 0;
+                    self.mode = Mode::HBlank;
+                } else if !was_enabled && self.lcd_enabled() {
+                    self.mode = Mode::OamScan;
+                }
+            }
+            // only the interrupt source selection is writable
+            0xFF41 => self.stat = val & 0x78,
+            0xFF42 => self.scy = val,
+            0xFF43 => self.scx = val,
+            0xFF44 => {}
+            0xFF45 => self.lyc = val,
+            0xFF47 => self.bgp = val,
+            0xFF48 => self.obp0 = val,
+            0xFF49 => self.obp1 = val,
+            0xFF4A => self.wy = val,
+            0xFF4B => self.wx = val,
+            0xFF4F => self.vram_bank = (val & 0x01) as usize,
+            0xFF68 => self.bg_palettes.write_index(val),
+            0xFF69 => self.bg_palettes.write_data(val, self.mode != Mode::Drawing),
+            0xFF6A => self.obj_palettes.write_index(val),
+            0xFF6B => self
+                .obj_palettes
+                .write_data(val, self.mode != Mode::Drawing),
+            _ => panic!("access to ppu in non mapped memory space: {:X}", addr),
+        }
+        self.update_stat_line(interrupts);
+    }
+
+    // advance by the given amount of clock cycles
+    pub fn tick(&mut self, cycles: u32, interrupts: &mut InterruptController) {
+        if !self.lcd_enabled() {
+            return;
+        }
+
+        for _ in 0..cycles {
+            self.line_cycles += 1;
+            match self.mode {
+                Mode::OamScan if self.line_cycles == OAM_SCAN_CYCLES => {
+                    self.mode = Mode::Drawing;
+                }
+                Mode::Drawing if self.line_cycles == OAM_SCAN_CYCLES + DRAWING_CYCLES => {
+                    self.render_line();
+                    self.mode = Mode::HBlank;
+                    self.hblank_started = true;
+                }
+                _ if self.line_cycles == LINE_CYCLES => {
+                    self.line_cycles = 0;
+                    self.ly = (self.ly + 1) % LINES_PER_FRAME;
+                    match self.ly as usize {
+                        SCREEN_HEIGHT => {
+                            self.mode = Mode::VBlank;
+                            self.frame_complete = true;
+                            self.vblank_started = true;
+                            interrupts.request(Interrupt::VBlank);
+                        }
+                        0 => {
+                            self.window_line = 0;
+                            self.mode = Mode::OamScan;
+                        }
+                        ly if ly < SCREEN_HEIGHT => self.mode = Mode::OamScan,
+                        _ => {}
+                    }
+                }
+                _ => continue,
+            }
+            self.update_stat_line(interrupts);
+        }
+    }
+
+    fn update_stat_line(&mut self, interrupts: &mut InterruptController) {
+        let line = self.lcd_enabled()
+            && ((self.stat & 0x40 != 0 && self.ly == self.lyc)
+                || (self.stat & 0x20 != 0 && self.mode == Mode::OamScan)
+                || (self.stat & 0x10 != 0 && self.mode == Mode::VBlank)
+                || (self.stat & 0x08 != 0 && self.mode == Mode::HBlank));
+        if line && !self.stat_line {
+            interrupts.request(Interrupt::LcdStat);
+        }
+        self.stat_line = line;
+    }
+
+    // color index (0-3) of a pixel in the given tile of a VRAM bank
+    fn tile_pixel(&self, bank: usize, tile_addr: u16, x: u8, y: u8) -> u8 {
+        let offset = bank * VRAM_SIZE + (tile_addr - 0x8000) as usize + (y as usize) * 2;
+        let lo = self.vram[offset];
+        let hi = self.vram[offset + 1];
+        let bit = 7 - x;
+        ((hi >> bit) & 1) << 1 | ((lo >> bit) & 1)
+    }
+
+    // address of a background or window tile, LCDC bit 4 selects signed addressing from 0x9000
+    fn bg_tile_addr(&self, tile: u8) -> u16 {
+        if self.lcdc & 0x10 != 0 {
+            0x8000 + (tile as u16) * 16
+        } else {
+            0x9000u16.wrapping_add(((tile as i8 as i16) * 16) as u16)
+        }
+    }
+
+    // the 4 KiB an SGB VRAM transfer receives: the tile data of the first 256 background tiles
+    // on screen, 20 to a row
+    pub fn transfer_data(&self) -> Vec<u8> {
+        let map = if self.lcdc & 0x08 != 0 {
+            0x1C00
+        } else {
+            0x1800
+        };
+        (0..256)
+            .flat_map(|i| {
+                let tile = self.vram[map + (i / 20) * 32 + i % 20];
+                let offset = (self.bg_tile_addr(tile) - 0x8000) as usize;
+                self.vram[offset..offset + 16].iter().copied()
+            })
+            .collect()
+    }
+
+    fn shade(palette: u8, color: u8) -> u8 {
+        (palette >> (color * 2)) & 0x03
+    }
+
+    fn render_line(&mut self) {
+        let mut line = [Pixel::default(); SCREEN_WIDTH];
+
+        // on the DMG, clearing LCDC bit 0 blanks both background and window, on the CGB it
+        // only takes away their priority over the sprites
+        if self.cgb || self.lcdc & 0x01 != 0 {
+            self.render_background(&mut line);
+        }
+        if self.lcdc & 0x02 != 0 {
+            self.render_sprites(&mut line);
+        }
+
+        let row = self.ly as usize * SCREEN_WIDTH;
+        for (x, pixel) in line.iter().enumerate() {
+            let color = if self.cgb {
+                let palettes = if pixel.sprite {
+                    &self.obj_palettes
+                } else {
+                    &self.bg_palettes
+                };
+                palettes.color(pixel.palette, pixel.color)
+            } else {
+                let palette = match (pixel.sprite, pixel.palette) {
+                    (false, _) => self.bgp,
+                    (true, 0) => self.obp0,
+                    (true, _) => self.obp1,
+                };
+                let shade = Self::shade(palette, pixel.color);
+                self.shades[row + x] = shade;
+                DMG_COLORS[shade as usize]
+            };
+            let offset = (row + x) * 4;
+            self.framebuffer[offset..offset + 4].copy_from_slice(&color);
+        }
+    }
+
+    fn render_background(&mut self, line: &mut [Pixel; SCREEN_WIDTH]) {
+        let bg_map: u16 = if self.lcdc & 0x08 != 0 {
+            0x9C00
+        } else {
+            0x9800
+        };
+        let window_map: u16 = if self.lcdc & 0x40 != 0 {
+            0x9C00
+        } else {
+            0x9800
+        };
+        let window_visible = self.lcdc & 0x20 != 0 && self.wy <= self.ly && self.wx <= 166;
+        let window_x = self.wx as i16 - 7;
+
+        for (x, pixel) in line.iter_mut().enumerate() {
+            let (map, px, py) = if window_visible && x as i16 >= window_x {
+                (window_map, (x as i16 - window_x) as u8, self.window_line)
+            } else {
+                (
+                    bg_map,
+                    (x as u8).wrapping_add(self.scx),
+                    self.ly.wrapping_add(self.scy),
+                )
+            };
+            let map_index = (map - 0x8000) as usize + (py as usize / 8) * 32 + (px as usize / 8);
+            let tile = self.vram[map_index];
+            // the CGB keeps the attributes of each map entry at the same place in bank 1
+            let attributes = if self.cgb {
+                self.vram[VRAM_SIZE + map_index]
+            } else {
+                0
+            };
+            let (mut tx, mut ty) = (px % 8, py % 8);
+            if attributes & 0x20 != 0 {
+                tx = 7 - tx;
+            }
+            if attributes & 0x40 != 0 {
+                ty = 7 - ty;
+            }
+            let bank = (attributes as usize >> 3) & 1;
+            *pixel = Pixel {
+                color: self.tile_pixel(bank, self.bg_tile_addr(tile), tx, ty),
+                palette: attributes & 0x07,
+                sprite: false,
+                priority: attributes & 0x80 != 0,
+            };
+        }
+
+        if window_visible {
+            self.window_line += 1;
+        }
+    }
+
+    // the first ten sprites in OAM order that overlap the current line
+    fn sprites_on_line(&self) -> Vec<Sprite> {
+        let height = if self.lcdc & 0x04 != 0 { 16 } else { 8 };
+        let ly = self.ly as i16;
+        self.oam
+            .chunks_exact(4)
+            .map(|entry| Sprite {
+                y: entry[0] as i16 - 16,
+                x: entry[1] as i16 - 8,
+                tile: entry[2],
+                flags: entry[3],
+            })
+            .filter(|sprite| ly >= sprite.y && ly < sprite.y + height)
+            .take(MAX_SPRITES_PER_LINE)
+            .collect()
+    }
+
+    fn render_sprites(&self, line: &mut [Pixel; SCREEN_WIDTH]) {
+        let tall = self.lcdc & 0x04 != 0;
+        let mut sprites = self.sprites_on_line();
+        // on the DMG the sprite with the smaller X coordinate wins, ties go to the earlier OAM
+        // entry, on the CGB only the OAM order counts
+        if !self.cgb {
+            sprites.sort_by_key(|sprite| sprite.x);
+        }
+
+        for x in 0..SCREEN_WIDTH as i16 {
+            for sprite in sprites.iter() {
+                if x < sprite.x || x >= sprite.x + 8 {
+                    continue;
+                }
+
+                let mut px = (x - sprite.x) as u8;
+                let mut py = (self.ly as i16 - sprite.y) as u8;
+                if sprite.flags & 0x20 != 0 {
+                    px = 7 - px;
+                }
+                if sprite.flags & 0x40 != 0 {
+                    py = if tall { 15 } else { 7 } - py;
+                }
+                let tile = if tall {
+                    sprite.tile & 0xFE
+                } else {
+                    sprite.tile
+                };
+                let bank = if self.cgb {
+                    (sprite.flags as usize >> 3) & 1
+                } else {
+                    0
+                };
+                let color = self.tile_pixel(bank, 0x8000 + tile as u16 * 16, px, py);
+                if color == 0 {
+                    continue;
+                }
+
+                // the first sprite found for a pixel ends the search, so the line still holds
+                // the background here
+                let x = x as usize;
+                let bg = line[x];
+                // on the CGB a cleared LCDC bit 0 puts all sprites on top, otherwise the map
+                // attribute can also put the background in front
+                let visible = if self.cgb && self.lcdc & 0x01 == 0 {
+                    true
+                } else {
+                    bg.color == 0 || (sprite.flags & 0x80 == 0 && !bg.priority)
+                };
+                if visible {
+                    let palette = if self.cgb {
+                        sprite.flags & 0x07
+                    } else {
+                        (sprite.flags >> 4) & 0x01
+                    };
+                    line[x] = Pixel {
+                        color,
+                        palette,
+                        sprite: true,
+                        priority: false,
+                    };
+                }
+                break;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn enabled_ppu(interrupts: &mut InterruptController) -> Ppu {
+        let mut ppu = Ppu::new();
+        ppu.write_byte(0xFF47, 0xE4, interrupts);
+        ppu.write_byte(0xFF48, 0xE4, interrupts);
+        ppu.write_byte(0xFF49, 0x1B, interrupts);
+        ppu.write_byte(0xFF40, 0x93, interrupts);
+        ppu
+    }
+
+    fn pixel(ppu: &Ppu, x: usize, y: usize) -> [u8; 4] {
+        let offset = (y * SCREEN_WIDTH + x) * 4;
+        ppu.framebuffer[offset..offset + 4].try_into().unwrap()
+    }
+
+    // tile whose rows all use the given color index
+    fn fill_tile(ppu: &mut Ppu, tile_addr: u16, color: u8) {
+        for row in 0..8 {
+            let offset = (tile_addr - 0x8000) as usize + row * 2;
+            ppu.vram[offset] = if color & 1 != 0 { 0xFF } else { 0x00 };
+            ppu.vram[offset + 1] = if color & 2 != 0 { 0xFF } else { 0x00 };
+        }
+    }
+
+    fn run_frame(ppu: &mut Ppu, interrupts: &mut InterruptController) {
+        ppu.tick(LINE_CYCLES * LINES_PER_FRAME as u32, interrupts);
+    }
+
+    #[test]
+    fn test_mode_timing() {
+        let mut interrupts = InterruptController::new();
+        let mut ppu = enabled_ppu(&mut interrupts);
+        assert_eq!(ppu.mode, Mode::OamScan);
+        ppu.tick(79, &mut interrupts);
+        assert_eq!(ppu.mode, Mode::OamScan);
+        ppu.tick(1, &mut interrupts);
+        assert_eq!(ppu.mode, Mode::Drawing);
+        ppu.tick(172, &mut interrupts);
+        assert_eq!(ppu.mode, Mode::HBlank);
+        ppu.tick(204, &mut interrupts);
+        assert_eq!(ppu.mode, Mode::OamScan);
+        assert_eq!(ppu.read_byte(0xFF44), 1);
+        assert_eq!(ppu.read_byte(0xFF41) & 0x03, Mode::OamScan as u8);
+    }
+
+    #[test]
+    fn test_vblank() {
+        let mut interrupts = InterruptController::new();
+        interrupts.write_enable(0x1F);
+        let mut ppu = enabled_ppu(&mut interrupts);
+        ppu.tick(LINE_CYCLES * 144 - 1, &mut interrupts);
+        assert_eq!(interrupts.pending(), None);
+        assert!(!ppu.take_frame_complete());
+
+        ppu.tick(1, &mut interrupts);
+        assert_eq!(ppu.read_byte(0xFF44), 144);
+        assert_eq!(ppu.mode, Mode::VBlank);
+        assert_eq!(interrupts.pending(), Some(Interrupt::VBlank));
+        assert!(ppu.take_frame_complete());
+        assert!(!ppu.take_frame_complete());
+
+        ppu.tick(LINE_CYCLES * 10, &mut interrupts);
+        assert_eq!(ppu.read_byte(0xFF44), 0);
+        assert_eq!(ppu.mode, Mode::OamScan);
+    }
+
+    #[test]
+    fn test_lyc_interrupt() {
+        let mut interrupts = InterruptController::new();
+        interrupts.write_enable(Interrupt::LcdStat as u8);
+        let mut ppu = enabled_ppu(&mut interrupts);
+        ppu.write_byte(0xFF45, 3, &mut interrupts);
+        ppu.write_byte(0xFF41, 0x40, &mut interrupts);
+        ppu.tick(LINE_CYCLES * 3 - 1, &mut interrupts);
+        assert_eq!(interrupts.pending(), None);
+        assert_eq!(ppu.read_byte(0xFF41) & 0x04, 0);
+
+        ppu.tick(1, &mut interrupts);
+        assert_eq!(interrupts.pending(), Some(Interrupt::LcdStat));
+        assert_eq!(ppu.read_byte(0xFF41) & 0x04, 0x04);
+    }
+
+    #[test]
+    fn test_stat_mode_interrupt_blocking() {
+        let mut interrupts = InterruptController::new();
+        interrupts.write_enable(Interrupt::LcdStat as u8);
+        let mut ppu = enabled_ppu(&mut interrupts);
+        // HBlank and OAM sources together: the line stays high from HBlank into the next OAM scan
+        ppu.write_byte(0xFF41, 0x28, &mut interrupts);
+        interrupts.acknowledge(Interrupt::LcdStat);
+        ppu.tick(OAM_SCAN_CYCLES, &mut interrupts);
+        assert_eq!(interrupts.pending(), None);
+        ppu.tick(DRAWING_CYCLES, &mut interrupts);
+        assert_eq!(interrupts.pending(), Some(Interrupt::LcdStat));
+        interrupts.acknowledge(Interrupt::LcdStat);
+
+        ppu.tick(
+            LINE_CYCLES - OAM_SCAN_CYCLES - DRAWING_CYCLES,
+            &mut interrupts,
+        );
+        assert_eq!(interrupts.pending(), None);
+    }
+
+    #[test]
+    fn test_lcd_off() {
+        let mut interrupts = InterruptController::new();
+        let mut ppu = enabled_ppu(&mut interrupts);
+        ppu.tick(LINE_CYCLES * 5 + 100, &mut interrupts);
+        ppu.write_byte(0xFF40, 0x00, &mut interrupts);
+        assert_eq!(ppu.read_byte(0xFF44), 0);
+        assert_eq!(ppu.mode, Mode::HBlank);
+        ppu.tick(LINE_CYCLES * 5, &mut interrupts);
+        assert_eq!(ppu.read_byte(0xFF44), 0);
+    }
+
+    #[test]
+    fn test_vram_blocked_while_drawing() {
+        let mut interrupts = InterruptController::new();
+        let mut ppu = enabled_ppu(&mut interrupts);
+        ppu.tick(OAM_SCAN_CYCLES, &mut interrupts);
+        ppu.write_vram(0x8000, 0x42);
+        assert_eq!(ppu.read_vram(0x8000), 0xFF);
+        assert_eq!(ppu.read_oam(0xFE00), 0xFF);
+        ppu.tick(DRAWING_CYCLES, &mut interrupts);
+        assert_eq!(ppu.read_vram(0x8000), 0x00);
+    }
+
+    #[test]
+    fn test_background() {
+        let mut interrupts = InterruptController::new();
+        let mut ppu = enabled_ppu(&mut interrupts);
+        fill_tile(&mut ppu, 0x8010, 3);
+        // tile 1 at the second column of the first map row
+        ppu.vram[0x1801] = 1;
+        run_frame(&mut ppu, &mut interrupts);
+        assert_eq!(pixel(&ppu, 7, 0), DMG_COLORS[0]);
+        assert_eq!(pixel(&ppu, 8, 0), DMG_COLORS[3]);
+        assert_eq!(pixel(&ppu, 15, 7), DMG_COLORS[3]);
+        assert_eq!(pixel(&ppu, 16, 0), DMG_COLORS[0]);
+
+        // scrolling moves the tile to the left edge
+        ppu.write_byte(0xFF43, 8, &mut interrupts);
+        run_frame(&mut ppu, &mut interrupts);
+        assert_eq!(pixel(&ppu, 0, 0), DMG_COLORS[3]);
+        assert_eq!(pixel(&ppu, 8, 0), DMG_COLORS[0]);
+    }
+
+    #[test]
+    fn test_signed_tile_addressing() {
+        let mut interrupts = InterruptController::new();
+        let mut ppu = enabled_ppu(&mut interrupts);
+        ppu.write_byte(0xFF40, 0x81, &mut interrupts);
+        fill_tile(&mut ppu, 0x8FF0, 2);
+        ppu.vram[0x1800] = 0xFF;
+        run_frame(&mut ppu, &mut interrupts);
+        assert_eq!(pixel(&ppu, 0, 0), DMG_COLORS[2]);
+    }
+
+    #[test]
+    fn test_window() {
+        let mut interrupts = InterruptController::new();
+        let mut ppu = enabled_ppu(&mut interrupts);
+        fill_tile(&mut ppu, 0x8010, 1);
+        // window uses the 0x9C00 map filled with tile 1
+        ppu.vram[0x1C00..0x2000].fill(1);
+        ppu.write_byte(0xFF40, 0xF3, &mut interrupts);
+        ppu.write_byte(0xFF4A, 10, &mut interrupts);
+        ppu.write_byte(0xFF4B, 87, &mut interrupts);
+        run_frame(&mut ppu, &mut interrupts);
+        assert_eq!(pixel(&ppu, 80, 9), DMG_COLORS[0]);
+        assert_eq!(pixel(&ppu, 79, 10), DMG_COLORS[0]);
+        assert_eq!(pixel(&ppu, 80, 10), DMG_COLORS[1]);
+        assert_eq!(pixel(&ppu, 159, 143), DMG_COLORS[1]);
+    }
+
+    #[test]
+    fn test_sprites() {
+        let mut interrupts = InterruptController::new();
+        let mut ppu = enabled_ppu(&mut interrupts);
+        fill_tile(&mut ppu, 0x8020, 3);
+        // sprite with tile 2 at screen position (10, 20), using OBP1
+        ppu.oam[0..4].copy_from_slice(&[36, 18, 2, 0x10]);
+        run_frame(&mut ppu, &mut interrupts);
+        assert_eq!(pixel(&ppu, 9, 20), DMG_COLORS[0]);
+        assert_eq!(pixel(&ppu, 10, 20), DMG_COLORS[0]);
+        assert_eq!(pixel(&ppu, 10, 19), DMG_COLORS[0]);
+
+        // OBP1 maps color 3 to shade 0, switch to OBP0
+        ppu.oam[3] = 0x00;
+        run_frame(&mut ppu, &mut interrupts);
+        assert_eq!(pixel(&ppu, 10, 20), DMG_COLORS[3]);
+        assert_eq!(pixel(&ppu, 17, 27), DMG_COLORS[3]);
+        assert_eq!(pixel(&ppu, 18, 27), DMG_COLORS[0]);
+        assert_eq!(pixel(&ppu, 17, 28), DMG_COLORS[0]);
+    }
+
+    #[test]
+    fn test_tall_sprites() {
+        let mut interrupts = InterruptController::new();
+        let mut ppu = enabled_ppu(&mut interrupts);
+        ppu.write_byte(0xFF40, 0x97, &mut interrupts);
+        fill_tile(&mut ppu, 0x8020, 1);
+        fill_tile(&mut ppu, 0x8030, 2);
+        // odd tile index is ignored for 8x16 sprites, flipped vertically
+        ppu.oam[0..4].copy_from_slice(&[16, 8, 3, 0x40]);
+        run_frame(&mut ppu, &mut interrupts);
+        assert_eq!(pixel(&ppu, 0, 0), DMG_COLORS[2]);
+        assert_eq!(pixel(&ppu, 0, 8), DMG_COLORS[1]);
+        assert_eq!(pixel(&ppu, 0, 15), DMG_COLORS[1]);
+        assert_eq!(pixel(&ppu, 0, 16), DMG_COLORS[0]);
+    }
+
+    #[test]
+    fn test_sprite_line_limit() {
+        let mut interrupts = InterruptController::new();
+        let mut ppu = enabled_ppu(&mut interrupts);
+        fill_tile(&mut ppu, 0x8010, 3);
+        for i in 0..12 {
+            ppu.oam[i * 4..i * 4 + 4].copy_from_slice(&[16, 8 + i as u8 * 8, 1, 0]);
+        }
+        run_frame(&mut ppu, &mut interrupts);
+        assert_eq!(pixel(&ppu, 79, 0), DMG_COLORS[3]);
+        assert_eq!(pixel(&ppu, 80, 0), DMG_COLORS[0]);
+        assert_eq!(pixel(&ppu, 88, 0), DMG_COLORS[0]);
+    }
+
+    #[test]
+    fn test_sprite_priority() {
+        let mut interrupts = InterruptController::new();
+        let mut ppu = enabled_ppu(&mut interrupts);
+        fill_tile(&mut ppu, 0x8010, 1);
+        fill_tile(&mut ppu, 0x8020, 2);
+        // the later OAM entry is further left and wins the overlap
+        ppu.oam[0..4].copy_from_slice(&[16, 12, 1, 0]);
+        ppu.oam[4..8].copy_from_slice(&[16, 10, 2, 0]);
+        // same X, the earlier OAM entry wins
+        ppu.oam[8..12].copy_from_slice(&[32, 8, 2, 0]);
+        ppu.oam[12..16].copy_from_slice(&[32, 8, 1, 0]);
+        run_frame(&mut ppu, &mut interrupts);
+        assert_eq!(pixel(&ppu, 4, 0), DMG_COLORS[2]);
+        assert_eq!(pixel(&ppu, 9, 0), DMG_COLORS[2]);
+        assert_eq!(pixel(&ppu, 10, 0), DMG_COLORS[1]);
+        assert_eq!(pixel(&ppu, 12, 0), DMG_COLORS[0]);
+        assert_eq!(pixel(&ppu, 0, 16), DMG_COLORS[2]);
+    }
+
+    #[test]
+    fn test_sprite_behind_background() {
+        let mut interrupts = InterruptController::new();
+        let mut ppu = enabled_ppu(&mut interrupts);
+        fill_tile(&mut ppu, 0x8000, 3);
+        fill_tile(&mut ppu, 0x8010, 1);
+        // background tile 1 only in the first map column
+        ppu.vram[0x1800] = 1;
+        ppu.vram[0x1801..0x1C00].fill(2);
+        ppu.oam[0..4].copy_from_slice(&[16, 12, 0, 0x80]);
+        run_frame(&mut ppu, &mut interrupts);
+        assert_eq!(pixel(&ppu, 4, 0), DMG_COLORS[1]);
+        assert_eq!(pixel(&ppu, 8, 0), DMG_COLORS[3]);
+    }
+
+    // write an RGB555 color through BCPS/BCPD or OCPS/OCPD
+    fn set_color(
+        ppu: &mut Ppu,
+        index_register: u16,
+        palette: u8,
+        color: u8,
+        rgb: u16,
+        interrupts: &mut InterruptController,
+    ) {
+        ppu.write_byte(index_register, 0x80 | (palette * 8 + color * 2), interrupts);
+        for val in rgb.to_le_bytes() {
+            ppu.write_byte(index_register + 1, val, interrupts);
+        }
+    }
+
+    fn cgb_ppu(interrupts: &mut InterruptController) -> Ppu {
+        let mut ppu = Ppu::new();
+        ppu.set_cgb_mode(true);
+        ppu.write_byte(0xFF40, 0x93, interrupts);
+        ppu
+    }
+
+    #[test]
+    fn test_cgb_registers() {
+        let mut interrupts = InterruptController::new();
+        let mut ppu = enabled_ppu(&mut interrupts);
+        ppu.write_byte(0xFF4F, 0x01, &mut interrupts);
+        assert_eq!(ppu.read_byte(0xFF4F), 0xFF);
+        assert_eq!(ppu.read_byte(0xFF68), 0xFF);
+
+        ppu.set_cgb_mode(true);
+        ppu.write_byte(0xFF4F, 0x01, &mut interrupts);
+        assert_eq!(ppu.read_byte(0xFF4F), 0xFF);
+        ppu.write_vram(0x8000, 0x42);
+        ppu.write_byte(0xFF4F, 0x00, &mut interrupts);
+        assert_eq!(ppu.read_byte(0xFF4F), 0xFE);
+        assert_eq!(ppu.read_vram(0x8000), 0x00);
+        assert_eq!(ppu.vram[VRAM_SIZE], 0x42);
+
+        // palette data is blocked while drawing but the index still advances
+        ppu.write_byte(0xFF68, 0x80, &mut interrupts);
+        ppu.tick(OAM_SCAN_CYCLES, &mut interrupts);
+        ppu.write_byte(0xFF69, 0x00, &mut interrupts);
+        assert_eq!(ppu.read_byte(0xFF69), 0xFF);
+        assert_eq!(ppu.read_byte(0xFF68), 0xC1);
+        ppu.tick(DRAWING_CYCLES, &mut interrupts);
+        ppu.write_byte(0xFF68, 0x00, &mut interrupts);
+        assert_eq!(ppu.read_byte(0xFF69), 0xFF);
+    }
+
+    #[test]
+    fn test_cgb_background_attributes() {
+        let mut interrupts = InterruptController::new();
+        let mut ppu = cgb_ppu(&mut interrupts);
+        set_color(&mut ppu, 0xFF68, 2, 1, 0x001F, &mut interrupts);
+        set_color(&mut ppu, 0xFF68, 2, 2, 0x03E0, &mut interrupts);
+        // tile 1 in bank 1 has color 1 in its left half and color 2 in its right half
+        for row in 0..8 {
+            ppu.vram[VRAM_SIZE + 0x10 + row * 2] = 0xF0;
+            ppu.vram[VRAM_SIZE + 0x10 + row * 2 + 1] = 0x0F;
+        }
+        ppu.vram[0x1800] = 1;
+        ppu.vram[0x1801] = 1;
+        // palette 2 from bank 1, the second entry is flipped horizontally
+        ppu.vram[VRAM_SIZE + 0x1800] = 0x0A;
+        ppu.vram[VRAM_SIZE + 0x1801] = 0x2A;
+        run_frame(&mut ppu, &mut interrupts);
+        assert_eq!(pixel(&ppu, 0, 0), [0xFF, 0x00, 0x00, 0xFF]);
+        assert_eq!(pixel(&ppu, 4, 0), [0x00, 0xFF, 0x00, 0xFF]);
+        assert_eq!(pixel(&ppu, 8, 0), [0x00, 0xFF, 0x00, 0xFF]);
+        assert_eq!(pixel(&ppu, 12, 0), [0xFF, 0x00, 0x00, 0xFF]);
+        // the untouched map entries use palette 0, which is still white
+        assert_eq!(pixel(&ppu, 16, 0), [0xFF, 0xFF, 0xFF, 0xFF]);
+    }
+
+    #[test]
+    fn test_cgb_sprite_priority() {
+        let mut interrupts = InterruptController::new();
+        let mut ppu = cgb_ppu(&mut interrupts);
+        set_color(&mut ppu, 0xFF6A, 1, 3, 0x001F, &mut interrupts);
+        set_color(&mut ppu, 0xFF6A, 2, 3, 0x7C00, &mut interrupts);
+        fill_tile(&mut ppu, 0x8000, 1);
+        fill_tile(&mut ppu, 0x8010, 3);
+        // the later OAM entry is further left but the earlier entry wins on the CGB
+        ppu.oam[0..4].copy_from_slice(&[16, 12, 1, 0x01]);
+        ppu.oam[4..8].copy_from_slice(&[16, 10, 1, 0x02]);
+        // the map attribute of the second row of tiles puts the background in front of the
+        // lower half of the sprite
+        ppu.vram[VRAM_SIZE + 0x1820] = 0x80;
+        ppu.oam[8..12].copy_from_slice(&[20, 8, 1, 0x01]);
+        run_frame(&mut ppu, &mut interrupts);
+        assert_eq!(pixel(&ppu, 2, 0), [0x00, 0x00, 0xFF, 0xFF]);
+        assert_eq!(pixel(&ppu, 4, 0), [0xFF, 0x00, 0x00, 0xFF]);
+        assert_eq!(pixel(&ppu, 0, 8), [0xFF, 0xFF, 0xFF, 0xFF]);
+        assert_eq!(pixel(&ppu, 0, 7), [0xFF, 0x00, 0x00, 0xFF]);
+
+        // with LCDC bit 0 cleared the sprites are on top and the background is still drawn
+        ppu.write_byte(0xFF40, 0x92, &mut interrupts);
+        run_frame(&mut ppu, &mut interrupts);
+        assert_eq!(pixel(&ppu, 0, 8), [0xFF, 0x00, 0x00, 0xFF]);
+    }
+}
@@ -0,0 +1,98 @@
+// 8 palettes of 4 colors, each color a little endian RGB555 word
+const PALETTE_RAM_SIZE: usize = 64;
+
+// CGB color palette memory of either the background or the sprites, accessed through an
+// index register (BCPS/OCPS) and a data register (BCPD/OCPD)
+pub struct PaletteRam {
+    data: [u8; PALETTE_RAM_SIZE],
+    index: u8,
+    // advance the index after every write to the data register
+    auto_increment: bool,
+}
+
+impl PaletteRam {
+    // the boot ROM leaves every color white
+    pub fn new() -> PaletteRam {
+        PaletteRam {
+            data: [0xFF; PALETTE_RAM_SIZE],
+            index: 0,
+            auto_increment: false,
+        }
+    }
+
+    pub fn read_index(&self) -> u8 {
+        (self.auto_increment as u8) << 7 | 0x40 | self.index
+    }
+
+    pub fn write_index(&mut self, val: u8) {
+        self.index = val & 0x3F;
+        self.auto_increment = val & 0x80 != 0;
+    }
+
+    pub fn read_data(&self) -> u8 {
+        self.data[self.index as usize]
+    }
+
+    // blocked writes while the PPU draws still advance the index
+    pub fn write_data(&mut self, val: u8, accessible: bool) {
+        if accessible {
+            self.data[self.index as usize] = val;
+        }
+        if self.auto_increment {
+            self.index = (self.index + 1) & 0x3F;
+        }
+    }
+
+    pub fn color(&self, palette: u8, color: u8) -> [u8; 4] {
+        let offset = palette as usize * 8 + color as usize * 2;
+        rgb555(u16::from_le_bytes([
+            self.data[offset],
+            self.data[offset + 1],
+        ]))
+    }
+}
+
+// RGBA color of an RGB555 word, the five bit channels are scaled to the full eight bits
+pub fn rgb555(rgb: u16) -> [u8; 4] {
+    let channel = |shift: u16| {
+        let val = ((rgb >> shift) & 0x1F) as u8;
+        val << 3 | val >> 2
+    };
+    [channel(0), channel(5), channel(10), 0xFF]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_auto_increment() {
+        let mut palettes = PaletteRam::new();
+        palettes.write_index(0xBE);
+        assert_eq!(palettes.read_index(), 0xFE);
+        palettes.write_data(0x1F, true);
+        palettes.write_data(0x7C, true);
+        // the index wraps around within the 64 bytes
+        assert_eq!(palettes.read_index(), 0xC0);
+        assert_eq!(palettes.color(7, 3), [0xFF, 0x00, 0xFF, 0xFF]);
+
+        palettes.write_data(0x00, false);
+        assert_eq!(palettes.read_index(), 0xC1);
+        palettes.write_index(0x00);
+        assert_eq!(palettes.read_data(), 0xFF);
+        palettes.write_data(0x00, true);
+        assert_eq!(palettes.read_index(), 0x40);
+        assert_eq!(palettes.read_data(), 0x00);
+    }
+
+    #[test]
+    fn test_color() {
+        let mut palettes = PaletteRam::new();
+        assert_eq!(palettes.color(0, 0), [0xFF, 0xFF, 0xFF, 0xFF]);
+        // red 0x10, green 0x01, blue 0x00
+        palettes.write_index(0x82);
+        palettes.write_data(0x30, true);
+        palettes.write_data(0x00, true);
+        assert_eq!(palettes.color(0, 1), [0x84, 0x08, 0x00, 0xFF]);
+    }
+}
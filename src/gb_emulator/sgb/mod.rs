@@ -0,0 +1,600 @@
+mod packet;
+
+use log::{trace, warn};
+use packet::{PacketReceiver, PACKET_SIZE};
+
+use super::ppu::{rgb555, Ppu, SCREEN_HEIGHT, SCREEN_WIDTH};
+
+// the SNES picture with the border around the Game Boy screen
+pub const SGB_SCREEN_WIDTH: usize = 256;
+pub const SGB_SCREEN_HEIGHT: usize = 224;
+const SCREEN_X: usize = 48;
+const SCREEN_Y: usize = 40;
+
+// palettes are assigned to the 20x18 cells of 8x8 pixels on the Game Boy screen
+const CELLS_X: usize = SCREEN_WIDTH / 8;
+const CELLS_Y: usize = SCREEN_HEIGHT / 8;
+const ATTRIBUTE_FILE_SIZE: usize = CELLS_X * CELLS_Y / 4;
+const ATTRIBUTE_FILES: usize = 45;
+const SYSTEM_PALETTES: usize = 512;
+
+// the border is a 32x28 map of 4 bit SNES tiles with four palettes of 16 colors
+const BORDER_TILES: usize = 256;
+const BORDER_TILE_SIZE: usize = 32;
+const BORDER_MAP_WIDTH: usize = SGB_SCREEN_WIDTH / 8;
+const BORDER_MAP_SIZE: usize = BORDER_MAP_WIDTH * SGB_SCREEN_HEIGHT / 8 * 2;
+const BORDER_PALETTE_OFFSET: usize = 0x800;
+
+// the colors the SGB starts out with for all four palettes
+const DEFAULT_PALETTE: [u16; 4] = [0x67BF, 0x265B, 0x10B5, 0x2866];
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Mask {
+    Cancel,
+    // keep showing the last frame
+    Freeze,
+    Black,
+    // fill the screen with color 0
+    Color0,
+}
+
+// the Super Game Boy side of the link: command packets sent through P1, the colors they set
+// up and the border around the screen
+pub struct Sgb {
+    receiver: PacketReceiver,
+    // packets of the command being received
+    command: Vec<u8>,
+    palettes: [[u16; 4]; 4],
+    // palette number for each cell of the screen
+    attributes: [u8; CELLS_X * CELLS_Y],
+    // filled by PAL_TRN and ATTR_TRN, selected by PAL_SET and ATTR_SET
+    system_palettes: Vec<u16>,
+    attribute_files: Vec<u8>,
+    border_tiles: Vec<u8>,
+    border_map: Vec<u8>,
+    border_palettes: [[u16; 16]; 4],
+    mask: Mask,
+    // MLT_REQ enables reading up to four joypads, the one read next is selected by P15
+    players: u8,
+    player: u8,
+    framebuffer: Vec<u8>,
+}
+
+impl Sgb {
+    pub fn new() -> Sgb {
+        let mut sgb = Sgb {
+            receiver: PacketReceiver::new(),
+            command: Vec::new(),
+            palettes: [DEFAULT_PALETTE; 4],
+            attributes: [0; CELLS_X * CELLS_Y],
+            system_palettes: vec![0; SYSTEM_PALETTES * 4],
+            attribute_files: vec![0; ATTRIBUTE_FILES * ATTRIBUTE_FILE_SIZE],
+            border_tiles: vec![0; BORDER_TILES * BORDER_TILE_SIZE],
+            border_map: vec![0; BORDER_MAP_SIZE],
+            border_palettes: [[0; 16]; 4],
+            mask: Mask::Cancel,
+            players: 1,
+            player: 0,
+            framebuffer: vec![0; SGB_SCREEN_WIDTH * SGB_SCREEN_HEIGHT * 4],
+        };
+        sgb.render(&[0; SCREEN_WIDTH * SCREEN_HEIGHT]);
+        sgb
+    }
+
+    // RGBA pixels of the last composed frame, SGB_SCREEN_WIDTH x SGB_SCREEN_HEIGHT
+    pub fn framebuffer(&self) -> &[u8] {
+        &self.framebuffer
+    }
+
+    // P1 writes carry the command packets, the PPU provides the data of VRAM transfers
+    pub fn write_joypad(&mut self, val: u8, old_val: u8, ppu: &Ppu) {
+        // the next joypad is selected on the rising edge of P15
+        if self.players > 1 && old_val & 0x20 == 0 && val & 0x20 != 0 {
+            self.player = (self.player + 1) % self.players;
+        }
+        let Some(packet) = self.receiver.write(val) else {
+            return;
+        };
+        self.command.extend_from_slice(&packet);
+        let packets = (self.command[0] & 0x07).max(1) as usize;
+        if self.command.len() >= packets * PACKET_SIZE {
+            let command = std::mem::take(&mut self.command);
+            self.run_command(&command, ppu);
+        }
+    }
+
+    // with both lines deselected the low nibble holds the joypad number, only the first
+    // joypad has buttons
+    pub fn read_joypad(&self, val: u8) -> u8 {
+        if self.players == 1 {
+            val
+        } else if val & 0x30 == 0x30 {
+            (val & 0xF0) | (0x0F - self.player)
+        } else if self.player != 0 {
+            val | 0x0F
+        } else {
+            val
+        }
+    }
+
+    fn run_command(&mut self, data: &[u8], ppu: &Ppu) {
+        let command = data[0] >> 3;
+        trace!("SGB command {:02X}", command);
+        match command {
+            0x00 => self.set_palettes(0, 1, data),
+            0x01 => self.set_palettes(2, 3, data),
+            0x02 => self.set_palettes(0, 3, data),
+            0x03 => self.set_palettes(1, 2, data),
+            0x04 => self.attribute_blocks(data),
+            0x05 => self.attribute_lines(data),
+            0x06 => self.attribute_division(data),
+            0x07 => self.attribute_cells(data),
+            0x0A => self.select_palettes(data),
+            0x0B => {
+                let transfer = ppu.transfer_data();
+                for (color, bytes) in self.system_palettes.iter_mut().zip(transfer.chunks(2)) {
+                    *color = u16::from_le_bytes([bytes[0], bytes[1]]);
+                }
+            }
+            0x11 => {
+                self.players = match data[1] & 0x03 {
+                    0x01 => 2,
+                    0x03 => 4,
+                    _ => 1,
+                };
+                self.player = 0;
+            }
+            0x13 => {
+                let offset = (data[1] & 0x01) as usize * BORDER_TILES / 2 * BORDER_TILE_SIZE;
+                let len = BORDER_TILES / 2 * BORDER_TILE_SIZE;
+                self.border_tiles[offset..offset + len].copy_from_slice(&ppu.transfer_data());
+            }
+            0x14 => {
+                let transfer = ppu.transfer_data();
+                self.border_map
+                    .copy_from_slice(&transfer[..BORDER_MAP_SIZE]);
+                let colors = transfer[BORDER_PALETTE_OFFSET..].chunks(2);
+                for (color, bytes) in self.border_palettes.iter_mut().flatten().zip(colors) {
+                    *color = u16::from_le_bytes([bytes[0], bytes[1]]);
+                }
+            }
+            0x15 => {
+                let transfer = ppu.transfer_data();
+                let len = ATTRIBUTE_FILES * ATTRIBUTE_FILE_SIZE;
+                self.attribute_files.copy_from_slice(&transfer[..len]);
+            }
+            0x16 => self.load_attribute_file(data[1]),
+            0x17 => {
+                self.mask = match data[1] & 0x03 {
+                    0x00 => Mask::Cancel,
+                    0x01 => Mask::Freeze,
+                    0x02 => Mask::Black,
+                    _ => Mask::Color0,
+                };
+            }
+            // sound, SNES program uploads and the like have nothing to do on this side
+            _ => warn!("unsupported SGB command {:02X}", command),
+        }
+    }
+
+    fn color(data: &[u8], offset: usize) -> u16 {
+        u16::from_le_bytes([data[offset], data[offset + 1]])
+    }
+
+    // PAL01, PAL23, PAL03 and PAL12 set two palettes, color 0 is shared by all palettes
+    fn set_palettes(&mut self, first: usize, second: usize, data: &[u8]) {
+        let color0 = Self::color(data, 1);
+        for palette in self.palettes.iter_mut() {
+            palette[0] = color0;
+        }
+        for color in 1..4 {
+            self.palettes[first][color] = Self::color(data, 1 + color * 2);
+            self.palettes[second][color] = Self::color(data, 7 + color * 2);
+        }
+    }
+
+    // PAL_SET picks four of the palettes sent by PAL_TRN
+    fn select_palettes(&mut self, data: &[u8]) {
+        for (palette, colors) in self.palettes.iter_mut().enumerate() {
+            let index = Self::color(data, 1 + palette * 2) as usize % SYSTEM_PALETTES;
+            colors.copy_from_slice(&self.system_palettes[index * 4..index * 4 + 4]);
+        }
+        if data[9] & 0x80 != 0 {
+            self.load_attribute_file(data[9]);
+        }
+        if data[9] & 0x40 != 0 {
+            self.mask = Mask::Cancel;
+        }
+    }
+
+    // ATTR_SET, the attribute files from ATTR_TRN hold four cells per byte
+    fn load_attribute_file(&mut self, val: u8) {
+        let file = (val & 0x3F) as usize % ATTRIBUTE_FILES;
+        let offset = file * ATTRIBUTE_FILE_SIZE;
+        for (cell, palette) in self.attributes.iter_mut().enumerate() {
+            let byte = self.attribute_files[offset + cell / 4];
+            *palette = (byte >> (6 - (cell % 4) * 2)) & 0x03;
+        }
+        if val & 0x40 != 0 {
+            self.mask = Mask::Cancel;
+        }
+    }
+
+    fn set_cell(&mut self, x: usize, y: usize, palette: u8) {
+        self.attributes[y * CELLS_X + x] = palette & 0x03;
+    }
+
+    // ATTR_BLK colors the inside, the surrounding line and the outside of rectangles
+    fn attribute_blocks(&mut self, data: &[u8]) {
+        let count = (data[1] as usize).min((data.len() - 2) / 6);
+        for block in data[2..2 + count * 6].chunks(6) {
+            let (control, palettes) = (block[0] & 0x07, block[1]);
+            let (x1, y1, x2, y2) = (
+                block[2] as usize,
+                block[3] as usize,
+                block[4] as usize,
+                block[5] as usize,
+            );
+            let inside = palettes & 0x03;
+            let outside = (palettes >> 4) & 0x03;
+            // changing only the inside or only the outside takes the line along
+            let (change_line, line) = match control {
+                0x01 => (true, inside),
+                0x04 => (true, outside),
+                _ => (control & 0x02 != 0, (palettes >> 2) & 0x03),
+            };
+            for y in 0..CELLS_Y {
+                for x in 0..CELLS_X {
+                    if x < x1 || x > x2 || y < y1 || y > y2 {
+                        if control & 0x04 != 0 {
+                            self.set_cell(x, y, outside);
+                        }
+                    } else if x > x1 && x < x2 && y > y1 && y < y2 {
+                        if control & 0x01 != 0 {
+                            self.set_cell(x, y, inside);
+                        }
+                    } else if change_line {
+                        self.set_cell(x, y, line);
+                    }
+                }
+            }
+        }
+    }
+
+    // ATTR_LIN colors whole rows or columns
+    fn attribute_lines(&mut self, data: &[u8]) {
+        let count = (data[1] as usize).min(data.len() - 2);
+        for &line in &data[2..2 + count] {
+            let (index, palette) = ((line & 0x1F) as usize, (line >> 5) & 0x03);
+            if line & 0x80 != 0 {
+                for x in 0..CELLS_X {
+                    if index < CELLS_Y {
+                        self.set_cell(x, index, palette);
+                    }
+                }
+            } else {
+                for y in 0..CELLS_Y {
+                    if index < CELLS_X {
+                        self.set_cell(index, y, palette);
+                    }
+                }
+            }
+        }
+    }
+
+    // ATTR_DIV splits the screen in two halves and the line between them
+    fn attribute_division(&mut self, data: &[u8]) {
+        let after = data[1] & 0x03;
+        let before = (data[1] >> 2) & 0x03;
+        let on_line = (data[1] >> 4) & 0x03;
+        let horizontal = data[1] & 0x40 != 0;
+        let division = data[2] as usize;
+        for y in 0..CELLS_Y {
+            for x in 0..CELLS_X {
+                let position = if horizontal { y } else { x };
+                let palette = match position.cmp(&division) {
+                    std::cmp::Ordering::Less => before,
+                    std::cmp::Ordering::Equal => on_line,
+                    std::cmp::Ordering::Greater => after,
+                };
+                self.set_cell(x, y, palette);
+            }
+        }
+    }
+
+    // ATTR_CHR sets cell after cell from a starting position, four cells per byte
+    fn attribute_cells(&mut self, data: &[u8]) {
+        let (mut x, mut y) = (data[1] as usize % CELLS_X, data[2] as usize % CELLS_Y);
+        let count = (u16::from_le_bytes([data[3], data[4]]) as usize).min((data.len() - 6) * 4);
+        let vertical = data[5] & 0x01 != 0;
+        for cell in 0..count {
+            let palette = data[6 + cell / 4] >> (6 - (cell % 4) * 2);
+            self.set_cell(x, y, palette);
+            if vertical {
+                y += 1;
+                if y == CELLS_Y {
+                    y = 0;
+                    x = (x + 1) % CELLS_X;
+                }
+            } else {
+                x += 1;
+                if x == CELLS_X {
+                    x = 0;
+                    y = (y + 1) % CELLS_Y;
+                }
+            }
+        }
+    }
+
+    // color index (0-15) of a pixel in a border tile, two bitplanes per row followed by the
+    // other two bitplanes of all rows
+    fn border_pixel(&self, tile: usize, x: usize, y: usize) -> u8 {
+        let offset = tile * BORDER_TILE_SIZE + y * 2;
+        let planes = [
+            self.border_tiles[offset],
+            self.border_tiles[offset + 1],
+            self.border_tiles[offset + 16],
+            self.border_tiles[offset + 17],
+        ];
+        planes
+            .iter()
+            .enumerate()
+            .map(|(plane, bits)| ((bits >> (7 - x)) & 1) << plane)
+            .sum()
+    }
+
+    fn set_pixel(&mut self, x: usize, y: usize, color: [u8; 4]) {
+        let offset = (y * SGB_SCREEN_WIDTH + x) * 4;
+        self.framebuffer[offset..offset + 4].copy_from_slice(&color);
+    }
+
+    // compose the border and the Game Boy screen, colored by the palette of each cell
+    pub fn render(&mut self, shades: &[u8]) {
+        let backdrop = rgb555(self.palettes[0][0]);
+        for cell in 0..BORDER_MAP_SIZE / 2 {
+            let entry =
+                u16::from_le_bytes([self.border_map[cell * 2], self.border_map[cell * 2 + 1]]);
+            let tile = (entry & 0xFF) as usize;
+            // the entries use palettes 4-7 of the SNES
+            let palette = ((entry >> 10) & 0x03) as usize;
+            let (cell_x, cell_y) = (cell % BORDER_MAP_WIDTH * 8, cell / BORDER_MAP_WIDTH * 8);
+            for y in 0..8 {
+                for x in 0..8 {
+                    let (screen_x, screen_y) = (cell_x + x, cell_y + y);
+                    // the Game Boy screen covers the middle of the border
+                    if (SCREEN_X..SCREEN_X + SCREEN_WIDTH).contains(&screen_x)
+                        && (SCREEN_Y..SCREEN_Y + SCREEN_HEIGHT).contains(&screen_y)
+                    {
+                        continue;
+                    }
+                    let px = if entry & 0x4000 != 0 { 7 - x } else { x };
+                    let py = if entry & 0x8000 != 0 { 7 - y } else { y };
+                    let color = match self.border_pixel(tile, px, py) {
+                        0 => backdrop,
+                        color => rgb555(self.border_palettes[palette][color as usize]),
+                    };
+                    self.set_pixel(screen_x, screen_y, color);
+                }
+            }
+        }
+
+        for y in 0..SCREEN_HEIGHT {
+            for x in 0..SCREEN_WIDTH {
+                let color = match self.mask {
+                    // the frozen picture stays in the framebuffer
+                    Mask::Freeze => continue,
+                    Mask::Black => [0x00, 0x00, 0x00, 0xFF],
+                    Mask::Color0 => backdrop,
+                    Mask::Cancel => {
+                        let palette = self.attributes[(y / 8) * CELLS_X + x / 8] as usize;
+                        rgb555(self.palettes[palette][shades[y * SCREEN_WIDTH + x] as usize])
+                    }
+                };
+                self.set_pixel(SCREEN_X + x, SCREEN_Y + y, color);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::memory::InterruptController;
+    use super::packet::tests::packet_writes;
+    use super::*;
+
+    // write the command to P1 packet by packet, padding the last packet with zeros
+    fn send(sgb: &mut Sgb, ppu: &Ppu, data: &[u8]) {
+        let mut old_val = 0x30;
+        for chunk in data.chunks(PACKET_SIZE) {
+            let mut packet = [0; PACKET_SIZE];
+            packet[..chunk.len()].copy_from_slice(chunk);
+            for val in packet_writes(&packet) {
+                sgb.write_joypad(val, old_val, ppu);
+                old_val = val;
+            }
+        }
+    }
+
+    // a PPU showing the given 4 KiB of tile data for a VRAM transfer
+    fn transfer_ppu(data: &[u8]) -> Ppu {
+        let mut interrupts = InterruptController::new();
+        let mut ppu = Ppu::new();
+        ppu.write_byte(0xFF40, 0x10, &mut interrupts);
+        for (offset, val) in data.iter().enumerate() {
+            ppu.write_vram(0x8000 + offset as u16, *val);
+        }
+        for tile in 0..256u16 {
+            ppu.write_vram(0x9800 + (tile / 20) * 32 + tile % 20, tile as u8);
+        }
+        ppu
+    }
+
+    fn pixel(sgb: &Sgb, x: usize, y: usize) -> [u8; 4] {
+        let offset = (y * SGB_SCREEN_WIDTH + x) * 4;
+        sgb.framebuffer[offset..offset + 4].try_into().unwrap()
+    }
+
+    const RED: [u8; 4] = [0xFF, 0x00, 0x00, 0xFF];
+    const GREEN: [u8; 4] = [0x00, 0xFF, 0x00, 0xFF];
+    const WHITE: [u8; 4] = [0xFF, 0xFF, 0xFF, 0xFF];
+
+    #[test]
+    fn test_palettes() {
+        let mut sgb = Sgb::new();
+        let ppu = Ppu::new();
+        // PAL12: white as color 0, red as color 1 of palette 1 and green as color 3 of palette 2
+        send(
+            &mut sgb,
+            &ppu,
+            &[
+                0x19, 0xFF, 0x7F, 0x1F, 0x00, 0, 0, 0, 0, 0, 0, 0, 0, 0xE0, 0x03,
+            ],
+        );
+        assert_eq!(sgb.palettes[0][0], 0x7FFF);
+        assert_eq!(sgb.palettes[3][0], 0x7FFF);
+        assert_eq!(sgb.palettes[1][1], 0x001F);
+        assert_eq!(sgb.palettes[2][3], 0x03E0);
+        assert_eq!(sgb.palettes[0][1], DEFAULT_PALETTE[1]);
+
+        // the left half of the screen uses palette 1, the right half palette 2
+        send(&mut sgb, &ppu, &[0x31, 0x26, 10]);
+        let mut shades = [1; SCREEN_WIDTH * SCREEN_HEIGHT];
+        shades[SCREEN_WIDTH - 1] = 3;
+        shades[SCREEN_WIDTH] = 0;
+        sgb.render(&shades);
+        assert_eq!(pixel(&sgb, SCREEN_X, SCREEN_Y), RED);
+        assert_eq!(pixel(&sgb, SCREEN_X + SCREEN_WIDTH - 1, SCREEN_Y), GREEN);
+        assert_eq!(pixel(&sgb, SCREEN_X, SCREEN_Y + 1), WHITE);
+        // without a border the backdrop shows color 0
+        assert_eq!(pixel(&sgb, 0, 0), WHITE);
+    }
+
+    #[test]
+    fn test_attribute_commands() {
+        let mut sgb = Sgb::new();
+        let ppu = Ppu::new();
+        let cell = |sgb: &Sgb, x: usize, y: usize| sgb.attributes[y * CELLS_X + x];
+
+        // ATTR_BLK: inside 1, line 2 and outside 3 for the block from (1, 1) to (4, 4)
+        send(&mut sgb, &ppu, &[0x21, 1, 0x07, 0x39, 1, 1, 4, 4]);
+        assert_eq!(cell(&sgb, 2, 2), 1);
+        assert_eq!(cell(&sgb, 1, 3), 2);
+        assert_eq!(cell(&sgb, 4, 4), 2);
+        assert_eq!(cell(&sgb, 5, 4), 3);
+        // only the inside given, the line goes along with it
+        send(&mut sgb, &ppu, &[0x21, 1, 0x01, 0x00, 1, 1, 4, 4]);
+        assert_eq!(cell(&sgb, 2, 2), 0);
+        assert_eq!(cell(&sgb, 1, 1), 0);
+        assert_eq!(cell(&sgb, 0, 0), 3);
+
+        // ATTR_LIN: row 17 with palette 1 and column 19 with palette 2
+        send(&mut sgb, &ppu, &[0x29, 2, 0xB1, 0x53]);
+        assert_eq!(cell(&sgb, 0, 17), 1);
+        assert_eq!(cell(&sgb, 19, 0), 2);
+        assert_eq!(cell(&sgb, 3, 17), 1);
+
+        // ATTR_CHR: from (18, 0) left to right, wrapping onto the next row
+        send(&mut sgb, &ppu, &[0x39, 18, 0, 4, 0, 0, 0x1B]);
+        assert_eq!(cell(&sgb, 18, 0), 0);
+        assert_eq!(cell(&sgb, 19, 0), 1);
+        assert_eq!(cell(&sgb, 0, 1), 2);
+        assert_eq!(cell(&sgb, 1, 1), 3);
+        // top to bottom
+        send(&mut sgb, &ppu, &[0x39, 5, 16, 3, 0, 1, 0x54]);
+        assert_eq!(cell(&sgb, 5, 16), 1);
+        assert_eq!(cell(&sgb, 5, 17), 1);
+        assert_eq!(cell(&sgb, 6, 0), 1);
+    }
+
+    #[test]
+    fn test_system_palettes_and_attribute_files() {
+        let mut sgb = Sgb::new();
+        // system palette 1 is red on white, attribute file 0 starts with palette 2
+        let mut data = vec![0; 0x1000];
+        data[8..16].copy_from_slice(&[0xFF, 0x7F, 0x1F, 0x00, 0x00, 0x00, 0x00, 0x00]);
+        let ppu = transfer_ppu(&data);
+        send(&mut sgb, &ppu, &[0x59]);
+        let mut files = vec![0; 0x1000];
+        files[0] = 0x80;
+        let ppu = transfer_ppu(&files);
+        send(&mut sgb, &ppu, &[0xA9]);
+
+        // PAL_SET with palette 1 for palette 2, applying attribute file 0
+        send(&mut sgb, &ppu, &[0x51, 0, 0, 0, 0, 1, 0, 0, 0, 0x80]);
+        assert_eq!(sgb.palettes[2], [0x7FFF, 0x001F, 0x0000, 0x0000]);
+        assert_eq!(sgb.attributes[0], 2);
+        assert_eq!(sgb.attributes[1], 0);
+        sgb.render(&[1; SCREEN_WIDTH * SCREEN_HEIGHT]);
+        assert_eq!(pixel(&sgb, SCREEN_X, SCREEN_Y), RED);
+
+        // ATTR_SET with file 0 again after clearing the cell
+        sgb.attributes[0] = 0;
+        send(&mut sgb, &ppu, &[0xB1, 0x00]);
+        assert_eq!(sgb.attributes[0], 2);
+    }
+
+    #[test]
+    fn test_border() {
+        let mut sgb = Sgb::new();
+        // border tile 1 is filled with color 1
+        let mut tiles = vec![0; 0x1000];
+        for row in 0..8 {
+            tiles[BORDER_TILE_SIZE + row * 2] = 0xFF;
+        }
+        let ppu = transfer_ppu(&tiles);
+        send(&mut sgb, &ppu, &[0x99, 0x00]);
+
+        // the top left corner shows tile 1 with SNES palette 4 where color 1 is red, the
+        // second entry is tile 0 which is all transparent
+        let mut map = vec![0; 0x1000];
+        map[0..2].copy_from_slice(&[0x01, 0x10]);
+        map[BORDER_PALETTE_OFFSET + 2..BORDER_PALETTE_OFFSET + 4].copy_from_slice(&[0x1F, 0x00]);
+        let ppu = transfer_ppu(&map);
+        send(&mut sgb, &ppu, &[0xA1]);
+        sgb.render(&[0; SCREEN_WIDTH * SCREEN_HEIGHT]);
+        assert_eq!(pixel(&sgb, 0, 0), RED);
+        assert_eq!(pixel(&sgb, 7, 7), RED);
+        assert_eq!(pixel(&sgb, 8, 0), rgb555(DEFAULT_PALETTE[0]));
+    }
+
+    #[test]
+    fn test_mask() {
+        let mut sgb = Sgb::new();
+        let ppu = Ppu::new();
+        sgb.render(&[3; SCREEN_WIDTH * SCREEN_HEIGHT]);
+        let dark = rgb555(DEFAULT_PALETTE[3]);
+        assert_eq!(pixel(&sgb, SCREEN_X, SCREEN_Y), dark);
+
+        send(&mut sgb, &ppu, &[0xB9, 0x01]);
+        sgb.render(&[0; SCREEN_WIDTH * SCREEN_HEIGHT]);
+        assert_eq!(pixel(&sgb, SCREEN_X, SCREEN_Y), dark);
+        send(&mut sgb, &ppu, &[0xB9, 0x02]);
+        sgb.render(&[0; SCREEN_WIDTH * SCREEN_HEIGHT]);
+        assert_eq!(pixel(&sgb, SCREEN_X, SCREEN_Y), [0x00, 0x00, 0x00, 0xFF]);
+        send(&mut sgb, &ppu, &[0xB9, 0x03]);
+        sgb.render(&[3; SCREEN_WIDTH * SCREEN_HEIGHT]);
+        assert_eq!(pixel(&sgb, SCREEN_X, SCREEN_Y), rgb555(DEFAULT_PALETTE[0]));
+        send(&mut sgb, &ppu, &[0xB9, 0x00]);
+        sgb.render(&[3; SCREEN_WIDTH * SCREEN_HEIGHT]);
+        assert_eq!(pixel(&sgb, SCREEN_X, SCREEN_Y), dark);
+    }
+
+    #[test]
+    fn test_multiplayer() {
+        let mut sgb = Sgb::new();
+        let ppu = Ppu::new();
+        assert_eq!(sgb.read_joypad(0xFF), 0xFF);
+        send(&mut sgb, &ppu, &[0x89, 0x01]);
+        assert_eq!(sgb.read_joypad(0xFF), 0xFF);
+        // reading the buttons of the first joypad and releasing P15 selects the second one
+        sgb.write_joypad(0x10, 0x30, &ppu);
+        assert_eq!(sgb.read_joypad(0xDE), 0xDE);
+        sgb.write_joypad(0x30, 0x10, &ppu);
+        assert_eq!(sgb.read_joypad(0xFF), 0xFE);
+        // the second joypad has nothing pressed
+        sgb.write_joypad(0x10, 0x30, &ppu);
+        assert_eq!(sgb.read_joypad(0xDE), 0xDF);
+        sgb.write_joypad(0x30, 0x10, &ppu);
+        assert_eq!(sgb.read_joypad(0xFF), 0xFF);
+    }
+}
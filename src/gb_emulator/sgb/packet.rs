@@ -0,0 +1,102 @@
+pub const PACKET_SIZE: usize = 16;
+
+// assembles 16 byte packets from the pulses a game writes to P1: both lines low resets the
+// transfer, P14 low sends a 0 bit and P15 low a 1 bit, both lines go high between bits and a
+// 0 bit ends the packet after its 128 data bits
+pub struct PacketReceiver {
+    packet: [u8; PACKET_SIZE],
+    // bits received so far, None while no transfer runs
+    bits: Option<usize>,
+    // a new bit is only taken after both lines went high again
+    waiting_for_high: bool,
+}
+
+impl PacketReceiver {
+    pub fn new() -> PacketReceiver {
+        PacketReceiver {
+            packet: [0; PACKET_SIZE],
+            bits: None,
+            waiting_for_high: false,
+        }
+    }
+
+    // returns the packet once its stop bit arrived
+    pub fn write(&mut self, p1: u8) -> Option<[u8; PACKET_SIZE]> {
+        match p1 & 0x30 {
+            0x00 => {
+                self.packet = [0; PACKET_SIZE];
+                self.bits = Some(0);
+                self.waiting_for_high = true;
+            }
+            0x30 => self.waiting_for_high = false,
+            line if !self.waiting_for_high => {
+                let bits = self.bits?;
+                self.waiting_for_high = true;
+                if bits == PACKET_SIZE * 8 {
+                    self.bits = None;
+                    return Some(self.packet);
+                }
+                if line == 0x10 {
+                    self.packet[bits / 8] |= 1 << (bits % 8);
+                }
+                self.bits = Some(bits + 1);
+            }
+            _ => {}
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+
+    // the P1 writes that send a packet
+    pub fn packet_writes(packet: &[u8; PACKET_SIZE]) -> Vec<u8> {
+        let mut writes = vec![0x00, 0x30];
+        for byte in packet {
+            for bit in 0..8 {
+                writes.push(if byte & (1 << bit) != 0 { 0x10 } else { 0x20 });
+                writes.push(0x30);
+            }
+        }
+        writes.extend_from_slice(&[0x20, 0x30]);
+        writes
+    }
+
+    #[test]
+    fn test_receive() {
+        let mut receiver = PacketReceiver::new();
+        let mut packet = [0; PACKET_SIZE];
+        packet[0] = 0x89;
+        packet[15] = 0xA5;
+        let writes = packet_writes(&packet);
+        let (last, rest) = writes.split_last().unwrap();
+        let (stop, rest) = rest.split_last().unwrap();
+        for val in rest {
+            assert_eq!(receiver.write(*val), None);
+        }
+        assert_eq!(receiver.write(*stop), Some(packet));
+        assert_eq!(receiver.write(*last), None);
+        // pulses outside of a transfer are ignored
+        assert_eq!(receiver.write(0x10), None);
+        assert_eq!(receiver.write(0x30), None);
+    }
+
+    #[test]
+    fn test_pulse_needs_release() {
+        let mut receiver = PacketReceiver::new();
+        receiver.write(0x00);
+        receiver.write(0x30);
+        // holding P15 low without releasing both lines in between counts as a single bit
+        receiver.write(0x10);
+        receiver.write(0x10);
+        receiver.write(0x30);
+        for _ in 0..127 {
+            receiver.write(0x20);
+            receiver.write(0x30);
+        }
+        let packet = receiver.write(0x20).unwrap();
+        assert_eq!(packet[0], 0x01);
+    }
+}
@@ -1,5 +1,8 @@
 mod gb_emulator;
 
-pub fn start_emulation() {
-  gb_emulator::start_emulation();
-}
\ No newline at end of file
+pub use gb_emulator::{
+    Button, CartridgeError, CartridgeHeader, CgbSupport, CpuError, Destination, Emulator,
+    EmulatorError, InProcessCable, Licensee, LinkCable, LoopbackCable, Model, SerialCapture,
+    TcpCable, TimeSource, CAMERA_HEIGHT, CAMERA_WIDTH, SAMPLE_RATE, SCREEN_HEIGHT, SCREEN_WIDTH,
+    SGB_SCREEN_HEIGHT, SGB_SCREEN_WIDTH,
+};
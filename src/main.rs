@@ -1,55 +1,240 @@
-use core::panic;
-use interpolation::Lerp;
+mod frontend;
+
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use frontend::battery::BatterySave;
+use frontend::keymap::KeyMap;
+use frontend::BUTTONS;
 use log::error;
 use pixels::{Pixels, SurfaceTexture};
-use rustyboy::start_emulation;
+use rustyboy::{CartridgeHeader, Emulator, Model, TcpCable};
 use winit::{
     dpi::PhysicalSize,
     event::{Event, WindowEvent},
-    event_loop::EventLoop,
+    event_loop::{ControlFlow, EventLoop},
     keyboard::KeyCode,
     window::WindowBuilder,
 };
 use winit_input_helper::WinitInputHelper;
 
+const FRAME_RATE: f64 = 59.73;
+const WINDOW_SCALE: u32 = 4;
+// two minutes, enough for Blargg's slowest test ROMs
+const HEADLESS_FRAMES: u32 = 7200;
+const USAGE: &str = "Usage: rustyboy [--keymap <file>] [--boot-rom <file>] \
+    [--model dmg|mgb|sgb|sgb2|cgb] [--link-listen <addr> | --link-connect <addr>] \
+    [--headless [--frames <n>]] <rom>";
+
+// the link cable to another rustyboy, one of them listens for the other to connect
+#[derive(Debug, PartialEq)]
+enum Link {
+    Listen(String),
+    Connect(String),
+}
+
+struct Options {
+    rom_path: String,
+    keymap_path: Option<String>,
+    boot_rom_path: Option<String>,
+    model: Option<Model>,
+    link: Option<Link>,
+    // run without a window for this many frames, printing the serial output
+    headless: Option<u32>,
+}
+
+fn parse_model(name: &str) -> Result<Model, String> {
+    match name {
+        "dmg" => Ok(Model::Dmg),
+        "mgb" => Ok(Model::Mgb),
+        "sgb" => Ok(Model::Sgb),
+        "sgb2" => Ok(Model::Sgb2),
+        "cgb" => Ok(Model::Cgb),
+        _ => Err(format!("unknown model {}", name)),
+    }
+}
+
+fn parse_args(mut args: impl Iterator<Item = String>) -> Result<Options, String> {
+    let mut rom_path = None;
+    let mut keymap_path = None;
+    let mut boot_rom_path = None;
+    let mut model = None;
+    let mut link = None;
+    let mut headless = false;
+    let mut frames = None;
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--keymap" => {
+                keymap_path = Some(args.next().ok_or("--keymap expects a file")?);
+            }
+            "--boot-rom" => {
+                boot_rom_path = Some(args.next().ok_or("--boot-rom expects a file")?);
+            }
+            "--model" => {
+                model = Some(parse_model(&args.next().ok_or("--model expects a model")?)?);
+            }
+            "--link-listen" => {
+                link = Some(Link::Listen(
+                    args.next().ok_or("--link-listen expects an address")?,
+                ));
+            }
+            "--link-connect" => {
+                link = Some(Link::Connect(
+                    args.next().ok_or("--link-connect expects an address")?,
+                ));
+            }
+            "--headless" => headless = true,
+            "--frames" => {
+                let val = args.next().ok_or("--frames expects a number")?;
+                frames = Some(
+                    val.parse()
+                        .map_err(|_| format!("invalid frame count {}", val))?,
+                );
+            }
+            _ if arg.starts_with("--") => return Err(format!("unknown option {}", arg)),
+            _ if rom_path.is_none() => rom_path = Some(arg),
+            _ => return Err(format!("unexpected argument {}", arg)),
+        }
+    }
+    if !headless && frames.is_some() {
+        return Err("--frames needs --headless".to_string());
+    }
+    // the serial port is taken by the capture
+    if headless && link.is_some() {
+        return Err("--headless can't be linked".to_string());
+    }
+    Ok(Options {
+        rom_path: rom_path.ok_or("no ROM given")?,
+        keymap_path,
+        boot_rom_path,
+        model,
+        link,
+        headless: headless.then(|| frames.unwrap_or(HEADLESS_FRAMES)),
+    })
+}
+
 fn main() {
     env_logger::init();
+
+    let options = match parse_args(std::env::args().skip(1)) {
+        Ok(options) => options,
+        Err(err) => {
+            error!("{}\n{}", err, USAGE);
+            std::process::exit(1);
+        }
+    };
+    let keymap = match &options.keymap_path {
+        Some(path) => match std::fs::read_to_string(path)
+            .map_err(|err| err.to_string())
+            .and_then(|config| KeyMap::parse(&config))
+        {
+            Ok(keymap) => keymap,
+            Err(err) => {
+                error!("Loading key map {} failed: {}", path, err);
+                std::process::exit(1);
+            }
+        },
+        None => KeyMap::default(),
+    };
+    let rom_path = options.rom_path;
+    let rom = match std::fs::read(&rom_path) {
+        Ok(rom) => rom,
+        Err(err) => {
+            error!("Reading ROM {} failed: {}", rom_path, err);
+            std::process::exit(1);
+        }
+    };
+    let boot_rom = match &options.boot_rom_path {
+        Some(path) => match std::fs::read(path) {
+            Ok(boot_rom) => Some(boot_rom),
+            Err(err) => {
+                error!("Reading boot ROM {} failed: {}", path, err);
+                std::process::exit(1);
+            }
+        },
+        None => None,
+    };
+    // unless the model is given it follows the boot ROM, or without one the cartridge header,
+    // an invalid header is reported when the emulator loads the ROM
+    let model = options.model.unwrap_or(match &boot_rom {
+        Some(boot_rom) if boot_rom.len() == Model::Cgb.boot_rom_size() => Model::Cgb,
+        Some(_) => Model::Dmg,
+        None => CartridgeHeader::parse(&rom)
+            .map(|header| Model::for_cartridge(&header))
+            .unwrap_or(Model::Dmg),
+    });
+    let mut emulator = match Emulator::new(&rom, model, boot_rom.as_deref()) {
+        Ok(emulator) => emulator,
+        Err(err) => {
+            error!("Loading ROM {} failed: {}", rom_path, err);
+            std::process::exit(1);
+        }
+    };
+    // test ROMs run without saves
+    if let Some(frames) = options.headless {
+        let passed = frontend::headless::run(&mut emulator, frames);
+        std::process::exit(if passed { 0 } else { 1 });
+    }
+    let mut battery = BatterySave::new(Path::new(&rom_path));
+    if let Err(err) = battery.load(&mut emulator) {
+        error!("Loading save failed: {}", err);
+        std::process::exit(1);
+    }
+    if let Some(link) = &options.link {
+        let cable = match link {
+            Link::Listen(addr) => TcpCable::listen(addr),
+            Link::Connect(addr) => TcpCable::connect(addr),
+        };
+        match cable {
+            Ok(cable) => emulator.set_link_cable(Box::new(cable)),
+            Err(err) => {
+                error!("Connecting link cable failed: {}", err);
+                std::process::exit(1);
+            }
+        }
+    }
+
+    #[cfg(feature = "gamepad")]
+    let mut gamepad = frontend::gamepad::Gamepad::new();
+    #[cfg(feature = "audio")]
+    let audio = frontend::audio::Audio::new();
+
     let event_loop = EventLoop::new().unwrap();
     let mut input = WinitInputHelper::new();
+    let (width, height) = emulator.screen_size();
+    let screen_size = PhysicalSize::new(width as u32, height as u32);
     let window = WindowBuilder::new()
-        .with_inner_size(PhysicalSize::new(800, 600))
+        .with_title(format!("rustyboy - {}", emulator.header().title))
+        .with_inner_size(PhysicalSize::new(
+            screen_size.width * WINDOW_SCALE,
+            screen_size.height * WINDOW_SCALE,
+        ))
+        .with_min_inner_size(screen_size)
         .build(&event_loop)
         .unwrap();
 
     let size = window.inner_size();
-    let width = size.width;
-    let height = size.height;
-    let surface_texture = SurfaceTexture::new(width, height, &window);
-    let mut pixels = match Pixels::new(width, height, surface_texture) {
+    let surface_texture = SurfaceTexture::new(size.width, size.height, &window);
+    // pixels scales the buffer by the largest integer factor that fits and letterboxes the rest
+    let mut pixels = match Pixels::new(screen_size.width, screen_size.height, surface_texture) {
         Ok(pixels) => pixels,
-        Err(error) => {
-            error!("Creating pixels backend failed: {}", error);
-            panic!();
+        Err(err) => {
+            error!("Creating pixels backend failed: {}", err);
+            std::process::exit(1);
         }
     };
 
-    start_emulation();
+    let frame_duration = Duration::from_secs_f64(1.0 / FRAME_RATE);
+    let mut next_frame = Instant::now();
 
     event_loop
         .run(move |event, elwt| {
-            if let Event::WindowEvent {
-                event: window_event,
-                ..
-            } = &event
-            {
-                if window_event == &WindowEvent::RedrawRequested {
-                    let win_width = window.inner_size().width;
-                    let frame = pixels.frame_mut();
-                    for (i, pixel) in frame.chunks_exact_mut(4).enumerate() {
-                        let r: u8 =
-                            0x0.lerp(&0xff, &((i as u32 % win_width) as f32 / win_width as f32));
-                        pixel.copy_from_slice(&[r, 0, 0, 0xff]);
-                    }
+            match &event {
+                Event::WindowEvent {
+                    event: WindowEvent::RedrawRequested,
+                    ..
+                } => {
+                    pixels.frame_mut().copy_from_slice(emulator.framebuffer());
                     if pixels
                         .render()
                         .map_err(|e| error!("pixels.render() failed: {}", e))
@@ -59,11 +244,35 @@ fn main() {
                         return;
                     }
                 }
+                Event::AboutToWait => {
+                    let now = Instant::now();
+                    if now >= next_frame {
+                        if let Err(err) = emulator.step_frame() {
+                            error!("Emulation stopped: {}", err);
+                            elwt.exit();
+                            return;
+                        }
+                        // without the audio feature the emulator drops samples nobody collects
+                        #[cfg(feature = "audio")]
+                        audio.push(&emulator.audio_samples());
+                        battery.update(&mut emulator);
+                        window.request_redraw();
+
+                        next_frame += frame_duration;
+                        // don't try to catch up after the window was blocked for a while
+                        if next_frame < now {
+                            next_frame = now + frame_duration;
+                        }
+                    }
+                    elwt.set_control_flow(ControlFlow::WaitUntil(next_frame));
+                }
+                Event::LoopExiting => battery.flush(&mut emulator),
+                _ => {}
             }
 
             // Handle input events
             if input.update(&event) {
-                // Close events
+                // Close events, Escape is bound by the frontend and never reaches the emulator
                 if input.key_pressed(KeyCode::Escape)
                     || input.close_requested()
                     || input.destroyed()
@@ -72,12 +281,18 @@ fn main() {
                     return;
                 }
 
+                #[cfg(feature = "gamepad")]
+                gamepad.poll();
+                for (_, button) in BUTTONS {
+                    let held = keymap.is_held(&input, button);
+                    #[cfg(feature = "gamepad")]
+                    let held = held || gamepad.is_held(button);
+                    emulator.set_button(button, held);
+                }
+
                 // Resize the window
                 if let Some(size) = input.window_resized() {
-                    if let Err(err) = pixels
-                        .resize_surface(size.width, size.height)
-                        .and_then(|_| pixels.resize_buffer(size.width, size.height))
-                    {
+                    if let Err(err) = pixels.resize_surface(size.width, size.height) {
                         error!("pixels.resize_surface {:?}", err);
                         elwt.exit();
                         return;
@@ -88,3 +303,54 @@ fn main() {
         })
         .unwrap();
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(args: &[&str]) -> impl Iterator<Item = String> {
+        args.iter()
+            .map(|arg| arg.to_string())
+            .collect::<Vec<_>>()
+            .into_iter()
+    }
+
+    #[test]
+    fn test_parse_args() {
+        let options = parse_args(args(&["--keymap", "keys.txt", "game.gb"])).unwrap();
+        assert_eq!(options.rom_path, "game.gb");
+        assert_eq!(options.keymap_path.as_deref(), Some("keys.txt"));
+        assert_eq!(options.boot_rom_path, None);
+        assert_eq!(options.model, None);
+
+        let options = parse_args(args(&[
+            "--boot-rom",
+            "cgb.bin",
+            "--model",
+            "cgb",
+            "game.gb",
+        ]))
+        .unwrap();
+        assert_eq!(options.boot_rom_path.as_deref(), Some("cgb.bin"));
+        assert_eq!(options.model, Some(Model::Cgb));
+        assert!(parse_args(args(&["--model", "gba", "game.gb"])).is_err());
+
+        let options = parse_args(args(&["--link-connect", "localhost:5000", "game.gb"])).unwrap();
+        assert_eq!(
+            options.link,
+            Some(Link::Connect("localhost:5000".to_string()))
+        );
+        assert!(parse_args(args(&["game.gb", "--link-listen"])).is_err());
+
+        let options = parse_args(args(&["--headless", "test.gb"])).unwrap();
+        assert_eq!(options.headless, Some(HEADLESS_FRAMES));
+        let options = parse_args(args(&["--headless", "--frames", "100", "test.gb"])).unwrap();
+        assert_eq!(options.headless, Some(100));
+        assert!(parse_args(args(&["--frames", "100", "test.gb"])).is_err());
+        assert!(parse_args(args(&["--headless", "--frames", "x", "test.gb"])).is_err());
+
+        assert!(parse_args(args(&[])).is_err());
+        assert!(parse_args(args(&["game.gb", "--keymap"])).is_err());
+        assert!(parse_args(args(&["--fast", "game.gb"])).is_err());
+    }
+}